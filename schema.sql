@@ -1,16 +1,29 @@
 -- temporary solution until an actual migration system is in place
 -- run using `sqlite3 -bail -batch filename.db <schema.sql`
+-- only missing tables are created, so a database created by an older version,
+-- which lacks columns added since, must be recreated; cherry refuses to start
+-- with such a database
 
 BEGIN;
 
 
 CREATE TABLE IF NOT EXISTS pull_request (
+  repo_id INTEGER NOT NULL,
   owner TEXT NOT NULL,
   repo TEXT NOT NULL,
   number INTEGER NOT NULL,
   commit_hash TEXT NOT NULL,
+  base_ref TEXT NOT NULL,
   state TEXT NOT NULL,
+  strategy TEXT,
+  alone INTEGER NOT NULL DEFAULT 0,
+  priority INTEGER NOT NULL DEFAULT 0,
+  failures INTEGER NOT NULL DEFAULT 0,
+  stack_below INTEGER,
+  merge_group TEXT,
   merge_attempt TEXT,
+  staging_from TEXT,
+  staging_to TEXT,
   timestamp INTEGER NOT NULL
 );
 
@@ -23,12 +36,32 @@ ON pull_request (merge_attempt);
 CREATE INDEX IF NOT EXISTS pull_request_state_timestamp
 ON pull_request (state, timestamp);
 
+CREATE INDEX IF NOT EXISTS pull_request_merge_group
+ON pull_request (merge_group);
+
+
+CREATE TABLE IF NOT EXISTS merge_group (
+  id TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS merge_group_id
+ON merge_group (id);
+
 
 CREATE TABLE IF NOT EXISTS merge_attempt (
   id TEXT NOT NULL,
+  repo_id INTEGER NOT NULL,
   owner TEXT NOT NULL,
   repo TEXT NOT NULL,
+  base_ref TEXT NOT NULL,
   state TEXT NOT NULL,
+  staging_branch TEXT,
+  tmp_branch TEXT,
+  target_hash TEXT,
+  staging_hash TEXT,
+  base_attempt TEXT,
   timestamp INTEGER NOT NULL
 );
 
@@ -42,4 +75,167 @@ CREATE INDEX IF NOT EXISTS merge_attempt_state_timestamp
 ON merge_attempt (state, timestamp);
 
 
+CREATE TABLE IF NOT EXISTS batch_result (
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  base_ref TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  success INTEGER NOT NULL,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS batch_result_owner_repo_base_ref_timestamp
+ON batch_result (owner, repo, base_ref, timestamp);
+
+
+CREATE TABLE IF NOT EXISTS split_history (
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  number INTEGER NOT NULL,
+  from_attempt TEXT NOT NULL,
+  to_attempt TEXT,
+  reason TEXT NOT NULL,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS split_history_owner_repo_number
+ON split_history (owner, repo, number);
+
+
+CREATE TABLE IF NOT EXISTS attempt_log (
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  attempt TEXT NOT NULL,
+  entry TEXT NOT NULL,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS attempt_log_attempt
+ON attempt_log (attempt);
+
+
+CREATE TABLE IF NOT EXISTS backport (
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  number INTEGER NOT NULL,
+  branch TEXT NOT NULL,
+  queue INTEGER NOT NULL DEFAULT 0,
+  requested_by TEXT NOT NULL,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS backport_owner_repo_number_branch
+ON backport (owner, repo, number, branch);
+
+
+CREATE TABLE IF NOT EXISTS shadow_intent (
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  number INTEGER,
+  intent TEXT NOT NULL,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS shadow_intent_owner_repo_timestamp
+ON shadow_intent (owner, repo, timestamp);
+
+
+CREATE TABLE IF NOT EXISTS shadow_merge (
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  number INTEGER NOT NULL,
+  attempt TEXT NOT NULL,
+  base_ref TEXT NOT NULL,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS shadow_merge_owner_repo_timestamp
+ON shadow_merge (owner, repo, timestamp);
+
+
+CREATE TABLE IF NOT EXISTS landed_attempt (
+  id TEXT NOT NULL,
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  base_ref TEXT NOT NULL,
+  target_hash TEXT NOT NULL,
+  staging_hash TEXT NOT NULL,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS landed_attempt_id
+ON landed_attempt (id);
+
+
+CREATE TABLE IF NOT EXISTS landed_pr (
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  number INTEGER NOT NULL,
+  attempt TEXT NOT NULL,
+  staging_from TEXT,
+  staging_to TEXT,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS landed_pr_owner_repo_number
+ON landed_pr (owner, repo, number);
+
+CREATE INDEX IF NOT EXISTS landed_pr_attempt
+ON landed_pr (attempt);
+
+
+CREATE TABLE IF NOT EXISTS tree_closed (
+  repo_id INTEGER NOT NULL,
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  priority INTEGER NOT NULL,
+  closed_by TEXT NOT NULL,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS tree_closed_owner_repo
+ON tree_closed (owner, repo);
+
+
+CREATE TABLE IF NOT EXISTS freeze (
+  repo_id INTEGER NOT NULL,
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  base_ref TEXT NOT NULL,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS freeze_owner_repo_base_ref
+ON freeze (owner, repo, base_ref);
+
+
+CREATE TABLE IF NOT EXISTS target_health (
+  repo_id INTEGER NOT NULL,
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  base_ref TEXT NOT NULL,
+  head_hash TEXT NOT NULL,
+  checks TEXT NOT NULL,
+  attempt TEXT,
+  overridden_by TEXT,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS target_health_owner_repo_base_ref
+ON target_health (owner, repo, base_ref);
+
+
+CREATE TABLE IF NOT EXISTS repo_config (
+  repo_id INTEGER NOT NULL,
+  owner TEXT NOT NULL,
+  repo TEXT NOT NULL,
+  commit_hash TEXT,
+  content TEXT,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS repo_config_owner_repo
+ON repo_config (owner, repo);
+
+
 COMMIT;
@@ -0,0 +1,345 @@
+use std::fmt;
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{de, Deserialize, Deserializer};
+
+/// A field of a schedule, as the set of values it matches.
+#[derive(Debug, Clone, PartialEq)]
+struct Field(u64);
+
+impl Field {
+  /// Parse a comma-separated list of `*`, `N` or `N-M`, each optionally
+  /// followed by `/step`, with values between `min` and `max`.
+  fn parse(s: &str, min: u32, max: u32) -> Result<Self, String> {
+    let mut bits = 0;
+    for item in s.split(',') {
+      let (range, step) = match item.split_once('/') {
+        Some((range, step)) => match step.parse::<u32>() {
+          Ok(step) if step > 0 => (range, step),
+          _ => return Err(format!("invalid step in `{}`", item)),
+        },
+        None => (item, 1),
+      };
+      let value = |v: &str| match v.parse::<u32>() {
+        Ok(v) if v >= min && v <= max => Ok(v),
+        _ => Err(format!("`{}` is not between {} and {}", v, min, max)),
+      };
+      let (start, end) = match range {
+        "*" => (min, max),
+        _ => match range.split_once('-') {
+          Some((start, end)) => (value(start)?, value(end)?),
+          None => (value(range)?, value(range)?),
+        },
+      };
+      if start > end {
+        return Err(format!("empty range `{}`", range));
+      }
+      for v in (start..=end).step_by(step as usize) {
+        bits |= 1 << v;
+      }
+    }
+    Ok(Self(bits))
+  }
+
+  fn matches(&self, value: u32) -> bool {
+    self.0 & (1 << value) != 0
+  }
+}
+
+/// A cron schedule, `minute hour day-of-month month day-of-week`, matching
+/// the minutes when all five fields match, except that as in cron, a day
+/// matches either field when neither day field starts with `*`.  Days of
+/// the week go from 0 (Sunday) to 6, and 7 is Sunday too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+  source: String,
+  minute: Field,
+  hour: Field,
+  day: Field,
+  month: Field,
+  weekday: Field,
+  /// Whether the day is matched by either day field.
+  either_day: bool,
+}
+
+impl Schedule {
+  pub fn parse(s: &str) -> Result<Self, String> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    if fields.len() != 5 {
+      return Err(format!("`{}` does not have 5 fields", s));
+    }
+    let mut weekday = Field::parse(fields[4], 0, 7)?;
+    if weekday.matches(7) {
+      weekday.0 |= 1;
+    }
+    Ok(Self {
+      source: s.to_string(),
+      minute: Field::parse(fields[0], 0, 59)?,
+      hour: Field::parse(fields[1], 0, 23)?,
+      day: Field::parse(fields[2], 1, 31)?,
+      month: Field::parse(fields[3], 1, 12)?,
+      weekday,
+      either_day: !fields[2].starts_with('*') && !fields[4].starts_with('*'),
+    })
+  }
+
+  pub fn matches(&self, time: NaiveDateTime) -> bool {
+    let day = self.day.matches(time.day());
+    let weekday = self
+      .weekday
+      .matches(time.weekday().num_days_from_sunday());
+    let day = if self.either_day {
+      day || weekday
+    } else {
+      day && weekday
+    };
+    self.minute.matches(time.minute())
+      && self.hour.matches(time.hour())
+      && self.month.matches(time.month())
+      && day
+  }
+}
+
+impl fmt::Display for Schedule {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.source)
+  }
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let s = String::deserialize(deserializer)?;
+    Self::parse(&s).map_err(de::Error::custom)
+  }
+}
+
+/// Time zone of a freeze window: a named zone, which follows daylight
+/// saving time, or a fixed offset from UTC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+  Named(Tz),
+  Fixed(FixedOffset),
+}
+
+impl Zone {
+  /// Local time in the zone at `time`.
+  fn local(&self, time: DateTime<Utc>) -> NaiveDateTime {
+    match self {
+      Self::Named(tz) => time.with_timezone(tz).naive_local(),
+      Self::Fixed(offset) => time.with_timezone(offset).naive_local(),
+    }
+  }
+}
+
+impl fmt::Display for Zone {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Named(tz) => write!(f, "{}", tz.name()),
+      Self::Fixed(offset) => write!(f, "{}", offset),
+    }
+  }
+}
+
+/// Parse a time zone given as `UTC`, as a name from the tz database such as
+/// `Europe/Paris`, or as an offset such as `+02:00`.
+pub fn parse_timezone(s: &str) -> Option<Zone> {
+  if s == "UTC" || s == "Z" {
+    return Some(Zone::Fixed(FixedOffset::east(0)));
+  }
+  let sign = match s.chars().next()? {
+    '+' => 1,
+    '-' => -1,
+    _ => return s.parse().ok().map(Zone::Named),
+  };
+  let (hours, minutes) = s[1..].split_once(':')?;
+  let hours: i32 = hours.parse().ok()?;
+  let minutes: i32 = minutes.parse().ok()?;
+  if hours > 23 || minutes > 59 {
+    return None;
+  }
+  FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).map(Zone::Fixed)
+}
+
+fn timezone<'de, D>(deserializer: D) -> Result<Zone, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let s = String::deserialize(deserializer)?;
+  parse_timezone(&s).ok_or_else(|| {
+    de::Error::invalid_value(
+      de::Unexpected::Str(&s),
+      &"a time zone such as `UTC`, `Europe/Paris` or `+02:00`",
+    )
+  })
+}
+
+fn local_time<'de, D>(deserializer: D) -> Result<Option<NaiveDateTime>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let s = String::deserialize(deserializer)?;
+  NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M")
+    .map(Some)
+    .map_err(|_| {
+      de::Error::invalid_value(
+        de::Unexpected::Str(&s),
+        &"a date and time such as `2024-12-20 18:00`",
+      )
+    })
+}
+
+/// A window during which Construct is paused, either on a schedule or
+/// between two dates, in the time zone `timezone`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FreezeWindow {
+  /// Shown in status replies and comments.
+  pub name: Option<String>,
+  pub schedule: Option<Schedule>,
+  #[serde(default, deserialize_with = "local_time")]
+  pub from: Option<NaiveDateTime>,
+  #[serde(default, deserialize_with = "local_time")]
+  pub until: Option<NaiveDateTime>,
+  #[serde(default = "utc", deserialize_with = "timezone")]
+  pub timezone: Zone,
+  /// Target branches frozen; all of them if empty.
+  #[serde(default)]
+  pub branches: Vec<String>,
+}
+
+fn utc() -> Zone {
+  Zone::Fixed(FixedOffset::east(0))
+}
+
+impl FreezeWindow {
+  pub fn validate(&self) -> Result<(), String> {
+    match (&self.schedule, self.from, self.until) {
+      (Some(_), None, None) => Ok(()),
+      (None, Some(from), Some(until)) if from < until => Ok(()),
+      (None, Some(_), Some(_)) => Err("`from` must be before `until`".to_string()),
+      _ => Err("needs either `schedule`, or both `from` and `until`".to_string()),
+    }
+  }
+
+  /// Whether the window freezes `target` at `now`.
+  pub fn freezes(&self, target: &str, now: DateTime<Utc>) -> bool {
+    if !self.branches.is_empty() && !self.branches.iter().any(|b| b == target) {
+      return false;
+    }
+    let local = self.timezone.local(now);
+    match (&self.schedule, self.from, self.until) {
+      (Some(schedule), _, _) => schedule.matches(local),
+      (None, Some(from), Some(until)) => from <= local && local < until,
+      _ => false,
+    }
+  }
+}
+
+impl fmt::Display for FreezeWindow {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if let Some(name) = &self.name {
+      write!(f, "`{}` ", name)?;
+    }
+    match (&self.schedule, self.until) {
+      (Some(schedule), _) => write!(f, "(on schedule `{}` {})", schedule, self.timezone),
+      (None, Some(until)) => write!(
+        f,
+        "(until {} {})",
+        until.format("%Y-%m-%d %H:%M"),
+        self.timezone
+      ),
+      _ => Ok(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  #[test]
+  fn test_schedule() {
+    // Friday afternoons and weekends
+    let schedule = Schedule::parse("* 16-23 * * 5").unwrap();
+    let friday = NaiveDateTime::parse_from_str("2024-12-20 16:30", "%Y-%m-%d %H:%M").unwrap();
+    assert!(schedule.matches(friday));
+    assert!(!schedule.matches(friday - chrono::Duration::hours(1)));
+    let weekend = Schedule::parse("*/15 * * * 6,7").unwrap();
+    assert!(weekend.matches(friday + chrono::Duration::days(2) - chrono::Duration::minutes(30)));
+    assert!(!weekend.matches(friday + chrono::Duration::days(2) - chrono::Duration::minutes(29)));
+    assert!(Schedule::parse("* * * *").is_err());
+    assert!(Schedule::parse("60 * * * *").is_err());
+    assert!(Schedule::parse("* 5-3 * * *").is_err());
+    assert!(Schedule::parse("*/0 * * * *").is_err());
+
+    // as in cron, a day matches either restricted day field
+    let schedule = Schedule::parse("0 9 1 * 1").unwrap();
+    let at = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+    assert!(schedule.matches(at("2024-12-01 09:00"))); // Sunday the 1st
+    assert!(schedule.matches(at("2024-12-02 09:00"))); // Monday the 2nd
+    assert!(!schedule.matches(at("2024-12-03 09:00")));
+    // a field starting with `*` still restricts the other
+    let schedule = Schedule::parse("0 9 */2 * 1").unwrap();
+    assert!(schedule.matches(at("2024-12-09 09:00")));
+    assert!(!schedule.matches(at("2024-12-02 09:00")));
+    assert!(!schedule.matches(at("2024-12-03 09:00")));
+    let schedule = Schedule::parse("0 9 * * 1").unwrap();
+    assert!(!schedule.matches(at("2024-12-01 09:00")));
+  }
+
+  #[test]
+  fn test_named_timezone() {
+    // Friday 16:00 in Paris is 15:00 UTC in winter and 14:00 UTC in summer
+    let window: FreezeWindow = toml::from_str(
+      r#"
+schedule = "* 16-23 * * 5"
+timezone = "Europe/Paris"
+"#,
+    )
+    .unwrap();
+    assert!(window.validate().is_ok());
+    assert!(window.freezes("main", Utc.ymd(2024, 12, 20).and_hms(15, 0, 0)));
+    assert!(!window.freezes("main", Utc.ymd(2024, 12, 20).and_hms(14, 59, 0)));
+    assert!(window.freezes("main", Utc.ymd(2024, 6, 21).and_hms(14, 0, 0)));
+    assert!(!window.freezes("main", Utc.ymd(2024, 6, 21).and_hms(13, 59, 0)));
+    assert_eq!(
+      window.to_string(),
+      "(on schedule `* 16-23 * * 5` Europe/Paris)"
+    );
+  }
+
+  #[test]
+  fn test_freezes() {
+    let window: FreezeWindow = toml::from_str(
+      r#"
+name = "release"
+from = "2024-12-20 18:00"
+until = "2025-01-06 08:00"
+timezone = "+01:00"
+branches = ["main"]
+"#,
+    )
+    .unwrap();
+    assert!(window.validate().is_ok());
+    let start = Utc.ymd(2024, 12, 20).and_hms(17, 0, 0);
+    assert!(window.freezes("main", start));
+    assert!(!window.freezes("main", start - chrono::Duration::seconds(1)));
+    assert!(!window.freezes("release-1", start));
+    assert_eq!(
+      window.to_string(),
+      "`release` (until 2025-01-06 08:00 +01:00)"
+    );
+    assert_eq!(
+      parse_timezone("-05:30"),
+      FixedOffset::west_opt(5 * 3600 + 1800).map(Zone::Fixed)
+    );
+    assert!(parse_timezone("Europe/Nowhere").is_none());
+    let window: FreezeWindow = toml::from_str("name = \"x\"").unwrap();
+    assert!(window.validate().is_err());
+  }
+}
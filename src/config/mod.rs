@@ -0,0 +1,36 @@
+use chrono::Duration;
+use serde::{de, Deserialize, Deserializer};
+
+pub mod freeze;
+pub mod repo;
+pub mod server;
+pub mod template;
+
+/// Parse a duration written as a number followed by a unit: `s`, `m`, `h`
+/// or `d`, e.g. `90s` or `24h`.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+  let s = s.trim();
+  let split = s.find(|c: char| !c.is_ascii_digit())?;
+  let (number, unit) = s.split_at(split);
+  let number: i64 = number.parse().ok()?;
+  match unit.trim() {
+    "s" => Some(Duration::seconds(number)),
+    "m" => Some(Duration::minutes(number)),
+    "h" => Some(Duration::hours(number)),
+    "d" => Some(Duration::days(number)),
+    _ => None,
+  }
+}
+
+pub(crate) fn duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let s = String::deserialize(deserializer)?;
+  parse_duration(&s).ok_or_else(|| {
+    de::Error::invalid_value(
+      de::Unexpected::Str(&s),
+      &"a duration such as `90s`, `10m` or `24h`",
+    )
+  })
+}
@@ -0,0 +1,756 @@
+use crate::config::duration;
+use crate::config::freeze::FreezeWindow;
+use crate::config::template::Template;
+use crate::control::command::Command;
+use crate::control::label::LabelConfig;
+use crate::github::types::{AccessLevel, Repository};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Location of the configuration file, read from the default branch.
+pub const CONFIG_PATH: &str = ".github/cherry.toml";
+
+#[derive(Debug, Error)]
+pub enum RepoConfigError {
+  #[error("{} is not valid UTF-8", CONFIG_PATH)]
+  Utf8(#[from] std::str::Utf8Error),
+  #[error("{0}")]
+  Parse(#[from] toml::de::Error),
+  #[error("{0}")]
+  Invalid(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+  Merge,
+  /// One commit per batch, with every PR as a parent.
+  Octopus,
+  /// One commit per PR.
+  Squash,
+  /// One commit per batch.
+  BatchSquash,
+  /// Replay each PR's commits, for a linear history.
+  CherryPick,
+}
+
+impl fmt::Display for Strategy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Merge => write!(f, "merge"),
+      Self::Octopus => write!(f, "octopus"),
+      Self::Squash => write!(f, "squash"),
+      Self::BatchSquash => write!(f, "batch-squash"),
+      Self::CherryPick => write!(f, "cherry-pick"),
+    }
+  }
+}
+
+impl FromStr for Strategy {
+  type Err = RepoConfigError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "merge" => Ok(Self::Merge),
+      "octopus" => Ok(Self::Octopus),
+      "squash" => Ok(Self::Squash),
+      "batch-squash" => Ok(Self::BatchSquash),
+      "cherry-pick" => Ok(Self::CherryPick),
+      _ => Err(RepoConfigError::Invalid(format!(
+        "unknown strategy `{}`",
+        s
+      ))),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+  #[serde(deserialize_with = "duration")]
+  pub requested: Duration,
+  #[serde(deserialize_with = "duration")]
+  pub queued: Duration,
+  #[serde(deserialize_with = "duration")]
+  pub merging: Duration,
+  #[serde(deserialize_with = "duration")]
+  pub split: Duration,
+  #[serde(deserialize_with = "duration")]
+  pub constructing: Duration,
+  #[serde(deserialize_with = "duration")]
+  pub testing: Duration,
+  #[serde(deserialize_with = "duration")]
+  pub success: Duration,
+}
+
+impl Default for Timeouts {
+  fn default() -> Self {
+    Self {
+      requested: Duration::hours(1),
+      queued: Duration::hours(24),
+      merging: Duration::hours(24),
+      split: Duration::hours(24),
+      constructing: Duration::minutes(15),
+      testing: Duration::hours(1),
+      success: Duration::minutes(15),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchingConfig {
+  /// How long the oldest queued PR waits for others to arrive before a
+  /// batch is constructed.
+  #[serde(deserialize_with = "duration")]
+  pub wait: Duration,
+  /// How long after the most recently queued PR a batch is constructed, so
+  /// that PRs queued together are batched together.
+  #[serde(deserialize_with = "duration")]
+  pub debounce: Duration,
+  /// Construct a batch as soon as this many PRs are queued.  If set, `wait`
+  /// and `debounce` are ignored, and a smaller batch is only constructed
+  /// after `max_wait`.
+  pub fill: Option<usize>,
+  /// Construct a batch once the oldest queued PR has waited this long,
+  /// whatever the other settings say.
+  #[serde(deserialize_with = "duration")]
+  pub max_wait: Duration,
+  /// Maximum number of PRs in a batch.
+  pub max_size: Option<usize>,
+  /// PRs with any of these labels are merged in a batch of their own, like
+  /// PRs requested with `cherry merge rollup=never`.
+  pub alone_labels: Vec<String>,
+  /// Scale `max_size` by the success rate of the last `adaptive_window`
+  /// batches, so that failures lead to smaller batches.
+  pub adaptive: bool,
+  pub adaptive_window: usize,
+}
+
+impl Default for BatchingConfig {
+  fn default() -> Self {
+    Self {
+      wait: Duration::minutes(10),
+      debounce: Duration::zero(),
+      fill: None,
+      max_wait: Duration::hours(1),
+      max_size: None,
+      alone_labels: vec![],
+      adaptive: false,
+      adaptive_window: 10,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrainConfig {
+  /// Number of merge attempts tested at once.  Each attempt after the first
+  /// is built on the staging commit of the one before it, and tested on its
+  /// own `<staging_branch>-train-<id>` branch.  1 disables speculation.
+  pub depth: usize,
+}
+
+impl Default for TrainConfig {
+  fn default() -> Self {
+    Self { depth: 1 }
+  }
+}
+
+/// How the PRs of a batch which failed its checks are split to be retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SplitStrategy {
+  /// Split the batch in two halves, in queue order.
+  Halve,
+  /// Retry each PR which was already in a failing batch on its own, and
+  /// the others together.
+  Isolate,
+  /// Retry each group of PRs changing overlapping paths on its own.
+  Paths,
+}
+
+impl fmt::Display for SplitStrategy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Halve => write!(f, "halve"),
+      Self::Isolate => write!(f, "isolate"),
+      Self::Paths => write!(f, "paths"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SplitConfig {
+  /// Strategy used when a batch fails.  `isolate` and `paths` fall back to
+  /// `halve` when they would not split the batch.
+  pub strategy: SplitStrategy,
+  /// Number of failed batches after which a PR is dropped.  Halving puts a
+  /// PR in up to log2(batch size) failing batches before the culprit is
+  /// found, so this should be larger than that.
+  pub max_failures: u32,
+}
+
+impl Default for SplitConfig {
+  fn default() -> Self {
+    Self {
+      strategy: SplitStrategy::Halve,
+      max_failures: 5,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApprovalConfig {
+  /// Number of approving reviews required.
+  pub count: usize,
+  /// Only count reviews made on the PR's current commit.
+  pub current_commit_only: bool,
+}
+
+impl Default for ApprovalConfig {
+  fn default() -> Self {
+    Self {
+      count: 1,
+      current_commit_only: true,
+    }
+  }
+}
+
+/// Optional actions taken on the PRs of a merge attempt once it lands.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PostMergeConfig {
+  /// Delete the head branch of each PR, unless it is in a fork, protected,
+  /// or a target branch.
+  pub delete_branches: bool,
+  /// Close the issues listed with `Fixes #N` and the like in each PR's body,
+  /// when merging into a branch other than the default branch.
+  pub close_issues: bool,
+  /// Label added to each PR.
+  pub label: Option<String>,
+  /// Title of the milestone set on each PR.
+  pub milestone: Option<String>,
+}
+
+/// Shadow mode, for trialing cherry on a repository: PRs are queued, batched
+/// and tested as usual, but GitHub writes other than comments are only
+/// recorded as intents.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShadowConfig {
+  pub enabled: bool,
+  /// Prefix comments with `[shadow]`.
+  pub prefix_comments: bool,
+}
+
+impl Default for ShadowConfig {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      prefix_comments: true,
+    }
+  }
+}
+
+/// Templates of the commit messages written when constructing merges.  See
+/// `Template` for the syntax.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessageConfig {
+  /// Message of the commit merging each PR, for the merge strategy.
+  pub merge: Template,
+  /// Message of the commit merging a whole batch, for the octopus strategy.
+  /// Only `{{batch}}` may be used outside `{{#each}}`.
+  pub octopus: Template,
+  /// Message of the commit squashing each PR, for the squash strategy.
+  /// `Co-authored-by` trailers are appended.
+  pub squash: Template,
+}
+
+impl Default for MessageConfig {
+  fn default() -> Self {
+    Self {
+      merge: Template::parse("Merge #{{number}}").unwrap(),
+      octopus: Template::parse("Merge {{batch}}").unwrap(),
+      squash: Template::parse("{{title}} (#{{number}})\n\n{{body}}").unwrap(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandConfig {
+  /// Access level required to run commands not listed in `overrides`.
+  pub permission: AccessLevel,
+  /// Access level required to run individual commands, by name.
+  pub overrides: HashMap<String, AccessLevel>,
+}
+
+impl Default for CommandConfig {
+  fn default() -> Self {
+    Self {
+      permission: AccessLevel::Write,
+      overrides: [
+        ("ping".to_string(), AccessLevel::Read),
+        ("status".to_string(), AccessLevel::Read),
+        ("report".to_string(), AccessLevel::Read),
+        ("treeclosed".to_string(), AccessLevel::Maintain),
+        ("treeopen".to_string(), AccessLevel::Maintain),
+        ("overridebase".to_string(), AccessLevel::Maintain),
+      ]
+      .iter()
+      .cloned()
+      .collect(),
+    }
+  }
+}
+
+impl CommandConfig {
+  pub fn required(&self, command: &str) -> AccessLevel {
+    self
+      .overrides
+      .get(command)
+      .copied()
+      .unwrap_or(self.permission)
+  }
+}
+
+/// Settings of the queue into one target branch, overriding the
+/// repository-wide ones.  Sections replace the repository-wide section
+/// entirely.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TargetConfig {
+  /// Defaults to `<staging_branch>-<target>`, or `staging_branch` for the
+  /// repository's default branch.
+  pub staging_branch: Option<String>,
+  pub strategy: Option<Strategy>,
+  pub required_checks: Option<Vec<String>>,
+  pub batching: Option<BatchingConfig>,
+  pub splitting: Option<SplitConfig>,
+  pub train: Option<TrainConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoConfig {
+  /// Branches which PRs may be merged into.  If empty, only the
+  /// repository's default branch is allowed.
+  pub branches: Vec<String>,
+  /// Branch to which constructed merges are pushed for testing.
+  pub staging_branch: String,
+  pub strategy: Strategy,
+  /// Strategies which PRs may choose instead of `strategy`, with
+  /// `cherry merge <strategy>`.
+  pub allowed_strategies: Vec<Strategy>,
+  /// Status contexts and check runs which must succeed on the staging
+  /// branch.  If empty, every reported status must succeed.
+  pub required_checks: Vec<String>,
+  pub batching: BatchingConfig,
+  pub splitting: SplitConfig,
+  pub train: TrainConfig,
+  /// Overrides for the queues into individual target branches, by branch.
+  pub targets: HashMap<String, TargetConfig>,
+  pub timeouts: Timeouts,
+  pub approval: ApprovalConfig,
+  pub messages: MessageConfig,
+  pub post_merge: PostMergeConfig,
+  pub shadow: ShadowConfig,
+  /// Windows during which no batch is constructed, as `[[freezes]]`.
+  pub freezes: Vec<FreezeWindow>,
+  pub commands: CommandConfig,
+  pub labels: LabelConfig,
+}
+
+impl Default for RepoConfig {
+  fn default() -> Self {
+    Self {
+      branches: vec![],
+      staging_branch: "cherry/staging".to_string(),
+      strategy: Strategy::Merge,
+      allowed_strategies: vec![],
+      required_checks: vec![],
+      batching: BatchingConfig::default(),
+      splitting: SplitConfig::default(),
+      train: TrainConfig::default(),
+      targets: HashMap::new(),
+      timeouts: Timeouts::default(),
+      approval: ApprovalConfig::default(),
+      messages: MessageConfig::default(),
+      post_merge: PostMergeConfig::default(),
+      shadow: ShadowConfig::default(),
+      freezes: vec![],
+      commands: CommandConfig::default(),
+      labels: LabelConfig::default(),
+    }
+  }
+}
+
+impl RepoConfig {
+  /// Branch on which the merge attempt `id` is tested.
+  pub fn attempt_staging_branch(&self, id: &str) -> String {
+    if self.train.depth > 1 {
+      format!("{}-train-{}", self.staging_branch, id)
+    } else {
+      self.staging_branch.clone()
+    }
+  }
+
+  /// Whether PRs into `target` may be merged, `default_branch` being the
+  /// repository's default branch.
+  pub fn allows_target(&self, target: &str, default_branch: &str) -> bool {
+    if self.branches.is_empty() {
+      target == default_branch
+    } else {
+      self.branches.iter().any(|b| b == target)
+    }
+  }
+
+  /// Configuration of the queue into `target`, with the overrides of
+  /// `targets.<target>` applied.
+  pub fn for_target(&self, target: &str, default_branch: &str) -> Self {
+    let mut config = self.clone();
+    let overrides = self.targets.get(target).cloned().unwrap_or_default();
+    config.staging_branch = match overrides.staging_branch {
+      Some(staging_branch) => staging_branch,
+      None if target == default_branch => self.staging_branch.clone(),
+      None => format!("{}-{}", self.staging_branch, target),
+    };
+    if let Some(strategy) = overrides.strategy {
+      config.strategy = strategy;
+    }
+    if let Some(required_checks) = overrides.required_checks {
+      config.required_checks = required_checks;
+    }
+    if let Some(batching) = overrides.batching {
+      config.batching = batching;
+    }
+    if let Some(splitting) = overrides.splitting {
+      config.splitting = splitting;
+    }
+    if let Some(train) = overrides.train {
+      config.train = train;
+    }
+    config
+  }
+
+  /// The freeze window in effect on `target` at `now`, if any.
+  pub fn active_freeze(&self, target: &str, now: DateTime<Utc>) -> Option<&FreezeWindow> {
+    self
+      .freezes
+      .iter()
+      .find(|window| window.freezes(target, now))
+  }
+
+  /// Whether a PR may be merged with `strategy`.
+  pub fn allows_strategy(&self, strategy: Strategy) -> bool {
+    strategy == self.strategy || self.allowed_strategies.contains(&strategy)
+  }
+
+  pub fn parse(content: &[u8]) -> Result<Self, RepoConfigError> {
+    let config: Self = toml::from_str(std::str::from_utf8(content)?)?;
+    config.validate()?;
+    Ok(config)
+  }
+
+  /// Parse `content`, or use the defaults if the file does not exist, and
+  /// check the staging branch of each target against `default_branch`.
+  pub fn load(content: Option<&[u8]>, default_branch: &str) -> Result<Self, RepoConfigError> {
+    let config = match content {
+      Some(content) => Self::parse(content)?,
+      None => Self::default(),
+    };
+    config.validate_staging_branches(default_branch)?;
+    Ok(config)
+  }
+
+  /// Check that no staging branch, which is force-pushed, is a target branch.
+  fn validate_staging_branches(&self, default_branch: &str) -> Result<(), RepoConfigError> {
+    let mut targets: Vec<&str> = self.branches.iter().map(String::as_str).collect();
+    targets.push(default_branch);
+    targets.extend(self.targets.keys().map(String::as_str));
+    for target in &targets {
+      let staging_branch = self.for_target(target, default_branch).staging_branch;
+      if targets.contains(&staging_branch.as_str()) {
+        return Err(RepoConfigError::Invalid(format!(
+          "staging branch `{}` of `{}` is a target branch",
+          staging_branch, target
+        )));
+      }
+    }
+    Ok(())
+  }
+
+  fn validate(&self) -> Result<(), RepoConfigError> {
+    let invalid = |s: String| Err(RepoConfigError::Invalid(s));
+    self.validate_queue()?;
+    for target in self.targets.keys() {
+      if !self.branches.is_empty() && !self.branches.contains(target) {
+        return invalid(format!("targets.{} is not listed in branches", target));
+      }
+      if let Err(e) = self.for_target(target, target).validate_queue() {
+        return invalid(format!("targets.{}: {}", target, e));
+      }
+    }
+    let timeouts = &self.timeouts;
+    for (name, timeout) in &[
+      ("requested", timeouts.requested),
+      ("queued", timeouts.queued),
+      ("merging", timeouts.merging),
+      ("split", timeouts.split),
+      ("constructing", timeouts.constructing),
+      ("testing", timeouts.testing),
+      ("success", timeouts.success),
+    ] {
+      if *timeout <= Duration::zero() {
+        return invalid(format!("timeouts.{} must be positive", name));
+      }
+    }
+    for (i, window) in self.freezes.iter().enumerate() {
+      if let Err(e) = window.validate() {
+        return invalid(format!("freezes[{}]: {}", i, e));
+      }
+    }
+    if let Err(e) = self.messages.octopus.check_batch() {
+      return invalid(format!("messages.octopus: {}", e));
+    }
+    for command in self.commands.overrides.keys() {
+      if !Command::NAMES.contains(&command.as_str()) {
+        return invalid(format!(
+          "unknown command `{}` in commands.overrides",
+          command
+        ));
+      }
+    }
+    Ok(())
+  }
+
+  /// Validate the settings which a target branch may override.
+  fn validate_queue(&self) -> Result<(), RepoConfigError> {
+    let invalid = |s: String| Err(RepoConfigError::Invalid(s));
+    if self.staging_branch.is_empty() {
+      return invalid("staging_branch must not be empty".to_string());
+    }
+    if self.branches.contains(&self.staging_branch) {
+      return invalid(format!(
+        "staging branch `{}` is also listed in branches",
+        self.staging_branch
+      ));
+    }
+    let batching = &self.batching;
+    if batching.wait < Duration::zero() || batching.debounce < Duration::zero() {
+      return invalid("batching.wait and batching.debounce must not be negative".to_string());
+    }
+    if batching.max_wait < batching.wait {
+      return invalid("batching.max_wait must not be less than batching.wait".to_string());
+    }
+    if batching.fill == Some(0) || batching.max_size == Some(0) {
+      return invalid("batching.fill and batching.max_size must be positive".to_string());
+    }
+    if let (Some(fill), Some(max_size)) = (batching.fill, batching.max_size) {
+      if fill > max_size {
+        return invalid("batching.fill must not exceed batching.max_size".to_string());
+      }
+    }
+    if batching.adaptive && batching.max_size.is_none() {
+      return invalid("batching.adaptive requires batching.max_size".to_string());
+    }
+    if batching.adaptive_window == 0 {
+      return invalid("batching.adaptive_window must be positive".to_string());
+    }
+    if self.splitting.max_failures == 0 {
+      return invalid("splitting.max_failures must be positive".to_string());
+    }
+    if self.train.depth == 0 {
+      return invalid("train.depth must be positive".to_string());
+    }
+    Ok(())
+  }
+}
+
+/// Most recently loaded valid configuration of each repository, and the
+/// error of each repository whose configuration has never been valid.
+#[derive(Default)]
+pub struct ConfigCache {
+  configs: HashMap<Repository, Arc<RepoConfig>>,
+  errors: HashMap<Repository, String>,
+}
+
+impl ConfigCache {
+  pub fn new() -> Self {
+    Self {
+      configs: HashMap::new(),
+      errors: HashMap::new(),
+    }
+  }
+
+  pub fn get(&self, repo: &Repository) -> Option<Arc<RepoConfig>> {
+    self.configs.get(repo).cloned()
+  }
+
+  pub fn error(&self, repo: &Repository) -> Option<String> {
+    self.errors.get(repo).cloned()
+  }
+
+  pub fn insert(&mut self, repo: Repository, config: Arc<RepoConfig>) {
+    self.errors.remove(&repo);
+    self.configs.insert(repo, config);
+  }
+
+  /// Record that `repo` has no valid configuration.
+  pub fn insert_error(&mut self, repo: Repository, error: String) {
+    self.errors.insert(repo, error);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    let config = RepoConfig::parse(
+      br#"
+branches = ["main", "release-1.x"]
+staging_branch = "staging"
+strategy = "merge"
+allowed_strategies = ["squash"]
+required_checks = ["ci/build"]
+
+[batching]
+wait = "5m"
+max_size = 4
+alone_labels = ["merge-alone"]
+adaptive = true
+
+[splitting]
+strategy = "isolate"
+
+[train]
+depth = 3
+
+[targets."release-1.x"]
+strategy = "squash"
+train = { depth = 1 }
+
+[timeouts]
+testing = "2h"
+
+[approval]
+count = 2
+
+[post_merge]
+delete_branches = true
+milestone = "v1.0"
+
+[shadow]
+enabled = true
+
+[[freezes]]
+name = "weekend"
+schedule = "* * * * 6,0"
+timezone = "-08:00"
+
+[commands]
+permission = "maintain"
+overrides = { ping = "none" }
+
+[labels]
+blocking = ["wip", "do-not-merge"]
+merge = ["ready-to-merge"]
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.branches, vec!["main", "release-1.x"]);
+    assert_eq!(config.staging_branch, "staging");
+    assert_eq!(config.allowed_strategies, vec![Strategy::Squash]);
+    assert_eq!(config.batching.wait, Duration::minutes(5));
+    assert_eq!(config.batching.max_size, Some(4));
+    assert_eq!(config.batching.max_wait, Duration::hours(1));
+    assert_eq!(config.splitting.strategy, SplitStrategy::Isolate);
+    assert_eq!(config.splitting.max_failures, 5);
+    assert_eq!(config.train.depth, 3);
+    assert!(config.allows_target("release-1.x", "main"));
+    assert!(!config.allows_target("develop", "main"));
+    let release = config.for_target("release-1.x", "main");
+    assert_eq!(release.staging_branch, "staging-release-1.x");
+    assert_eq!(release.strategy, Strategy::Squash);
+    assert_eq!(release.train.depth, 1);
+    assert_eq!(release.batching.max_size, Some(4));
+    assert_eq!(config.for_target("main", "main").staging_branch, "staging");
+    assert_eq!(config.timeouts.testing, Duration::hours(2));
+    assert_eq!(config.timeouts.queued, Duration::hours(24));
+    assert_eq!(config.approval.count, 2);
+    assert!(config.post_merge.delete_branches);
+    assert!(!config.post_merge.close_issues);
+    assert_eq!(config.post_merge.milestone.as_deref(), Some("v1.0"));
+    assert!(config.shadow.enabled);
+    assert!(config.shadow.prefix_comments);
+    assert_eq!(config.freezes.len(), 1);
+    assert_eq!(
+      config.commands.required("treeclosed"),
+      AccessLevel::Maintain
+    );
+    assert_eq!(config.commands.required("merge"), AccessLevel::Maintain);
+    assert_eq!(config.commands.required("ping"), AccessLevel::None);
+    assert!(config.labels.is_blocking("wip"));
+    assert!(config.labels.is_merge("ready-to-merge"));
+
+    assert!(RepoConfig::parse(b"").is_ok());
+    assert_eq!(
+      RepoConfig::parse(b"strategy = \"octopus\"")
+        .unwrap()
+        .strategy,
+      Strategy::Octopus
+    );
+    assert!(RepoConfig::parse(b"strategy = \"yolo\"").is_err());
+    assert!(RepoConfig::parse(b"unknown_key = 1").is_err());
+    assert!(RepoConfig::parse(b"[timeouts]\ntesting = \"soon\"").is_err());
+    assert!(RepoConfig::parse(b"branches = [\"main\"]\nstaging_branch = \"main\"").is_err());
+    assert!(RepoConfig::parse(b"[commands.overrides]\nmerj = \"read\"").is_err());
+    assert!(RepoConfig::parse(b"[batching]\nadaptive = true").is_err());
+    assert!(RepoConfig::parse(b"[train]\ndepth = 0").is_err());
+    assert!(RepoConfig::parse(b"branches = [\"main\"]\n[targets.dev]").is_err());
+    let e = RepoConfig::parse(b"[targets.main.train]\ndepth = 0").unwrap_err();
+    assert_eq!(e.to_string(), "targets.main: train.depth must be positive");
+    assert!(RepoConfig::parse(b"[batching]\nfill = 5\nmax_size = 4").is_err());
+    let e = RepoConfig::parse(b"[[freezes]]\nfrom = \"2024-12-20 18:00\"").unwrap_err();
+    assert_eq!(
+      e.to_string(),
+      "freezes[0]: needs either `schedule`, or both `from` and `until`"
+    );
+    let e = RepoConfig::parse(b"[messages]\nmerge = \"{{titel}}\"").unwrap_err();
+    assert!(e.to_string().contains("unknown placeholder `{{titel}}`"));
+    let e = RepoConfig::parse(b"[messages]\noctopus = \"{{title}}\"").unwrap_err();
+    assert!(e.to_string().starts_with("messages.octopus: `{{title}}`"));
+
+    // staging branches are force-pushed, so none may be a target branch
+    assert!(RepoConfig::load(None, "main").is_ok());
+    let e = RepoConfig::load(Some("staging_branch = \"main\"".as_bytes()), "main").unwrap_err();
+    assert_eq!(
+      e.to_string(),
+      "staging branch `main` of `main` is a target branch"
+    );
+    let e = RepoConfig::load(
+      Some("[targets.dev]\nstaging_branch = \"dev\"".as_bytes()),
+      "main",
+    )
+    .unwrap_err();
+    assert_eq!(
+      e.to_string(),
+      "staging branch `dev` of `dev` is a target branch"
+    );
+    let content = "branches = [\"dev\", \"cherry/staging-dev\"]";
+    assert!(RepoConfig::load(Some(content.as_bytes()), "main").is_err());
+  }
+}
@@ -0,0 +1,376 @@
+use crate::config::duration;
+use crate::github::client::Credentials;
+use crate::tls::{self, TlsError};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use actix_web::http::uri::{InvalidUri, Uri};
+use chrono::Duration;
+use jsonwebtoken::EncodingKey;
+use quaint::connector::ConnectionInfo;
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ServerConfigError {
+  #[error("reading config file `{}`", .0.display())]
+  Read(PathBuf, #[source] io::Error),
+  #[error("parsing config file")]
+  Parse(#[from] toml::de::Error),
+  #[error("invalid value for environment variable `{0}`")]
+  Env(&'static str),
+  #[error("missing setting `{0}`")]
+  Missing(&'static str),
+  #[error("only one of `github.private_key` and `github.private_key_path` may be set")]
+  ConflictingPrivateKey,
+  #[error("reading private key `{}`", .0.display())]
+  ReadPrivateKey(PathBuf, #[source] io::Error),
+  #[error("base64-decoding private key")]
+  Base64(#[from] base64::DecodeError),
+  #[error("parsing private key")]
+  PrivateKey(#[from] jsonwebtoken::errors::Error),
+  #[error("invalid GitHub API URL")]
+  ApiUrl(#[from] InvalidUri),
+  #[error("invalid database URL")]
+  DatabaseUrl(#[source] quaint::error::Error),
+  #[error("loading TLS certificate")]
+  Tls(#[from] TlsError),
+  #[error("{0}")]
+  Invalid(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+  /// Address for plain HTTP.  Disabled if unset, in which case `tls` must
+  /// be configured.
+  pub bind_address: Option<String>,
+}
+
+impl Default for HttpConfig {
+  fn default() -> Self {
+    Self {
+      bind_address: Some("127.0.0.1:8080".to_string()),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+  pub bind_address: String,
+  /// PEM file containing the certificate chain, leaf first.  Both files are
+  /// re-read on SIGHUP.
+  pub certificate_chain: PathBuf,
+  /// PEM file containing the certificate's private key.
+  pub private_key: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+  /// See https://docs.rs/quaint/0.1.13/quaint/pooled/struct.Quaint.html#method.new for the format.
+  pub url: Option<String>,
+  /// Maximum number of open connections.
+  pub pool_size: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GithubConfig {
+  pub api_url: String,
+  pub app_id: Option<String>,
+  /// Base64-encoded PEM private key of the app.
+  pub private_key: Option<String>,
+  /// Path to the PEM private key of the app.
+  pub private_key_path: Option<PathBuf>,
+  /// Secret used to verify webhook signatures.  Signatures are not checked
+  /// if unset.
+  pub webhook_secret: Option<String>,
+}
+
+impl Default for GithubConfig {
+  fn default() -> Self {
+    Self {
+      api_url: "https://api.github.com".to_string(),
+      app_id: None,
+      private_key: None,
+      private_key_path: None,
+      webhook_secret: None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GitBackend {
+  /// Construct merges through the GitHub API.
+  Api,
+  /// Construct merges in local mirrors and push the staging branch.
+  Local,
+}
+
+impl FromStr for GitBackend {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "api" => Ok(Self::Api),
+      "local" => Ok(Self::Local),
+      _ => Err(()),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitConfig {
+  pub backend: GitBackend,
+  /// Directory holding the bare mirrors used by the `local` backend.
+  pub mirror_dir: PathBuf,
+  /// Base URL of the repositories for the `local` backend, to which
+  /// `/<owner>/<repo>.git` is appended.
+  pub remote_url: String,
+  /// Committer of the commits created by the `local` backend.
+  pub committer_name: String,
+  pub committer_email: String,
+}
+
+impl Default for GitConfig {
+  fn default() -> Self {
+    Self {
+      backend: GitBackend::Api,
+      mirror_dir: "mirrors".into(),
+      remote_url: "https://github.com".to_string(),
+      committer_name: "cherry".to_string(),
+      committer_email: "cherry@localhost".to_string(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+  /// Filter in `env_logger` syntax, e.g. `info` or `cherry=debug`.
+  pub filter: String,
+}
+
+impl Default for LogConfig {
+  fn default() -> Self {
+    Self {
+      filter: "info".to_string(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollConfig {
+  #[serde(deserialize_with = "duration")]
+  pub interval: Duration,
+}
+
+impl Default for PollConfig {
+  fn default() -> Self {
+    Self {
+      interval: Duration::minutes(10),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+  pub http: HttpConfig,
+  pub tls: Option<TlsConfig>,
+  pub database: DatabaseConfig,
+  pub github: GithubConfig,
+  pub git: GitConfig,
+  pub log: LogConfig,
+  pub poll: PollConfig,
+}
+
+impl ServerConfig {
+  /// Load the configuration file at `path`, if any, and apply overrides
+  /// from the environment.
+  pub fn load(path: Option<&Path>) -> Result<Self, ServerConfigError> {
+    let mut config: Self = match path {
+      Some(path) => toml::from_str(
+        fs::read_to_string(path)
+          .map_err(|e| ServerConfigError::Read(path.to_path_buf(), e))?
+          .as_str(),
+      )?,
+      None => Self::default(),
+    };
+    config.apply_env(|v| std::env::var(v).ok())?;
+    Ok(config)
+  }
+
+  /// Override settings with the environment variables returned by `var`.
+  fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), ServerConfigError> {
+    if let Some(v) = var("BIND_ADDRESS") {
+      self.http.bind_address = Some(v);
+    }
+    if let Some(v) = var("DATABASE_ADDRESS") {
+      self.database.url = Some(v);
+    }
+    if let Some(v) = var("DATABASE_POOL_SIZE") {
+      self.database.pool_size = Some(
+        v.parse()
+          .map_err(|_| ServerConfigError::Env("DATABASE_POOL_SIZE"))?,
+      );
+    }
+    if let Some(v) = var("GITHUB_API_URL") {
+      self.github.api_url = v;
+    }
+    if let Some(v) = var("GITHUB_APP_ID") {
+      self.github.app_id = Some(v);
+    }
+    if let Some(v) = var("GITHUB_APP_PRIVATE_KEY") {
+      self.github.private_key = Some(v);
+      self.github.private_key_path = None;
+    }
+    if let Some(v) = var("GITHUB_APP_PRIVATE_KEY_PATH") {
+      self.github.private_key_path = Some(v.into());
+      self.github.private_key = None;
+    }
+    if let Some(v) = var("GITHUB_WEBHOOK_SECRET") {
+      self.github.webhook_secret = Some(v);
+    }
+    if let Some(v) = var("MERGE_BACKEND") {
+      self.git.backend = v
+        .parse()
+        .map_err(|_| ServerConfigError::Env("MERGE_BACKEND"))?;
+    }
+    if let Some(v) = var("MIRROR_DIR") {
+      self.git.mirror_dir = v.into();
+    }
+    if let Some(v) = var("RUST_LOG") {
+      self.log.filter = v;
+    }
+    if let Some(v) = var("POLL_INTERVAL") {
+      self.poll.interval =
+        super::parse_duration(&v).ok_or(ServerConfigError::Env("POLL_INTERVAL"))?;
+    }
+    Ok(())
+  }
+
+  /// Check every setting without starting anything.
+  pub fn validate(&self) -> Result<(), ServerConfigError> {
+    if self.http.bind_address.is_none() && self.tls.is_none() {
+      return Err(ServerConfigError::Invalid(
+        "at least one of `http.bind_address` and `tls` must be set".to_string(),
+      ));
+    }
+    if self.poll.interval <= Duration::zero() {
+      return Err(ServerConfigError::Invalid(
+        "`poll.interval` must be positive".to_string(),
+      ));
+    }
+    if let Some(tls) = &self.tls {
+      tls::load_certified_key(&tls.certificate_chain, &tls.private_key)?;
+    }
+    ConnectionInfo::from_url(&self.database_url()?).map_err(ServerConfigError::DatabaseUrl)?;
+    self.api_url()?;
+    self.credentials()?;
+    Ok(())
+  }
+
+  /// Database URL including the pool size.
+  pub fn database_url(&self) -> Result<String, ServerConfigError> {
+    let url = self
+      .database
+      .url
+      .clone()
+      .ok_or(ServerConfigError::Missing("database.url"))?;
+    Ok(match self.database.pool_size {
+      Some(pool_size) => {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        format!("{}{}connection_limit={}", url, separator, pool_size)
+      }
+      None => url,
+    })
+  }
+
+  pub fn api_url(&self) -> Result<Uri, ServerConfigError> {
+    Ok(self.github.api_url.trim_end_matches('/').parse()?)
+  }
+
+  pub fn credentials(&self) -> Result<Credentials, ServerConfigError> {
+    let github = &self.github;
+    let private_key = match (&github.private_key, &github.private_key_path) {
+      (Some(_), Some(_)) => return Err(ServerConfigError::ConflictingPrivateKey),
+      (Some(key), None) => base64::decode(key)?,
+      (None, Some(path)) => {
+        fs::read(path).map_err(|e| ServerConfigError::ReadPrivateKey(path.clone(), e))?
+      }
+      (None, None) => return Err(ServerConfigError::Missing("github.private_key")),
+    };
+    Ok(Credentials {
+      app_id: github
+        .app_id
+        .clone()
+        .ok_or(ServerConfigError::Missing("github.app_id"))?,
+      private_key: EncodingKey::from_rsa_pem(&private_key[..])?,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_env_overrides() {
+    let mut config: ServerConfig = toml::from_str(
+      r#"
+[http]
+bind_address = "0.0.0.0:80"
+
+[database]
+url = "file:cherry.db"
+pool_size = 4
+
+[github]
+app_id = "1"
+private_key_path = "/etc/cherry/key.pem"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+      config.database_url().unwrap(),
+      "file:cherry.db?connection_limit=4"
+    );
+
+    config
+      .apply_env(|v| match v {
+        "GITHUB_APP_PRIVATE_KEY" => Some("a2V5".to_string()),
+        "POLL_INTERVAL" => Some("30s".to_string()),
+        "DATABASE_ADDRESS" => Some("file:other.db?db_name=cherry".to_string()),
+        "MERGE_BACKEND" => Some("local".to_string()),
+        _ => None,
+      })
+      .unwrap();
+    assert_eq!(config.http.bind_address.as_deref(), Some("0.0.0.0:80"));
+    assert_eq!(config.github.private_key.as_deref(), Some("a2V5"));
+    assert_eq!(config.github.private_key_path, None);
+    assert_eq!(config.poll.interval, Duration::seconds(30));
+    assert_eq!(config.git.backend, GitBackend::Local);
+    assert_eq!(
+      config.database_url().unwrap(),
+      "file:other.db?db_name=cherry&connection_limit=4"
+    );
+    // "key" is not a PEM file
+    assert!(config.credentials().is_err());
+
+    assert!(config
+      .apply_env(|v| match v {
+        "DATABASE_POOL_SIZE" => Some("many".to_string()),
+        _ => None,
+      })
+      .is_err());
+  }
+}
@@ -0,0 +1,313 @@
+use crate::github::types::GitIdentity;
+
+use serde::{de, Deserialize, Deserializer};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TemplateError {
+  #[error("`{{{{` at byte {0} is never closed with `}}}}`")]
+  Unterminated(usize),
+  #[error("unknown placeholder `{{{{{0}}}}}`")]
+  Unknown(String),
+  #[error("`{{{{#each}}}}` cannot be nested")]
+  NestedEach,
+  #[error("`{{{{#each}}}}` is never closed with `{{{{/each}}}}`")]
+  UnclosedEach,
+  #[error("`{{{{/each}}}}` without a matching `{{{{#each}}}}`")]
+  UnmatchedEnd,
+  #[error("`{{{{{0}}}}}` refers to a single PR, so it can only be used inside `{{{{#each}}}}`")]
+  OutsideEach(String),
+}
+
+/// A PR, as seen by templates.
+#[derive(Debug, Clone, Default)]
+pub struct TemplatePr {
+  pub number: i64,
+  pub title: String,
+  pub body: String,
+  /// Login of the author.
+  pub author: String,
+  /// Logins of the approving reviewers.
+  pub approvers: Vec<String>,
+  /// Git identities of the approving reviewers, for trailers.
+  pub approver_identities: Vec<GitIdentity>,
+  pub labels: Vec<String>,
+  pub url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Var {
+  Number,
+  Title,
+  Body,
+  Author,
+  Approvers,
+  Labels,
+  Url,
+  ApprovedBy,
+  ReviewedBy,
+  Batch,
+}
+
+impl Var {
+  fn parse(name: &str) -> Option<Self> {
+    Some(match name {
+      "number" => Self::Number,
+      "title" => Self::Title,
+      "body" => Self::Body,
+      "author" => Self::Author,
+      "approvers" => Self::Approvers,
+      "labels" => Self::Labels,
+      "url" => Self::Url,
+      "approved_by" => Self::ApprovedBy,
+      "reviewed_by" => Self::ReviewedBy,
+      "batch" => Self::Batch,
+      _ => return None,
+    })
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      Self::Number => "number",
+      Self::Title => "title",
+      Self::Body => "body",
+      Self::Author => "author",
+      Self::Approvers => "approvers",
+      Self::Labels => "labels",
+      Self::Url => "url",
+      Self::ApprovedBy => "approved_by",
+      Self::ReviewedBy => "reviewed_by",
+      Self::Batch => "batch",
+    }
+  }
+
+  fn render(self, pr: Option<&TemplatePr>, batch: &[TemplatePr], out: &mut String) {
+    let trailers = |key: &str, pr: &TemplatePr| {
+      pr.approver_identities
+        .iter()
+        .map(|identity| format!("{}: {}", key, identity))
+        .collect::<Vec<_>>()
+        .join("\n")
+    };
+    let rendered = match (self, pr) {
+      (Self::Batch, _) => batch
+        .iter()
+        .map(|pr| format!("#{}", pr.number))
+        .collect::<Vec<_>>()
+        .join(", "),
+      (_, None) => return,
+      (Self::Number, Some(pr)) => pr.number.to_string(),
+      (Self::Title, Some(pr)) => pr.title.clone(),
+      (Self::Body, Some(pr)) => pr.body.trim().to_string(),
+      (Self::Author, Some(pr)) => pr.author.clone(),
+      (Self::Approvers, Some(pr)) => pr.approvers.join(", "),
+      (Self::Labels, Some(pr)) => pr.labels.join(", "),
+      (Self::Url, Some(pr)) => pr.url.clone(),
+      (Self::ApprovedBy, Some(pr)) => trailers("Approved-by", pr),
+      (Self::ReviewedBy, Some(pr)) => trailers("Reviewed-by", pr),
+    };
+    out.push_str(&rendered);
+  }
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+  Text(String),
+  Var(Var),
+  /// Repeated for every PR in the batch.
+  Each(Vec<Part>),
+}
+
+/// Commit message template.  `{{name}}` is replaced by a property of the
+/// PR, and `{{#each}}...{{/each}}` is repeated for every PR in the batch.
+/// Blank lines left by empty placeholders are collapsed.
+#[derive(Debug, Clone)]
+pub struct Template {
+  parts: Vec<Part>,
+}
+
+fn render_parts(parts: &[Part], pr: Option<&TemplatePr>, batch: &[TemplatePr], out: &mut String) {
+  for part in parts {
+    match part {
+      Part::Text(text) => out.push_str(text),
+      Part::Var(var) => var.render(pr, batch, out),
+      Part::Each(parts) => {
+        for pr in batch {
+          render_parts(parts, Some(pr), batch, out);
+        }
+      }
+    }
+  }
+}
+
+/// Strip trailing whitespace and runs of blank lines.
+fn tidy(message: &str) -> String {
+  let mut lines: Vec<&str> = vec![];
+  for line in message.lines().map(str::trim_end) {
+    let blank = line.is_empty();
+    if blank && matches!(lines.last(), None | Some(&"")) {
+      continue;
+    }
+    lines.push(line);
+  }
+  while lines.last() == Some(&"") {
+    lines.pop();
+  }
+  lines.join("\n")
+}
+
+impl Template {
+  pub fn parse(source: &str) -> Result<Self, TemplateError> {
+    let mut parts = vec![];
+    let mut each: Option<Vec<Part>> = None;
+    let mut offset = 0;
+    while let Some(start) = source[offset..].find("{{").map(|i| offset + i) {
+      if start > offset {
+        let text = Part::Text(source[offset..start].to_string());
+        each.as_mut().unwrap_or(&mut parts).push(text);
+      }
+      let end = source[start..]
+        .find("}}")
+        .map(|i| start + i)
+        .ok_or(TemplateError::Unterminated(start))?;
+      match source[start + 2..end].trim() {
+        "#each" if each.is_some() => return Err(TemplateError::NestedEach),
+        "#each" => each = Some(vec![]),
+        "/each" => match each.take() {
+          Some(inner) => parts.push(Part::Each(inner)),
+          None => return Err(TemplateError::UnmatchedEnd),
+        },
+        name => {
+          let var = Var::parse(name).ok_or_else(|| TemplateError::Unknown(name.to_string()))?;
+          each.as_mut().unwrap_or(&mut parts).push(Part::Var(var));
+        }
+      }
+      offset = end + 2;
+    }
+    if each.is_some() {
+      return Err(TemplateError::UnclosedEach);
+    }
+    if offset < source.len() {
+      parts.push(Part::Text(source[offset..].to_string()));
+    }
+    Ok(Self { parts })
+  }
+
+  /// Check that the template can be rendered for a whole batch, without a
+  /// PR of its own.
+  pub fn check_batch(&self) -> Result<(), TemplateError> {
+    for part in &self.parts {
+      if let Part::Var(var) = part {
+        if *var != Var::Batch {
+          return Err(TemplateError::OutsideEach(var.name().to_string()));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn uses(&self, pred: impl Fn(Var) -> bool + Copy) -> bool {
+    fn walk(parts: &[Part], pred: impl Fn(Var) -> bool + Copy) -> bool {
+      parts.iter().any(|part| match part {
+        Part::Text(_) => false,
+        Part::Var(var) => pred(*var),
+        Part::Each(parts) => walk(parts, pred),
+      })
+    }
+    walk(&self.parts, pred)
+  }
+
+  /// Whether rendering needs more than the PR numbers.
+  pub fn needs_details(&self) -> bool {
+    self.uses(|var| var != Var::Number && var != Var::Batch)
+  }
+
+  /// Whether rendering needs the git identities of approvers.
+  pub fn needs_identities(&self) -> bool {
+    self.uses(|var| var == Var::ApprovedBy || var == Var::ReviewedBy)
+  }
+
+  /// Render for `pr`, one of the PRs in `batch`, or for the whole batch if
+  /// `pr` is `None`.
+  pub fn render(&self, pr: Option<&TemplatePr>, batch: &[TemplatePr]) -> String {
+    let mut out = String::new();
+    render_parts(&self.parts, pr, batch, &mut out);
+    tidy(&out)
+  }
+}
+
+impl<'de> Deserialize<'de> for Template {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let s = String::deserialize(deserializer)?;
+    Self::parse(&s).map_err(de::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pr(number: i64, title: &str, body: &str) -> TemplatePr {
+    TemplatePr {
+      number,
+      title: title.to_string(),
+      body: body.to_string(),
+      author: "alice".to_string(),
+      approvers: vec!["bob".to_string()],
+      approver_identities: vec![GitIdentity {
+        name: "Bob".to_string(),
+        email: "bob@example.com".to_string(),
+      }],
+      labels: vec![],
+      url: format!("https://github.com/owner/repo/pull/{}", number),
+    }
+  }
+
+  #[test]
+  fn test_render() {
+    let batch = vec![pr(1, "Fix it", ""), pr(2, "Add it", "Details.\r\n")];
+    let template =
+      Template::parse("{{title}} (#{{number}})\n\n{{body}}\n\n{{ url }}\n\n{{reviewed_by}}\n")
+        .unwrap();
+    assert!(template.needs_identities());
+    assert_eq!(
+      template.render(Some(&batch[0]), &batch),
+      "Fix it (#1)\n\nhttps://github.com/owner/repo/pull/1\n\nReviewed-by: Bob <bob@example.com>"
+    );
+
+    let template =
+      Template::parse("Merge {{batch}}\n{{#each}}\n#{{number}}: {{title}}{{/each}}").unwrap();
+    template.check_batch().unwrap();
+    assert_eq!(
+      template.render(None, &batch),
+      "Merge #1, #2\n\n#1: Fix it\n#2: Add it"
+    );
+
+    let template = Template::parse("Merge #{{number}}").unwrap();
+    assert!(!template.needs_details());
+    assert_eq!(
+      template.check_batch().unwrap_err(),
+      TemplateError::OutsideEach("number".to_string())
+    );
+
+    assert_eq!(
+      Template::parse("{{titel}}").unwrap_err(),
+      TemplateError::Unknown("titel".to_string())
+    );
+    assert_eq!(
+      Template::parse("x {{title").unwrap_err(),
+      TemplateError::Unterminated(2)
+    );
+    assert_eq!(
+      Template::parse("{{#each}}{{#each}}").unwrap_err(),
+      TemplateError::NestedEach
+    );
+    assert_eq!(
+      Template::parse("{{#each}}").unwrap_err(),
+      TemplateError::UnclosedEach
+    );
+  }
+}
@@ -0,0 +1,93 @@
+use crate::config::repo::ApprovalConfig;
+use crate::github::types::{Review, ReviewState};
+
+use std::collections::BTreeMap;
+
+/// Outcome of evaluating a PR's reviews.
+#[derive(Debug, Default, PartialEq)]
+pub struct Approval {
+  pub approvers: Vec<String>,
+  pub changes_requested: Vec<String>,
+}
+
+impl Approval {
+  /// Evaluate `reviews`, given in chronological order, for the commit
+  /// `commit_hash`.  Only the latest approving, rejecting or dismissed
+  /// review by each author counts.
+  pub fn evaluate(config: &ApprovalConfig, reviews: &[Review], commit_hash: &str) -> Self {
+    let mut latest = BTreeMap::new();
+    for review in reviews {
+      if config.current_commit_only && review.commit_hash != commit_hash {
+        continue;
+      }
+      match review.state {
+        ReviewState::Approved | ReviewState::ChangesRequested | ReviewState::Dismissed => {
+          latest.insert(review.user.as_str(), review.state);
+        }
+        ReviewState::Commented | ReviewState::Pending => (),
+      }
+    }
+    let mut approval = Self::default();
+    for (user, state) in latest {
+      match state {
+        ReviewState::Approved => approval.approvers.push(user.to_string()),
+        ReviewState::ChangesRequested => approval.changes_requested.push(user.to_string()),
+        _ => (),
+      }
+    }
+    approval
+  }
+
+  pub fn approved(&self, config: &ApprovalConfig) -> bool {
+    self.changes_requested.is_empty() && self.approvers.len() >= config.count
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn review(user: &str, state: ReviewState, commit_hash: &str) -> Review {
+    Review {
+      user: user.to_string(),
+      state,
+      commit_hash: commit_hash.to_string(),
+    }
+  }
+
+  #[test]
+  fn test_evaluate() {
+    use ReviewState::*;
+    let config = ApprovalConfig::default();
+    let reviews = vec![
+      review("alice", ChangesRequested, "new"),
+      review("bob", Approved, "old"),
+      review("alice", Approved, "new"),
+      review("alice", Commented, "new"),
+      review("carol", Approved, "new"),
+      review("carol", Dismissed, "new"),
+    ];
+    let approval = Approval::evaluate(&config, &reviews, "new");
+    assert_eq!(
+      approval,
+      Approval {
+        approvers: vec!["alice".to_string()],
+        changes_requested: vec![],
+      }
+    );
+    assert!(approval.approved(&config));
+
+    let stale = ApprovalConfig {
+      current_commit_only: false,
+      ..ApprovalConfig::default()
+    };
+    let approval = Approval::evaluate(&stale, &reviews, "new");
+    assert_eq!(approval.approvers, vec!["alice", "bob"]);
+
+    let reviews = vec![
+      review("bob", Approved, "new"),
+      review("dave", ChangesRequested, "new"),
+    ];
+    assert!(!Approval::evaluate(&config, &reviews, "new").approved(&config));
+  }
+}
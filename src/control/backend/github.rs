@@ -0,0 +1,128 @@
+use super::{BackendError, MergeBackend};
+use crate::github::client::{Client, CommitRange, MergeResult};
+use crate::github::types::{GitIdentity, Repository};
+
+use async_trait::async_trait;
+
+/// Constructs merges through GitHub's git data and merges APIs, without any
+/// local state.
+pub struct GithubBackend {
+  client: Client,
+}
+
+impl GithubBackend {
+  pub fn new(client: Client) -> Self {
+    Self { client }
+  }
+}
+
+#[async_trait(?Send)]
+impl MergeBackend for GithubBackend {
+  async fn fetch(
+    &self,
+    repo: &Repository,
+    target_branch: &str,
+    _prs: &[i64],
+  ) -> Result<String, BackendError> {
+    self
+      .client
+      .branch_hash(repo, target_branch)
+      .await?
+      .ok_or_else(|| BackendError::MissingBranch(target_branch.to_string()))
+  }
+
+  async fn reset_branch(
+    &self,
+    repo: &Repository,
+    branch: &str,
+    commit_hash: &str,
+  ) -> Result<(), BackendError> {
+    Ok(
+      self
+        .client
+        .force_update_branch(repo, branch, commit_hash)
+        .await?,
+    )
+  }
+
+  async fn delete_branch(&self, repo: &Repository, branch: &str) -> Result<(), BackendError> {
+    Ok(self.client.delete_branch(repo, branch).await?)
+  }
+
+  async fn merge(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+    commit_message: &str,
+  ) -> Result<MergeResult, BackendError> {
+    match self.client.merge(repo, base, head, commit_message).await? {
+      // the API does not say which paths conflict, so find those changed on
+      // both sides
+      MergeResult::Conflict(_) => {
+        let ours = self.client.changed_paths(repo, head, base).await?;
+        let theirs = self.client.changed_paths(repo, base, head).await?;
+        Ok(MergeResult::Conflict(
+          theirs.into_iter().filter(|p| ours.contains(p)).collect(),
+        ))
+      }
+      result => Ok(result),
+    }
+  }
+
+  async fn commit_tree(
+    &self,
+    repo: &Repository,
+    commit_hash: &str,
+  ) -> Result<String, BackendError> {
+    Ok(self.client.commit_tree(repo, commit_hash).await?)
+  }
+
+  async fn create_commit(
+    &self,
+    repo: &Repository,
+    message: &str,
+    tree: &str,
+    parents: &[&str],
+    author: Option<&GitIdentity>,
+  ) -> Result<String, BackendError> {
+    Ok(
+      self
+        .client
+        .create_commit(repo, message, tree, parents, author)
+        .await?,
+    )
+  }
+
+  async fn compare_commits(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+  ) -> Result<CommitRange, BackendError> {
+    Ok(self.client.compare_commits(repo, base, head).await?)
+  }
+
+  async fn changed_paths(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+  ) -> Result<Vec<String>, BackendError> {
+    Ok(self.client.changed_paths(repo, base, head).await?)
+  }
+
+  async fn publish(
+    &self,
+    repo: &Repository,
+    branch: &str,
+    commit_hash: &str,
+  ) -> Result<(), BackendError> {
+    Ok(
+      self
+        .client
+        .force_update_branch(repo, branch, commit_hash)
+        .await?,
+    )
+  }
+}
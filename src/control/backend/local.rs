@@ -0,0 +1,554 @@
+use super::{BackendError, MergeBackend};
+use crate::github::client::{Client, CommitRange, MergeResult};
+use crate::github::types::{Commit, GitIdentity, Repository};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use actix_rt::blocking::BlockingError;
+use async_trait::async_trait;
+
+/// Environment variables for a git command.
+type Env = Vec<(&'static str, String)>;
+
+/// Constructs merges in a local bare mirror of each repository, and pushes
+/// only the staging branch.  Needs git 2.38 or later for
+/// `git merge-tree --write-tree`.
+pub struct LocalBackend {
+  mirrors: PathBuf,
+  remote_url: String,
+  /// Used to authenticate fetches and pushes with an installation token.
+  /// Remotes are accessed anonymously if unset.
+  client: Option<Client>,
+  committer: GitIdentity,
+}
+
+async fn run_git(dir: PathBuf, args: Vec<String>, env: Env) -> Result<Output, BackendError> {
+  actix_rt::blocking::run(move || {
+    Command::new("git")
+      .arg("-C")
+      .arg(&dir)
+      .arg("-c")
+      .arg("core.quotePath=false")
+      .args(&args)
+      .envs(env)
+      .env("GIT_TERMINAL_PROMPT", "0")
+      .output()
+  })
+  .await
+  .map_err(|e| match e {
+    BlockingError::Error(e) => BackendError::Io(e),
+    BlockingError::Canceled => BackendError::Cancelled,
+  })
+}
+
+/// Trimmed standard output of a successful git command.
+fn stdout(args: &[String], output: Output) -> Result<String, BackendError> {
+  if output.status.success() {
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+  } else {
+    Err(BackendError::Git(
+      args.join(" "),
+      String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    ))
+  }
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+  args.iter().map(|a| a.to_string()).collect()
+}
+
+/// Parse `git log --format=%H%x00%P%x00%an%x00%ae%x00%B%x1e`.
+fn parse_log(log: &str) -> Vec<Commit> {
+  log
+    .split('\x1e')
+    .map(|entry| entry.trim_start_matches('\n'))
+    .filter(|entry| !entry.is_empty())
+    .filter_map(|entry| {
+      let mut fields = entry.splitn(5, '\0');
+      Some(Commit {
+        sha: fields.next()?.to_string(),
+        parents: fields
+          .next()?
+          .split_whitespace()
+          .map(str::to_string)
+          .collect(),
+        author: GitIdentity {
+          name: fields.next()?.to_string(),
+          email: fields.next()?.to_string(),
+        },
+        message: fields.next()?.trim_end().to_string(),
+      })
+    })
+    .collect()
+}
+
+impl LocalBackend {
+  /// Mirrors are kept in `mirrors`, and fetched from and pushed to
+  /// `<remote_url>/<owner>/<repo>.git`.
+  pub fn new(
+    mirrors: PathBuf,
+    remote_url: String,
+    client: Option<Client>,
+    committer: GitIdentity,
+  ) -> Self {
+    Self {
+      mirrors,
+      remote_url,
+      client,
+      committer,
+    }
+  }
+
+  fn mirror(&self, repo: &Repository) -> PathBuf {
+    self
+      .mirrors
+      .join(&repo.owner)
+      .join(format!("{}.git", repo.repo))
+  }
+
+  fn remote(&self, repo: &Repository) -> String {
+    format!(
+      "{}/{}/{}.git",
+      self.remote_url.trim_end_matches('/'),
+      repo.owner,
+      repo.repo
+    )
+  }
+
+  /// Environment passing the installation token to git, without exposing it
+  /// on the command line.
+  async fn auth_env(&self, repo: &Repository) -> Result<Env, BackendError> {
+    Ok(match &self.client {
+      Some(client) => {
+        let token = client.installation_token(repo).await?;
+        let basic = base64::encode(format!("x-access-token:{}", token));
+        vec![
+          ("GIT_CONFIG_COUNT", "1".to_string()),
+          ("GIT_CONFIG_KEY_0", "http.extraHeader".to_string()),
+          (
+            "GIT_CONFIG_VALUE_0",
+            format!("Authorization: Basic {}", basic),
+          ),
+        ]
+      }
+      None => vec![],
+    })
+  }
+
+  fn committer_env(&self, author: Option<&GitIdentity>) -> Env {
+    let author = author.unwrap_or(&self.committer);
+    vec![
+      ("GIT_AUTHOR_NAME", author.name.clone()),
+      ("GIT_AUTHOR_EMAIL", author.email.clone()),
+      ("GIT_COMMITTER_NAME", self.committer.name.clone()),
+      ("GIT_COMMITTER_EMAIL", self.committer.email.clone()),
+    ]
+  }
+
+  async fn git_output(
+    &self,
+    repo: &Repository,
+    args: &[String],
+    env: Env,
+  ) -> Result<Output, BackendError> {
+    run_git(self.mirror(repo), args.to_vec(), env).await
+  }
+
+  async fn git(&self, repo: &Repository, args: Vec<String>) -> Result<String, BackendError> {
+    let output = self.git_output(repo, &args, vec![]).await?;
+    stdout(&args, output)
+  }
+
+  async fn git_env(
+    &self,
+    repo: &Repository,
+    args: Vec<String>,
+    env: Env,
+  ) -> Result<String, BackendError> {
+    let output = self.git_output(repo, &args, env).await?;
+    stdout(&args, output)
+  }
+
+  async fn init(&self, repo: &Repository) -> Result<(), BackendError> {
+    let mirror = self.mirror(repo);
+    if mirror.exists() {
+      return Ok(());
+    }
+    let parent = mirror.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(parent)?;
+    let args = vec![
+      "init".to_string(),
+      "--bare".to_string(),
+      mirror.to_string_lossy().into_owned(),
+    ];
+    let output = run_git(parent.to_path_buf(), args.clone(), vec![]).await?;
+    stdout(&args, output).map(drop)
+  }
+}
+
+#[async_trait(?Send)]
+impl MergeBackend for LocalBackend {
+  async fn fetch(
+    &self,
+    repo: &Repository,
+    target_branch: &str,
+    prs: &[i64],
+  ) -> Result<String, BackendError> {
+    self.init(repo).await?;
+    let target_ref = format!("refs/remotes/origin/{}", target_branch);
+    let mut fetch = args(&["fetch", "--no-tags", "--force"]);
+    fetch.push(self.remote(repo));
+    fetch.push(format!("+refs/heads/{}:{}", target_branch, target_ref));
+    for pr in prs {
+      fetch.push(format!("+refs/pull/{0}/head:refs/pull/{0}/head", pr));
+    }
+    let env = self.auth_env(repo).await?;
+    self.git_env(repo, fetch, env).await?;
+    self.git(repo, args(&["rev-parse", &target_ref])).await
+  }
+
+  async fn reset_branch(
+    &self,
+    repo: &Repository,
+    branch: &str,
+    commit_hash: &str,
+  ) -> Result<(), BackendError> {
+    let branch_ref = format!("refs/heads/{}", branch);
+    self
+      .git(repo, args(&["update-ref", &branch_ref, commit_hash]))
+      .await
+      .map(drop)
+  }
+
+  async fn delete_branch(&self, repo: &Repository, branch: &str) -> Result<(), BackendError> {
+    let branch_ref = format!("refs/heads/{}", branch);
+    self
+      .git(repo, args(&["update-ref", "-d", &branch_ref]))
+      .await
+      .map(drop)
+  }
+
+  async fn merge(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+    commit_message: &str,
+  ) -> Result<MergeResult, BackendError> {
+    let base_ref = format!("refs/heads/{}", base);
+    let base_hash = self.git(repo, args(&["rev-parse", &base_ref])).await?;
+
+    let is_ancestor = args(&["merge-base", "--is-ancestor", head, &base_hash]);
+    let output = self.git_output(repo, &is_ancestor, vec![]).await?;
+    match output.status.code() {
+      Some(0) => return Ok(MergeResult::NothingToMerge),
+      Some(1) => (),
+      _ => return stdout(&is_ancestor, output).map(|_| MergeResult::NothingToMerge),
+    }
+
+    let merge_tree = args(&[
+      "merge-tree",
+      "--write-tree",
+      "--name-only",
+      &base_hash,
+      head,
+    ]);
+    let output = self.git_output(repo, &merge_tree, vec![]).await?;
+    let conflict = output.status.code() == Some(1);
+    let output = if conflict {
+      String::from_utf8_lossy(&output.stdout).into_owned()
+    } else {
+      stdout(&merge_tree, output)?
+    };
+    // the tree is on the first line, followed by the conflicting paths and a
+    // blank line
+    let mut lines = output.lines();
+    let tree = lines.next().unwrap_or_default();
+    if conflict {
+      let mut paths: Vec<String> = vec![];
+      for path in lines.take_while(|line| !line.is_empty()) {
+        if !paths.iter().any(|p| p == path) {
+          paths.push(path.to_string());
+        }
+      }
+      return Ok(MergeResult::Conflict(paths));
+    }
+
+    let commit = self
+      .git_env(
+        repo,
+        args(&[
+          "commit-tree",
+          tree,
+          "-p",
+          &base_hash,
+          "-p",
+          head,
+          "-m",
+          commit_message,
+        ]),
+        self.committer_env(None),
+      )
+      .await?;
+    self
+      .git(repo, args(&["update-ref", &base_ref, &commit, &base_hash]))
+      .await?;
+    Ok(MergeResult::Merged(commit))
+  }
+
+  async fn commit_tree(
+    &self,
+    repo: &Repository,
+    commit_hash: &str,
+  ) -> Result<String, BackendError> {
+    let tree = format!("{}^{{tree}}", commit_hash);
+    self.git(repo, args(&["rev-parse", &tree])).await
+  }
+
+  async fn create_commit(
+    &self,
+    repo: &Repository,
+    message: &str,
+    tree: &str,
+    parents: &[&str],
+    author: Option<&GitIdentity>,
+  ) -> Result<String, BackendError> {
+    let mut commit_tree = args(&["commit-tree", tree, "-m", message]);
+    for parent in parents {
+      commit_tree.push("-p".to_string());
+      commit_tree.push(parent.to_string());
+    }
+    self
+      .git_env(repo, commit_tree, self.committer_env(author))
+      .await
+  }
+
+  async fn compare_commits(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+  ) -> Result<CommitRange, BackendError> {
+    let range = format!("{}..{}", base, head);
+    let log = self
+      .git(
+        repo,
+        args(&[
+          "log",
+          "--reverse",
+          "--topo-order",
+          "--format=%H%x00%P%x00%an%x00%ae%x00%B%x1e",
+          &range,
+        ]),
+      )
+      .await?;
+    let commits = parse_log(&log);
+    Ok(CommitRange {
+      total_commits: commits.len(),
+      commits,
+    })
+  }
+
+  async fn changed_paths(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+  ) -> Result<Vec<String>, BackendError> {
+    let range = format!("{}...{}", base, head);
+    let paths = self
+      .git(repo, args(&["diff", "--name-only", &range]))
+      .await?;
+    Ok(paths.lines().map(str::to_string).collect())
+  }
+
+  async fn publish(
+    &self,
+    repo: &Repository,
+    branch: &str,
+    commit_hash: &str,
+  ) -> Result<(), BackendError> {
+    let mut push = args(&["push", "--force"]);
+    push.push(self.remote(repo));
+    push.push(format!("{}:refs/heads/{}", commit_hash, branch));
+    let env = self.auth_env(repo).await?;
+    self.git_env(repo, push, env).await.map(drop)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::repo::{RepoConfig, Strategy};
+  use crate::control::construct::{BatchPr, Conflict, Construction};
+  use crate::github::client::{Credentials, TokenCache};
+
+  use std::sync::Arc;
+
+  use actix_web::client::Client as AwcClient;
+  use jsonwebtoken::EncodingKey;
+  use tokio::sync::Mutex;
+
+  fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+      .arg("-C")
+      .arg(dir)
+      .args(args)
+      .env("GIT_AUTHOR_NAME", "alice")
+      .env("GIT_AUTHOR_EMAIL", "alice@example.com")
+      .env("GIT_COMMITTER_NAME", "alice")
+      .env("GIT_COMMITTER_EMAIL", "alice@example.com")
+      .output()
+      .unwrap();
+    assert!(output.status.success(), "git {:?}: {:?}", args, output);
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+  }
+
+  /// Commit `content` to `file` on top of `parent` in `work`, and return the
+  /// new commit.
+  fn commit(work: &Path, parent: &str, file: &str, content: &str) -> String {
+    git(work, &["checkout", "-q", "--detach", parent]);
+    fs::write(work.join(file), content).unwrap();
+    git(work, &["add", file]);
+    git(work, &["commit", "-q", "-m", file]);
+    git(work, &["rev-parse", "HEAD"])
+  }
+
+  /// Push `hash` to the remote as the head of PR `number`.
+  fn open_pr(work: &Path, number: i64, hash: &str) -> BatchPr {
+    git(
+      work,
+      &[
+        "push",
+        "-q",
+        "origin",
+        &format!("{}:refs/pull/{}/head", hash, number),
+      ],
+    );
+    BatchPr {
+      number,
+      commit_hash: hash.to_string(),
+    }
+  }
+
+  #[actix_rt::test]
+  async fn test_construct() {
+    let root = std::env::temp_dir().join(format!("cherry-test-{}", uuid::Uuid::new_v4()));
+    let remote = root.join("remote/owner/repo.git");
+    let work = root.join("work");
+    fs::create_dir_all(&remote).unwrap();
+    fs::create_dir_all(&work).unwrap();
+    git(&remote, &["init", "-q", "--bare"]);
+    git(&work, &["init", "-q"]);
+    git(
+      &work,
+      &["remote", "add", "origin", remote.to_str().unwrap()],
+    );
+    fs::write(work.join("a.txt"), "base").unwrap();
+    git(&work, &["add", "a.txt"]);
+    git(&work, &["commit", "-q", "-m", "base"]);
+    let base = git(&work, &["rev-parse", "HEAD"]);
+    git(&work, &["push", "-q", "origin", "HEAD:refs/heads/main"]);
+
+    let pr1 = open_pr(&work, 1, &commit(&work, &base, "b.txt", "one"));
+    let pr2 = open_pr(&work, 2, &commit(&work, &base, "a.txt", "two"));
+    let pr3 = open_pr(&work, 3, &commit(&work, &base, "a.txt", "three"));
+    let c1 = commit(&work, &base, "c.txt", "four");
+    let pr4 = open_pr(&work, 4, &commit(&work, &c1, "d.txt", "four"));
+
+    let backend = LocalBackend::new(
+      root.join("mirrors"),
+      format!("file://{}", root.join("remote").display()),
+      None,
+      GitIdentity {
+        name: "cherry".to_string(),
+        email: "cherry@localhost".to_string(),
+      },
+    );
+    let client = Client::new(
+      Credentials {
+        app_id: "1".to_string(),
+        private_key: EncodingKey::from_secret(b""),
+      },
+      Arc::new(Mutex::new(TokenCache::new())),
+      AwcClient::new(),
+      "http://localhost".parse().unwrap(),
+    );
+    let repo = Repository {
+      id: 1,
+      owner: "owner".to_string(),
+      repo: "repo".to_string(),
+    };
+    let target_hash = backend.fetch(&repo, "main", &[1, 2, 3, 4]).await.unwrap();
+    assert_eq!(target_hash, base);
+    let construction = Construction {
+      backend: &backend,
+      client: &client,
+      config: &RepoConfig::default(),
+      repo: &repo,
+      target_hash: &target_hash,
+      tmp_branch: "staging-tmp-1",
+      staging_branch: "staging",
+    };
+
+    let constructed = construction
+      .run(Strategy::Merge, &[pr1.clone(), pr2.clone(), pr3])
+      .await
+      .unwrap();
+    assert_eq!(
+      constructed.conflicts,
+      vec![Conflict {
+        number: 3,
+        paths: vec!["a.txt".to_string()],
+        with_target: false,
+        with_prs: vec![2],
+      }]
+    );
+    assert_eq!(
+      git(&remote, &["rev-parse", "staging"]),
+      constructed.staging_hash
+    );
+    assert_eq!(git(&remote, &["show", "staging:a.txt"]), "two");
+    assert_eq!(git(&remote, &["show", "staging:b.txt"]), "one");
+    let mirror = root.join("mirrors/owner/repo.git");
+    assert_eq!(git(&mirror, &["branch", "--list", "staging-tmp-1"]), "");
+
+    let constructed = construction
+      .run(Strategy::CherryPick, &[pr1.clone(), pr4])
+      .await
+      .unwrap();
+    assert!(constructed.conflicts.is_empty());
+    let range = format!("{}..staging", base);
+    assert_eq!(
+      git(&remote, &["log", "--format=%s %an", &range]),
+      "d.txt alice\nc.txt alice\nb.txt alice"
+    );
+    assert_eq!(git(&remote, &["rev-list", "--merges", &range]), "");
+
+    // a PR conflicting with the target is told to rebase, even after others
+    let main = commit(&work, &base, "a.txt", "main");
+    git(
+      &work,
+      &["push", "-q", "origin", &format!("{}:refs/heads/main", main)],
+    );
+    let target_hash = backend.fetch(&repo, "main", &[1, 2]).await.unwrap();
+    let construction = Construction {
+      target_hash: &target_hash,
+      ..construction
+    };
+    let constructed = construction
+      .run(Strategy::Merge, &[pr1, pr2])
+      .await
+      .unwrap();
+    let conflict = &constructed.conflicts[0];
+    assert!(conflict.with_target);
+    assert_eq!(
+      conflict.report("main"),
+      "Merge failed: this PR conflicts with `main`.\n\nFiles involved:\n- `a.txt`\n\nPlease rebase onto `main`, resolve the conflicts and request the merge again."
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+  }
+}
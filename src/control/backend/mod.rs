@@ -0,0 +1,98 @@
+use crate::github::client::{ClientError, CommitRange, MergeResult};
+use crate::github::types::{GitIdentity, Repository};
+
+use std::io;
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+pub mod github;
+pub mod local;
+
+#[derive(Debug, Error)]
+pub enum BackendError {
+  #[error(transparent)]
+  Client(#[from] ClientError),
+  #[error("running git")]
+  Io(#[from] io::Error),
+  #[error("`git {0}` failed: {1}")]
+  Git(String, String),
+  #[error("git task was cancelled")]
+  Cancelled,
+  #[error("branch `{0}` does not exist")]
+  MissingBranch(String),
+}
+
+/// Git operations used to construct merges.  Branches other than the one
+/// passed to `publish` are private to the construction, and may only exist
+/// in the backend.
+#[async_trait(?Send)]
+pub trait MergeBackend {
+  /// Make the head of `target_branch` and of each PR available, and return
+  /// the head of `target_branch`.
+  async fn fetch(
+    &self,
+    repo: &Repository,
+    target_branch: &str,
+    prs: &[i64],
+  ) -> Result<String, BackendError>;
+
+  /// Point `branch` at `commit_hash`, even if this is not a fast-forward.
+  async fn reset_branch(
+    &self,
+    repo: &Repository,
+    branch: &str,
+    commit_hash: &str,
+  ) -> Result<(), BackendError>;
+
+  async fn delete_branch(&self, repo: &Repository, branch: &str) -> Result<(), BackendError>;
+
+  /// Merge `head`, a commit, into the branch `base`.  Conflicts list the
+  /// conflicting paths, or at least the paths changed on both sides.
+  async fn merge(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+    commit_message: &str,
+  ) -> Result<MergeResult, BackendError>;
+
+  /// Tree of the commit `commit_hash`.
+  async fn commit_tree(&self, repo: &Repository, commit_hash: &str)
+    -> Result<String, BackendError>;
+
+  /// Create a commit, not on any branch, and return its hash.
+  async fn create_commit(
+    &self,
+    repo: &Repository,
+    message: &str,
+    tree: &str,
+    parents: &[&str],
+    author: Option<&GitIdentity>,
+  ) -> Result<String, BackendError>;
+
+  /// Commits reachable from `head` but not from `base`, oldest first.  The
+  /// range may be truncated, as reported by its `total_commits`.
+  async fn compare_commits(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+  ) -> Result<CommitRange, BackendError>;
+
+  /// Paths changed by `head` since its merge base with `base`.
+  async fn changed_paths(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+  ) -> Result<Vec<String>, BackendError>;
+
+  /// Force-reset `branch` on GitHub to `commit_hash`, creating it if needed.
+  async fn publish(
+    &self,
+    repo: &Repository,
+    branch: &str,
+    commit_hash: &str,
+  ) -> Result<(), BackendError>;
+}
@@ -0,0 +1,300 @@
+use super::construct::{Backported, Construction};
+use super::{pr_row, Controller, ControllerError};
+use crate::control::command::{BackportOptions, MergeOptions};
+use crate::github::types::{PullRequest, Repository};
+
+use log::{error, info};
+use quaint::ast::{Comparable, Conjuctive, Delete, Insert, Select};
+use quaint::connector::{Queryable, TransactionCapable};
+
+/// Branch the backport of `pr` to `target` is pushed to.
+pub(super) fn branch(pr: i64, target: &str) -> String {
+  format!("cherry/backport-{}-{}", pr, target)
+}
+
+/// Title of the PR backporting the PR titled `title` to `target`.
+pub(super) fn title(target: &str, title: &str) -> String {
+  format!("[{}] {}", target, title)
+}
+
+/// Description of the PR backporting `pr` to `target`.
+pub(super) fn description(pr: i64, target: &str, requested_by: &str) -> String {
+  format!(
+    "Backport of #{} to `{}`.\n\nRequested by @{} in #{}.",
+    pr, target, requested_by, pr
+  )
+}
+
+/// Comment on a PR whose commits `shas` could not be cherry-picked onto
+/// `target` because of conflicts in `paths`, telling how to finish the
+/// backport on `branch`.
+pub(super) fn conflict_report(
+  target: &str,
+  branch: &str,
+  shas: &[String],
+  paths: &[String],
+) -> String {
+  let mut report = format!(
+    "Backport to `{}` failed: the commits of this PR conflict with `{}`.\n\n",
+    target, target
+  );
+  if !paths.is_empty() {
+    report.push_str("Files involved:\n");
+    for path in paths {
+      report.push_str(&format!("- `{}`\n", path));
+    }
+    report.push('\n');
+  }
+  report.push_str(&format!(
+    "The branch `{}` was pushed at `{}`.  To finish the backport, run:\n\n\
+     ```\n\
+     git fetch origin\n\
+     git checkout {}\n\
+     git cherry-pick -x {}\n\
+     ```\n\n\
+     then resolve the conflicts, push the branch and open a PR into `{}`.",
+    branch,
+    target,
+    branch,
+    shas.join(" "),
+    target
+  ));
+  report
+}
+
+impl<Q> Controller<Q>
+where
+  Q: Queryable + TransactionCapable + 'static,
+{
+  /// Record the backports of `pr` to the branches given in `options`, on
+  /// behalf of `requested_by`, and open them if the PR already landed.
+  pub async fn backport(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    options: BackportOptions,
+    requested_by: &str,
+  ) -> Result<(), ControllerError> {
+    let pr_info = self.client.pr_info(repo, pr).await?;
+    // PRs which landed rewritten are closed rather than merged
+    let merged = pr_info.merged
+      || !self
+        .db
+        .select(Select::from_table("landed_pr").so_that(pr_row(repo, pr)))
+        .await?
+        .is_empty();
+    let queued = !self
+      .db
+      .select(Select::from_table("pull_request").so_that(pr_row(repo, pr)))
+      .await?
+      .is_empty();
+    if !merged && !queued {
+      self
+        .comment(
+          repo,
+          pr,
+          "Error: only merged PRs, or PRs being merged, can be backported.",
+        )
+        .await?;
+      return Ok(());
+    }
+    for branch in &options.branches {
+      if self.client.branch_hash(repo, branch).await?.is_none() {
+        let message = format!("Error: branch `{}` does not exist.", branch);
+        self.comment(repo, pr, message.as_str()).await?;
+        return Ok(());
+      }
+    }
+    let tx = self.db.start_transaction().await?;
+    for branch in &options.branches {
+      tx.delete(
+        Delete::from_table("backport")
+          .so_that(pr_row(repo, pr).and("branch".equals(branch.as_str()))),
+      )
+      .await?;
+      tx.insert(
+        Insert::single_into("backport")
+          .value("owner", repo.owner.as_str())
+          .value("repo", repo.repo.as_str())
+          .value("number", pr)
+          .value("branch", branch.as_str())
+          .value("queue", i64::from(options.queue))
+          .value("requested_by", requested_by)
+          .value("timestamp", self.timestamp())
+          .build(),
+      )
+      .await?;
+    }
+    tx.commit().await?;
+    if merged {
+      return self.run_backports(repo, pr).await;
+    }
+    let branches: Vec<String> = options
+      .branches
+      .iter()
+      .map(|branch| format!("`{}`", branch))
+      .collect();
+    let message = format!(
+      "This PR will be backported to {} once it lands.",
+      branches.join(", ")
+    );
+    self.comment(repo, pr, message.as_str()).await
+  }
+
+  /// Open the backports recorded for `pr`, which landed, and forget them.
+  pub(super) async fn run_backports(
+    &self,
+    repo: &Repository,
+    pr: i64,
+  ) -> Result<(), ControllerError> {
+    let rows = self
+      .db
+      .select(Select::from_table("backport").so_that(pr_row(repo, pr)))
+      .await?;
+    if rows.is_empty() {
+      return Ok(());
+    }
+    let pr_info = self.client.pr_info(repo, pr).await?;
+    for row in rows {
+      let branch = row["branch"].to_string().unwrap_or_default();
+      let queue = row["queue"].as_i64() == Some(1);
+      let requested_by = row["requested_by"].to_string().unwrap_or_default();
+      if let Err(e) = self
+        .backport_to(repo, pr, &pr_info, &branch, queue, &requested_by)
+        .await
+      {
+        error!("backporting {} #{} to `{}`: {}", repo, pr, branch, e);
+        let message = format!("Backport to `{}` failed: {}.", branch, e);
+        self.comment(repo, pr, message.as_str()).await?;
+      }
+      self
+        .db
+        .delete(
+          Delete::from_table("backport")
+            .so_that(pr_row(repo, pr).and("branch".equals(branch.as_str()))),
+        )
+        .await?;
+    }
+    Ok(())
+  }
+
+  /// Cherry-pick the commits of `pr` onto `target` on a branch of their own,
+  /// and open a PR with them, or report how to finish the job on conflict.
+  pub(super) async fn backport_to(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    pr_info: &PullRequest,
+    target: &str,
+    queue: bool,
+    requested_by: &str,
+  ) -> Result<(), ControllerError> {
+    let branch = branch(pr, target);
+    if self.client.branch_hash(repo, &branch).await?.is_some() {
+      let message = format!(
+        "Error: branch `{}` already exists, so this PR was not backported to `{}`.",
+        branch, target
+      );
+      return self.comment(repo, pr, message.as_str()).await;
+    }
+    let range = self.client.pr_commits(repo, pr).await?;
+    if range.commits.len() < range.total_commits {
+      let message = format!(
+        "Error: this PR has {} commits, but only {} can be listed, so it was not backported to `{}`.",
+        range.total_commits,
+        range.commits.len(),
+        target
+      );
+      return self.comment(repo, pr, message.as_str()).await;
+    }
+    let commits = range.commits;
+    let intent = || {
+      format!(
+        "open a PR backporting #{} to `{}` from `{}`",
+        pr, target, branch
+      )
+    };
+    if !self.should_write(repo, Some(pr), intent).await? {
+      return Ok(());
+    }
+    let shas: Vec<String> = commits.iter().map(|commit| commit.sha.clone()).collect();
+    let target_hash = self.backend.fetch(repo, target, &[pr]).await?;
+    let config = self.config(repo).await?;
+    let tmp_branch = format!("{}-tmp", branch);
+    let backported = Construction {
+      backend: self.backend.as_ref(),
+      client: &self.client,
+      config: &config,
+      repo,
+      target_hash: &target_hash,
+      tmp_branch: &tmp_branch,
+      staging_branch: &branch,
+    }
+    .backport(commits)
+    .await?;
+    match backported {
+      Backported::Picked(_) => {
+        let backport_pr = self
+          .client
+          .create_pr(
+            repo,
+            &branch,
+            target,
+            &title(target, &pr_info.title),
+            &description(pr, target, requested_by),
+          )
+          .await?;
+        info!(
+          "opened {} #{} backporting #{} to `{}`",
+          repo, backport_pr, pr, target
+        );
+        let message = format!(
+          "Opened #{} to backport this PR to `{}`.",
+          backport_pr, target
+        );
+        self.comment(repo, pr, message.as_str()).await?;
+        if queue {
+          self
+            .request(repo, backport_pr, MergeOptions::default())
+            .await?;
+        }
+      }
+      Backported::Conflict(paths) => {
+        let message = conflict_report(target, &branch, &shas, &paths);
+        self.comment(repo, pr, message.as_str()).await?;
+      }
+      Backported::MergeCommit(sha) => {
+        let message = format!(
+          "Backport to `{}` failed: commit {} is a merge commit, which cannot be cherry-picked.",
+          target, sha
+        );
+        self.comment(repo, pr, message.as_str()).await?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_conflict_report() {
+    let branch = branch(12, "release-1");
+    assert_eq!(branch, "cherry/backport-12-release-1");
+    assert_eq!(
+      conflict_report(
+        "release-1",
+        &branch,
+        &["a1".to_string(), "b2".to_string()],
+        &["src/lib.rs".to_string()]
+      ),
+      "Backport to `release-1` failed: the commits of this PR conflict with `release-1`.\n\n\
+       Files involved:\n- `src/lib.rs`\n\n\
+       The branch `cherry/backport-12-release-1` was pushed at `release-1`.  To finish the backport, run:\n\n\
+       ```\ngit fetch origin\ngit checkout cherry/backport-12-release-1\ngit cherry-pick -x a1 b2\n```\n\n\
+       then resolve the conflicts, push the branch and open a PR into `release-1`."
+    );
+  }
+}
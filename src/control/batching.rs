@@ -0,0 +1,135 @@
+use crate::config::repo::BatchingConfig;
+
+use chrono::{DateTime, Duration, Utc};
+
+/// A QUEUED PR which may be batched.
+#[derive(Debug, Clone)]
+pub(super) struct Candidate {
+  pub(super) number: i64,
+  /// When the PR was queued, in epoch seconds.
+  pub(super) timestamp: i64,
+  /// Whether the PR must be merged in a batch of its own.
+  pub(super) alone: bool,
+}
+
+/// Maximum batch size, given the outcomes of recent batches, most recent
+/// first, with `true` for success.
+pub(super) fn size_limit(config: &BatchingConfig, recent: &[bool]) -> Option<usize> {
+  let max_size = config.max_size?;
+  if !config.adaptive || recent.is_empty() {
+    return Some(max_size);
+  }
+  let recent = &recent[..recent.len().min(config.adaptive_window)];
+  let successes = recent.iter().filter(|&&success| success).count();
+  // scale by the success rate, rounding to the nearest size
+  let limit = (max_size * successes * 2 + recent.len()) / (recent.len() * 2);
+  Some(limit.max(1))
+}
+
+/// Choose the next batch among `candidates`, which are ordered oldest first,
+/// or `None` if the policy says to wait for more PRs.
+pub(super) fn select(
+  config: &BatchingConfig,
+  candidates: &[Candidate],
+  limit: Option<usize>,
+  now: DateTime<Utc>,
+) -> Option<Vec<i64>> {
+  let oldest = candidates.first()?;
+  let newest = candidates.iter().map(|c| c.timestamp).max()?;
+  let age = |timestamp: i64| Duration::seconds(now.timestamp() - timestamp);
+
+  if oldest.alone {
+    return Some(vec![oldest.number]);
+  }
+  let limit = limit.unwrap_or(usize::MAX);
+  let batch: Vec<i64> = candidates
+    .iter()
+    .filter(|c| !c.alone)
+    .take(limit)
+    .map(|c| c.number)
+    .collect();
+
+  let full = batch.len() >= config.fill.unwrap_or(usize::MAX).min(limit);
+  let window =
+    config.fill.is_none() && age(oldest.timestamp) >= config.wait && age(newest) >= config.debounce;
+  if full || window || age(oldest.timestamp) >= config.max_wait {
+    Some(batch)
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::TimeZone;
+
+  fn candidate(number: i64, timestamp: i64, alone: bool) -> Candidate {
+    Candidate {
+      number,
+      timestamp,
+      alone,
+    }
+  }
+
+  #[test]
+  fn test_select() {
+    let now = Utc.timestamp(100_000, 0);
+    let minute = 60;
+    let t = now.timestamp();
+    let config = BatchingConfig {
+      debounce: Duration::minutes(2),
+      ..BatchingConfig::default()
+    };
+    let candidates = vec![
+      candidate(1, t - 11 * minute, false),
+      candidate(2, t - 5 * minute, true),
+      candidate(3, t - 4 * minute, false),
+    ];
+    assert_eq!(select(&config, &candidates, None, now), Some(vec![1, 3]));
+    assert_eq!(select(&config, &candidates, Some(1), now), Some(vec![1]));
+
+    // a PR queued within the debounce window delays the batch, unless the
+    // batch is full
+    let mut debounced = candidates.clone();
+    debounced.push(candidate(4, t - minute, false));
+    assert_eq!(select(&config, &debounced, None, now), None);
+    assert_eq!(
+      select(&config, &debounced, Some(3), now),
+      Some(vec![1, 3, 4])
+    );
+    // the oldest PR waits at least `wait`
+    assert_eq!(select(&config, &candidates[2..], None, now), None);
+    // a PR merged alone does not wait
+    assert_eq!(select(&config, &candidates[1..], None, now), Some(vec![2]));
+
+    let fill = BatchingConfig {
+      fill: Some(2),
+      max_wait: Duration::minutes(30),
+      ..BatchingConfig::default()
+    };
+    let young = vec![candidate(1, t - 20 * minute, false)];
+    assert_eq!(select(&fill, &young, None, now), None);
+    let old = vec![candidate(1, t - 31 * minute, false)];
+    assert_eq!(select(&fill, &old, None, now), Some(vec![1]));
+    assert_eq!(select(&fill, &candidates, None, now), Some(vec![1, 3]));
+  }
+
+  #[test]
+  fn test_size_limit() {
+    let config = BatchingConfig {
+      max_size: Some(8),
+      adaptive: true,
+      adaptive_window: 4,
+      ..BatchingConfig::default()
+    };
+    assert_eq!(size_limit(&config, &[]), Some(8));
+    assert_eq!(size_limit(&config, &[true, false, true, true]), Some(6));
+    assert_eq!(
+      size_limit(&config, &[false, false, false, false, true]),
+      Some(1)
+    );
+    assert_eq!(size_limit(&BatchingConfig::default(), &[false]), None);
+  }
+}
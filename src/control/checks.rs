@@ -0,0 +1,127 @@
+use crate::github::types::StatusState;
+
+/// Result of the checks on a staging commit.
+#[derive(Debug, PartialEq)]
+pub(super) enum Outcome {
+  Pending,
+  Success,
+  /// Names of the failed checks.
+  Failure(Vec<String>),
+}
+
+/// Evaluate the `checks` reported on a commit against `required`.  If
+/// `required` is empty, every reported check must succeed, and at least one
+/// must be reported.  Any failure is final, even while other checks are
+/// pending.
+pub(super) fn evaluate(required: &[String], checks: &[(String, StatusState)]) -> Outcome {
+  let relevant: Vec<(&str, Option<StatusState>)> = if required.is_empty() {
+    checks
+      .iter()
+      .map(|(name, state)| (name.as_str(), Some(*state)))
+      .collect()
+  } else {
+    required
+      .iter()
+      .map(|name| {
+        let state = checks.iter().find(|(n, _)| n == name).map(|(_, s)| *s);
+        (name.as_str(), state)
+      })
+      .collect()
+  };
+  let failed: Vec<String> = relevant
+    .iter()
+    .filter(|(_, state)| matches!(state, Some(StatusState::Error | StatusState::Failure)))
+    .map(|(name, _)| name.to_string())
+    .collect();
+  if !failed.is_empty() {
+    Outcome::Failure(failed)
+  } else if relevant.is_empty()
+    || relevant
+      .iter()
+      .any(|(_, state)| *state != Some(StatusState::Success))
+  {
+    Outcome::Pending
+  } else {
+    Outcome::Success
+  }
+}
+
+/// Result of the checks on several commits which must all pass: failed if
+/// any failed, listing each failed check once.
+pub(super) fn combine(outcomes: Vec<Outcome>) -> Outcome {
+  let mut failed: Vec<String> = vec![];
+  let mut pending = outcomes.is_empty();
+  for outcome in outcomes {
+    match outcome {
+      Outcome::Pending => pending = true,
+      Outcome::Success => (),
+      Outcome::Failure(names) => {
+        for name in names {
+          if !failed.contains(&name) {
+            failed.push(name);
+          }
+        }
+      }
+    }
+  }
+  if !failed.is_empty() {
+    Outcome::Failure(failed)
+  } else if pending {
+    Outcome::Pending
+  } else {
+    Outcome::Success
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_evaluate() {
+    let checks = vec![
+      ("ci/build".to_string(), StatusState::Success),
+      ("ci/lint".to_string(), StatusState::Pending),
+      ("ci/test".to_string(), StatusState::Failure),
+    ];
+    let required = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(
+      evaluate(&required(&["ci/build"]), &checks),
+      Outcome::Success
+    );
+    assert_eq!(
+      evaluate(&required(&["ci/build", "ci/lint"]), &checks),
+      Outcome::Pending
+    );
+    assert_eq!(
+      evaluate(&required(&["ci/build", "ci/docs"]), &checks),
+      Outcome::Pending
+    );
+    assert_eq!(
+      evaluate(&[], &checks),
+      Outcome::Failure(vec!["ci/test".to_string()])
+    );
+    assert_eq!(evaluate(&[], &checks[..2]), Outcome::Pending);
+    assert_eq!(evaluate(&[], &checks[..1]), Outcome::Success);
+    assert_eq!(evaluate(&[], &[]), Outcome::Pending);
+  }
+
+  #[test]
+  fn test_combine() {
+    let failure = |name: &str| Outcome::Failure(vec![name.to_string()]);
+    assert_eq!(
+      combine(vec![Outcome::Success, Outcome::Success]),
+      Outcome::Success
+    );
+    assert_eq!(
+      combine(vec![Outcome::Success, Outcome::Pending]),
+      Outcome::Pending
+    );
+    assert_eq!(
+      combine(vec![failure("ci/a"), Outcome::Pending, failure("ci/a")]),
+      failure("ci/a")
+    );
+    assert_eq!(combine(vec![]), Outcome::Pending);
+  }
+}
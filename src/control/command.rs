@@ -1,4 +1,7 @@
+use crate::config::repo::Strategy;
+
 use std::fmt;
+use std::str::FromStr;
 
 use async_trait::async_trait;
 use thiserror::Error;
@@ -7,6 +10,159 @@ use thiserror::Error;
 pub enum ParseError {
   #[error("unknown command: {0}")]
   UnknownCommand(String),
+  #[error("unknown merge strategy: {0}")]
+  UnknownStrategy(String),
+  #[error("invalid merge option: {0}")]
+  InvalidOption(String),
+  #[error("`cherry backport` needs at least one branch")]
+  MissingBranch,
+}
+
+/// A PR in another repository, as `owner/repo#N`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkedPr {
+  pub owner: String,
+  pub repo: String,
+  pub number: i64,
+}
+
+impl fmt::Display for LinkedPr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}#{}", self.owner, self.repo, self.number)
+  }
+}
+
+impl FromStr for LinkedPr {
+  type Err = ParseError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid = || ParseError::InvalidOption(format!("with={}", s));
+    let (repo, number) = s.split_once('#').ok_or_else(invalid)?;
+    let (owner, repo) = repo.split_once('/').ok_or_else(invalid)?;
+    if owner.is_empty() || repo.is_empty() || repo.contains('/') {
+      return Err(invalid());
+    }
+    Ok(Self {
+      owner: owner.to_string(),
+      repo: repo.to_string(),
+      number: number.parse().map_err(|_| invalid())?,
+    })
+  }
+}
+
+/// Highest priority a PR may be given with `p=`; reverts queued by cherry
+/// come above it.
+pub const MAX_PRIORITY: i64 = 1000;
+
+/// Parse a priority given as `p=N` or `treeclosed=N`.
+fn priority(word: &str, value: &str) -> Result<i64, ParseError> {
+  match value.parse() {
+    Ok(priority) if (0..=MAX_PRIORITY).contains(&priority) => Ok(priority),
+    _ => Err(ParseError::InvalidOption(word.to_string())),
+  }
+}
+
+/// Options of `cherry merge [<strategy>] [rollup=never|maybe] [p=N]
+/// [with=owner/repo#N,...]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergeOptions {
+  /// Strategy instead of the repository's default.
+  pub strategy: Option<Strategy>,
+  /// Merge the PR in a batch of its own.
+  pub alone: bool,
+  /// PRs in other repositories which land together with this one.
+  pub with: Vec<LinkedPr>,
+  /// Queue the PR ahead of those with a lower priority, and past a closed
+  /// tree if high enough.
+  pub priority: i64,
+}
+
+impl MergeOptions {
+  fn parse<'a>(words: impl Iterator<Item = &'a str>) -> Result<Self, ParseError> {
+    let mut options = Self::default();
+    for word in words.filter(|w| !w.is_empty()) {
+      match word {
+        "rollup=never" => options.alone = true,
+        "rollup=maybe" => options.alone = false,
+        _ if word.starts_with("p=") => options.priority = priority(word, &word["p=".len()..])?,
+        _ if word.starts_with("with=") => {
+          for linked in word["with=".len()..].split(',') {
+            let linked: LinkedPr = linked.parse()?;
+            if !options.with.contains(&linked) {
+              options.with.push(linked);
+            }
+          }
+        }
+        _ if word.contains('=') => return Err(ParseError::InvalidOption(word.to_string())),
+        _ => {
+          options.strategy = Some(
+            word
+              .parse()
+              .map_err(|_| ParseError::UnknownStrategy(word.to_string()))?,
+          )
+        }
+      }
+    }
+    Ok(options)
+  }
+}
+
+/// Options of `cherry revert [<attempt id>] [each] [queue]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RevertOptions {
+  /// Revert the whole merge attempt instead of the PR commented on.
+  pub attempt: Option<String>,
+  /// Revert each PR of the attempt in a commit of its own.
+  pub each: bool,
+  /// Request the merge of the revert PR with top priority.
+  pub queue: bool,
+}
+
+impl RevertOptions {
+  fn parse<'a>(words: impl Iterator<Item = &'a str>) -> Result<Self, ParseError> {
+    let mut options = Self::default();
+    for word in words.filter(|w| !w.is_empty()) {
+      match word {
+        "each" => options.each = true,
+        "queue" => options.queue = true,
+        _ if word.contains('=') || options.attempt.is_some() => {
+          return Err(ParseError::InvalidOption(word.to_string()))
+        }
+        _ => options.attempt = Some(word.to_string()),
+      }
+    }
+    Ok(options)
+  }
+}
+
+/// Options of `cherry backport <branch>... [queue]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BackportOptions {
+  /// Release branches to cherry-pick the PR onto.
+  pub branches: Vec<String>,
+  /// Request the merge of each backport PR.
+  pub queue: bool,
+}
+
+impl BackportOptions {
+  fn parse<'a>(words: impl Iterator<Item = &'a str>) -> Result<Self, ParseError> {
+    let mut options = Self::default();
+    for word in words.filter(|w| !w.is_empty()) {
+      match word {
+        "queue" => options.queue = true,
+        _ if word.contains('=') => return Err(ParseError::InvalidOption(word.to_string())),
+        _ => {
+          if !options.branches.iter().any(|b| b == word) {
+            options.branches.push(word.to_string());
+          }
+        }
+      }
+    }
+    if options.branches.is_empty() {
+      return Err(ParseError::MissingBranch);
+    }
+    Ok(options)
+  }
 }
 
 #[async_trait(?Send)]
@@ -14,24 +170,79 @@ pub trait Context {
   type Error;
 
   async fn reply(&mut self, message: String) -> Result<(), Self::Error>;
+
+  /// Whether the commenter may run `command`.
+  async fn permitted(&mut self, command: &Command) -> Result<bool, Self::Error>;
+
+  /// Request a merge with `options`.
+  async fn merge(&mut self, options: MergeOptions) -> Result<(), Self::Error>;
+
+  /// Reply with the PR's state in the queue and its split history.
+  async fn status(&mut self) -> Result<(), Self::Error>;
+
+  /// Reply with a comparison of what cherry would have merged in shadow
+  /// mode with what was merged by hand.
+  async fn report(&mut self) -> Result<(), Self::Error>;
+
+  /// Open a PR reverting the PR, or a merge attempt, after it landed.
+  async fn revert(&mut self, options: RevertOptions) -> Result<(), Self::Error>;
+
+  /// Cherry-pick the PR onto release branches, once it landed.
+  async fn backport(&mut self, options: BackportOptions) -> Result<(), Self::Error>;
+
+  /// Close the tree to PRs with a priority below `priority`, or open it.
+  async fn tree(&mut self, priority: Option<i64>) -> Result<(), Self::Error>;
+
+  /// Let the queue into `target`, or the PR's base branch, go on despite
+  /// failing checks there.
+  async fn override_base(&mut self, target: Option<String>) -> Result<(), Self::Error>;
 }
 
 #[derive(Debug)]
 pub enum Command {
   Ping,
-  Merge,
+  Merge(MergeOptions),
+  Status,
+  Report,
+  Revert(RevertOptions),
+  Backport(BackportOptions),
+  /// Only batch PRs with at least this priority.
+  TreeClosed(i64),
+  TreeOpen,
+  /// Target branch whose failing checks are overridden.
+  OverrideBase(Option<String>),
 }
 
 impl fmt::Display for Command {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
       Self::Ping => write!(f, "ping"),
-      Self::Merge => write!(f, "merge"),
+      Self::Merge(_) => write!(f, "merge"),
+      Self::Status => write!(f, "status"),
+      Self::Report => write!(f, "report"),
+      Self::Revert(_) => write!(f, "revert"),
+      Self::Backport(_) => write!(f, "backport"),
+      Self::TreeClosed(_) => write!(f, "treeclosed"),
+      Self::TreeOpen => write!(f, "treeopen"),
+      Self::OverrideBase(_) => write!(f, "overridebase"),
     }
   }
 }
 
 impl Command {
+  /// Names of all commands, as used in configuration.
+  pub const NAMES: &'static [&'static str] = &[
+    "ping",
+    "merge",
+    "status",
+    "report",
+    "revert",
+    "backport",
+    "treeclosed",
+    "treeopen",
+    "overridebase",
+  ];
+
   pub fn parse_comment(s: &str) -> Result<Vec<Self>, ParseError> {
     s.lines()
       .filter_map(|l| {
@@ -41,7 +252,19 @@ impl Command {
         }
         Some(match words.next() {
           Some("ping") => Ok(Self::Ping),
-          Some("merge") | Some("r+") => Ok(Self::Merge),
+          Some("status") => Ok(Self::Status),
+          Some("report") => Ok(Self::Report),
+          Some("merge") | Some("r+") => MergeOptions::parse(words).map(Self::Merge),
+          Some("revert") => RevertOptions::parse(words).map(Self::Revert),
+          Some("backport") => BackportOptions::parse(words).map(Self::Backport),
+          Some("treeopen") => Ok(Self::TreeOpen),
+          Some("overridebase") => match (words.next(), words.next()) {
+            (target, None) => Ok(Self::OverrideBase(target.map(str::to_string))),
+            (_, Some(word)) => Err(ParseError::InvalidOption(word.to_string())),
+          },
+          Some(word) if word.starts_with("treeclosed=") => {
+            priority(word, &word["treeclosed=".len()..]).map(Self::TreeClosed)
+          }
           other => Err(ParseError::UnknownCommand(
             other.unwrap_or("[none]").to_string(),
           )),
@@ -54,9 +277,102 @@ impl Command {
   where
     C: Context,
   {
+    if !context.permitted(self).await? {
+      return context
+        .reply(format!(
+          "Permission denied: you may not run `cherry {}` in this repository.",
+          self
+        ))
+        .await;
+    }
     match self {
       Self::Ping => context.reply("pong!".to_string()).await,
-      Self::Merge => unimplemented!(),
+      Self::Merge(options) => context.merge(options.clone()).await,
+      Self::Status => context.status().await,
+      Self::Report => context.report().await,
+      Self::Revert(options) => context.revert(options.clone()).await,
+      Self::Backport(options) => context.backport(options.clone()).await,
+      Self::TreeClosed(priority) => context.tree(Some(*priority)).await,
+      Self::TreeOpen => context.tree(None).await,
+      Self::OverrideBase(target) => context.override_base(target.clone()).await,
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_merge_options() {
+    let options = MergeOptions::parse("squash with=a/b#1,c/d.e#23 with=a/b#1".split(' ')).unwrap();
+    assert_eq!(options.strategy, Some(Strategy::Squash));
+    assert_eq!(
+      options.with,
+      vec![
+        LinkedPr {
+          owner: "a".to_string(),
+          repo: "b".to_string(),
+          number: 1,
+        },
+        LinkedPr {
+          owner: "c".to_string(),
+          repo: "d.e".to_string(),
+          number: 23,
+        },
+      ]
+    );
+    assert_eq!(
+      MergeOptions::parse(std::iter::once("p=5"))
+        .unwrap()
+        .priority,
+      5
+    );
+    for invalid in &[
+      "p=-1",
+      "p=1001",
+      "with=a/b",
+      "with=b#1",
+      "with=a/b/c#1",
+      "with=a/b#x",
+      "with=",
+    ] {
+      assert!(MergeOptions::parse(std::iter::once(*invalid)).is_err());
+    }
+  }
+
+  #[test]
+  fn test_revert_options() {
+    assert_eq!(
+      RevertOptions::parse("".split(' ')).unwrap(),
+      RevertOptions::default()
+    );
+    let options = RevertOptions::parse("a1b2 each queue".split(' ')).unwrap();
+    assert_eq!(options.attempt.as_deref(), Some("a1b2"));
+    assert!(options.each && options.queue);
+    assert!(RevertOptions::parse("a1 b2".split(' ')).is_err());
+    assert!(RevertOptions::parse("queue=yes".split(' ')).is_err());
+  }
+
+  #[test]
+  fn test_backport_options() {
+    let options = BackportOptions::parse("release-1 queue release/2 release-1".split(' ')).unwrap();
+    assert_eq!(options.branches, vec!["release-1", "release/2"]);
+    assert!(options.queue);
+    assert!(BackportOptions::parse("queue".split(' ')).is_err());
+    assert!(BackportOptions::parse("".split(' ')).is_err());
+  }
+
+  #[test]
+  fn test_parse_comment() {
+    let commands = Command::parse_comment(
+      "cherry treeclosed=10\ncherry overridebase\ncherry overridebase release-1",
+    )
+    .unwrap();
+    assert!(matches!(commands[0], Command::TreeClosed(10)));
+    assert!(matches!(commands[1], Command::OverrideBase(None)));
+    assert!(matches!(&commands[2], Command::OverrideBase(Some(target)) if target == "release-1"));
+    assert!(Command::parse_comment("cherry overridebase a b").is_err());
+    assert!(Command::parse_comment("cherry treeclosed=1001").is_err());
+  }
+}
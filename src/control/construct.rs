@@ -0,0 +1,837 @@
+use super::approval::Approval;
+use super::backend::{BackendError, MergeBackend};
+use crate::config::repo::{RepoConfig, Strategy};
+use crate::config::template::{Template, TemplatePr};
+use crate::github::client::{Client, ClientError, MergeResult};
+use crate::github::types::{Commit, CommitAuthor, GitIdentity, PullRequest, Repository};
+use log::error;
+
+/// A PR in a batch, at the commit which was approved.
+#[derive(Debug, Clone)]
+pub(super) struct BatchPr {
+  pub(super) number: i64,
+  pub(super) commit_hash: String,
+}
+
+/// Result of constructing a batch on the staging branch.
+#[derive(Debug)]
+pub(super) struct Constructed {
+  /// Head of the staging branch.
+  pub(super) staging_hash: String,
+  /// PRs which were skipped because they conflict.
+  pub(super) conflicts: Vec<Conflict>,
+  /// PRs which can never be constructed with the strategy, and why.
+  pub(super) rejected: Vec<(i64, String)>,
+  /// Commits applying each merged PR on the staging branch, unless the
+  /// strategy lands the batch as a single commit.
+  pub(super) ranges: Vec<PrRange>,
+}
+
+/// The commits applying one PR on the staging branch: those reachable from
+/// `to` but not from `from`.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct PrRange {
+  pub(super) number: i64,
+  pub(super) from: String,
+  pub(super) to: String,
+}
+
+/// Batch merged into the temporary branch.
+struct Merged {
+  /// Head of the temporary branch.
+  head: String,
+  /// PRs which were merged, excluding those already contained in the target.
+  merged: Vec<i64>,
+  conflicts: Vec<Conflict>,
+  ranges: Vec<PrRange>,
+}
+
+/// A PR skipped because it conflicts, and what it conflicts with.
+#[derive(Debug, PartialEq)]
+pub(super) struct Conflict {
+  pub(super) number: i64,
+  /// Paths involved in the conflict, if known.
+  pub(super) paths: Vec<String>,
+  /// Whether the PR conflicts with the target branch on its own, in which
+  /// case it needs a rebase.
+  pub(super) with_target: bool,
+  /// PRs earlier in the batch which the PR conflicts with.
+  pub(super) with_prs: Vec<i64>,
+}
+
+impl Conflict {
+  /// Comment reporting the conflict on the PR.
+  pub(super) fn report(&self, target_branch: &str) -> String {
+    let mut report = if self.with_target {
+      format!(
+        "Merge failed: this PR conflicts with `{}`.\n\n",
+        target_branch
+      )
+    } else {
+      let prs: Vec<String> = self.with_prs.iter().map(|pr| format!("#{}", pr)).collect();
+      format!(
+        "Merge conflict: this PR conflicts with {}, merged before it in its batch.\n\n",
+        prs.join(", ")
+      )
+    };
+    if !self.paths.is_empty() {
+      report.push_str("Files involved:\n");
+      for path in &self.paths {
+        report.push_str(&format!("- `{}`\n", path));
+      }
+      report.push('\n');
+    }
+    if self.with_target {
+      report.push_str(&format!(
+        "Please rebase onto `{}`, resolve the conflicts and request the merge again.",
+        target_branch
+      ));
+    } else {
+      report.push_str(&format!(
+        "It merges cleanly into `{}` on its own, so no rebase is needed: it will be retried in a later batch.",
+        target_branch
+      ));
+    }
+    report
+  }
+}
+
+/// Construction of one batch on top of `target_hash`.  Git operations go
+/// through `backend`; `client` is only used to read PR metadata.
+pub(super) struct Construction<'a> {
+  pub(super) backend: &'a dyn MergeBackend,
+  pub(super) client: &'a Client,
+  pub(super) config: &'a RepoConfig,
+  pub(super) repo: &'a Repository,
+  pub(super) target_hash: &'a str,
+  pub(super) tmp_branch: &'a str,
+  pub(super) staging_branch: &'a str,
+}
+
+impl Construction<'_> {
+  pub(super) async fn run(
+    &self,
+    strategy: Strategy,
+    prs: &[BatchPr],
+  ) -> Result<Constructed, BackendError> {
+    let result = match strategy {
+      Strategy::Merge => self.merge(prs).await,
+      Strategy::Octopus => self.octopus(prs).await,
+      Strategy::Squash => self.squash(prs).await,
+      Strategy::BatchSquash => self.batch_squash(prs).await,
+      Strategy::CherryPick => self.cherry_pick(prs).await,
+    };
+    self.clean_up_on_error(result).await
+  }
+
+  /// Branch on which a conflicting PR is merged alone by `diagnose`.
+  fn probe_branch(&self) -> String {
+    format!("{}-probe", self.tmp_branch)
+  }
+
+  /// Point the private `branch` at `target_hash`.  It is reset rather than
+  /// created, in case a construction which was interrupted left it behind.
+  async fn start_branch(&self, branch: &str) -> Result<(), BackendError> {
+    self
+      .backend
+      .reset_branch(self.repo, branch, self.target_hash)
+      .await
+  }
+
+  /// Delete the private branches if `result` is an error, since the failed
+  /// construction would otherwise leave them behind.
+  async fn clean_up_on_error<T>(&self, result: Result<T, BackendError>) -> Result<T, BackendError> {
+    if result.is_err() {
+      for branch in &[self.tmp_branch.to_string(), self.probe_branch()] {
+        if let Err(e) = self.backend.delete_branch(self.repo, branch).await {
+          error!("deleting branch {} in {}: {}", branch, self.repo, e);
+        }
+      }
+    }
+    result
+  }
+
+  /// What `template` needs to know about `prs`.
+  async fn template_prs(
+    &self,
+    template: &Template,
+    prs: &[BatchPr],
+  ) -> Result<Vec<TemplatePr>, BackendError> {
+    let mut details = vec![];
+    for pr in prs {
+      let mut details_pr = TemplatePr {
+        number: pr.number,
+        ..TemplatePr::default()
+      };
+      if template.needs_details() {
+        let PullRequest {
+          title,
+          body,
+          author,
+          labels,
+          url,
+          ..
+        } = self.client.pr_info(self.repo, pr.number).await?;
+        let reviews = self.client.reviews(self.repo, pr.number).await?;
+        let approval = Approval::evaluate(&self.config.approval, &reviews, &pr.commit_hash);
+        if template.needs_identities() {
+          for approver in &approval.approvers {
+            let identity = self.client.git_identity(self.repo, approver).await?;
+            details_pr.approver_identities.push(identity);
+          }
+        }
+        details_pr.title = title;
+        details_pr.body = body;
+        details_pr.author = author;
+        details_pr.approvers = approval.approvers;
+        details_pr.labels = labels;
+        details_pr.url = url;
+      }
+      details.push(details_pr);
+    }
+    Ok(details)
+  }
+
+  /// Work out what `pr` conflicts with, given the paths which conflicted
+  /// after merging `earlier`, by merging it alone into the target.
+  async fn diagnose(
+    &self,
+    pr: &BatchPr,
+    paths: Vec<String>,
+    earlier: &[&BatchPr],
+  ) -> Result<Conflict, BackendError> {
+    let mut conflict = Conflict {
+      number: pr.number,
+      paths,
+      with_target: true,
+      with_prs: vec![],
+    };
+    if earlier.is_empty() {
+      return Ok(conflict);
+    }
+    let probe_branch = self.probe_branch();
+    self.start_branch(&probe_branch).await?;
+    let message = format!("Merge #{} alone", pr.number);
+    let result = self
+      .backend
+      .merge(self.repo, &probe_branch, &pr.commit_hash, &message)
+      .await?;
+    self.backend.delete_branch(self.repo, &probe_branch).await?;
+    if let MergeResult::Conflict(paths) = result {
+      conflict.paths = paths;
+      return Ok(conflict);
+    }
+    conflict.with_target = false;
+    for other in earlier {
+      let changed = self
+        .backend
+        .changed_paths(self.repo, self.target_hash, &other.commit_hash)
+        .await?;
+      if conflict.paths.iter().any(|path| changed.contains(path)) {
+        conflict.with_prs.push(other.number);
+      }
+    }
+    if conflict.with_prs.is_empty() {
+      conflict.with_prs = earlier.iter().map(|other| other.number).collect();
+    }
+    Ok(conflict)
+  }
+
+  /// Create `tmp_branch` at `target_hash` and merge each PR into it in turn,
+  /// with the commit message given by `message`, skipping those which
+  /// conflict.
+  async fn merge_into_tmp(
+    &self,
+    prs: &[BatchPr],
+    message: impl Fn(usize) -> String,
+  ) -> Result<Merged, BackendError> {
+    self.start_branch(self.tmp_branch).await?;
+    let mut result = Merged {
+      head: self.target_hash.to_string(),
+      merged: vec![],
+      conflicts: vec![],
+      ranges: vec![],
+    };
+    for (i, pr) in prs.iter().enumerate() {
+      match self
+        .backend
+        .merge(self.repo, self.tmp_branch, &pr.commit_hash, &message(i))
+        .await?
+      {
+        MergeResult::Merged(hash) => {
+          result.ranges.push(PrRange {
+            number: pr.number,
+            from: std::mem::replace(&mut result.head, hash.clone()),
+            to: hash,
+          });
+          result.merged.push(pr.number);
+        }
+        MergeResult::NothingToMerge => (),
+        MergeResult::Conflict(paths) => {
+          let earlier: Vec<&BatchPr> = prs
+            .iter()
+            .filter(|pr| result.merged.contains(&pr.number))
+            .collect();
+          let conflict = self.diagnose(pr, paths, &earlier).await?;
+          result.conflicts.push(conflict);
+        }
+      }
+    }
+    Ok(result)
+  }
+
+  /// Force-reset the staging branch to `staging_hash`, and clean up.
+  async fn publish(&self, staging_hash: &str) -> Result<(), BackendError> {
+    self
+      .backend
+      .publish(self.repo, self.staging_branch, staging_hash)
+      .await?;
+    self.backend.delete_branch(self.repo, self.tmp_branch).await
+  }
+
+  /// Merge strategy: merge each PR in turn into a temporary branch, then
+  /// force-reset the staging branch to the result.
+  async fn merge(&self, prs: &[BatchPr]) -> Result<Constructed, BackendError> {
+    let template = &self.config.messages.merge;
+    let details = self.template_prs(template, prs).await?;
+    let merged = self
+      .merge_into_tmp(prs, |i| template.render(Some(&details[i]), &details))
+      .await?;
+    self.publish(&merged.head).await?;
+    Ok(Constructed {
+      staging_hash: merged.head,
+      conflicts: merged.conflicts,
+      rejected: vec![],
+      ranges: merged.ranges,
+    })
+  }
+
+  /// Octopus strategy: merge the PRs into a temporary branch as for Merge,
+  /// then commit the resulting tree once with the target and every merged PR
+  /// as parents.
+  async fn octopus(&self, prs: &[BatchPr]) -> Result<Constructed, BackendError> {
+    let merged = self.merge_into_tmp(prs, |i| tmp_message(&prs[i])).await?;
+    let staging_hash = if merged.merged.is_empty() {
+      merged.head
+    } else {
+      let tree = self.backend.commit_tree(self.repo, &merged.head).await?;
+      let merged_prs: Vec<BatchPr> = prs
+        .iter()
+        .filter(|pr| merged.merged.contains(&pr.number))
+        .cloned()
+        .collect();
+      let mut parents = vec![self.target_hash];
+      parents.extend(merged_prs.iter().map(|pr| pr.commit_hash.as_str()));
+      let template = &self.config.messages.octopus;
+      let details = self.template_prs(template, &merged_prs).await?;
+      let message = template.render(None, &details);
+      self
+        .backend
+        .create_commit(self.repo, &message, &tree, &parents, None)
+        .await?
+    };
+    self.publish(&staging_hash).await?;
+    Ok(Constructed {
+      staging_hash,
+      conflicts: merged.conflicts,
+      rejected: vec![],
+      ranges: vec![],
+    })
+  }
+
+  /// Squash strategy: one commit per PR, authored by the PR's author, in
+  /// batch order.
+  async fn squash(&self, prs: &[BatchPr]) -> Result<Constructed, BackendError> {
+    self.start_branch(self.tmp_branch).await?;
+    let template = &self.config.messages.squash;
+    let details = self.template_prs(template, prs).await?;
+    let mut head = self.target_hash.to_string();
+    let mut merged_prs: Vec<&BatchPr> = vec![];
+    let mut conflicts = vec![];
+    let mut ranges = vec![];
+    for (pr, details_pr) in prs.iter().zip(&details) {
+      let merged = match self
+        .backend
+        .merge(
+          self.repo,
+          self.tmp_branch,
+          &pr.commit_hash,
+          &tmp_message(pr),
+        )
+        .await?
+      {
+        MergeResult::Merged(hash) => hash,
+        MergeResult::NothingToMerge => continue,
+        MergeResult::Conflict(paths) => {
+          conflicts.push(self.diagnose(pr, paths, &merged_prs).await?);
+          continue;
+        }
+      };
+      merged_prs.push(pr);
+      let squashed = Squashed::fetch(self.client, self.repo, pr.number).await?;
+      let message = with_trailers(
+        template.render(Some(details_pr), &details),
+        &squashed.co_authors,
+      );
+      let tree = self.backend.commit_tree(self.repo, &merged).await?;
+      let squashed_hash = self
+        .backend
+        .create_commit(self.repo, &message, &tree, &[&head], Some(&squashed.author))
+        .await?;
+      ranges.push(PrRange {
+        number: pr.number,
+        from: std::mem::replace(&mut head, squashed_hash.clone()),
+        to: squashed_hash,
+      });
+      self
+        .backend
+        .reset_branch(self.repo, self.tmp_branch, &head)
+        .await?;
+    }
+    self.publish(&head).await?;
+    Ok(Constructed {
+      staging_hash: head,
+      conflicts,
+      rejected: vec![],
+      ranges,
+    })
+  }
+
+  /// Batch squash strategy: merge the PRs into a temporary branch as for
+  /// Merge, then commit the resulting tree once on top of the target.
+  async fn batch_squash(&self, prs: &[BatchPr]) -> Result<Constructed, BackendError> {
+    let merged = self.merge_into_tmp(prs, |i| tmp_message(&prs[i])).await?;
+    let staging_hash = if merged.merged.is_empty() {
+      merged.head
+    } else {
+      let mut squashed = vec![];
+      for &pr in &merged.merged {
+        squashed.push(Squashed::fetch(self.client, self.repo, pr).await?);
+      }
+      let tree = self.backend.commit_tree(self.repo, &merged.head).await?;
+      self
+        .backend
+        .create_commit(
+          self.repo,
+          &batch_squash_message(&squashed),
+          &tree,
+          &[self.target_hash],
+          Some(&squashed[0].author),
+        )
+        .await?
+    };
+    self.publish(&staging_hash).await?;
+    Ok(Constructed {
+      staging_hash,
+      conflicts: merged.conflicts,
+      rejected: vec![],
+      ranges: vec![],
+    })
+  }
+
+  /// Cherry-pick strategy: replay each PR's commits one by one, keeping their
+  /// authors and messages, for a linear history.  PRs containing merge
+  /// commits are rejected.
+  async fn cherry_pick(&self, prs: &[BatchPr]) -> Result<Constructed, BackendError> {
+    let mut picks = vec![];
+    let mut rejected = vec![];
+    for pr in prs {
+      let range = self
+        .backend
+        .compare_commits(self.repo, self.target_hash, &pr.commit_hash)
+        .await?;
+      if range.commits.len() < range.total_commits {
+        rejected.push((
+          pr.number,
+          format!(
+            "the PR has {} commits, but only {} can be listed, so the cherry-pick strategy cannot replay it",
+            range.total_commits,
+            range.commits.len()
+          ),
+        ));
+        continue;
+      }
+      match topological_order(range.commits) {
+        Ok(commits) => picks.push((pr, commits)),
+        Err(sha) => rejected.push((
+          pr.number,
+          format!(
+            "commit {} is a merge commit, which the cherry-pick strategy cannot replay",
+            sha
+          ),
+        )),
+      }
+    }
+
+    self.start_branch(self.tmp_branch).await?;
+    // S in the plan: the last commit replayed so far
+    let mut base = self.target_hash.to_string();
+    let mut picked: Vec<&BatchPr> = vec![];
+    let mut conflicts = vec![];
+    let mut ranges = vec![];
+    for (pr, commits) in picks {
+      match self.replay(&base, &commits).await? {
+        Ok(head) => {
+          ranges.push(PrRange {
+            number: pr.number,
+            from: std::mem::replace(&mut base, head.clone()),
+            to: head,
+          });
+          picked.push(pr);
+        }
+        Err(paths) => conflicts.push(self.diagnose(pr, paths, &picked).await?),
+      }
+    }
+    self.publish(&base).await?;
+    Ok(Constructed {
+      staging_hash: base,
+      conflicts,
+      rejected,
+      ranges,
+    })
+  }
+
+  /// Replay `commits`, in topological order, on top of `base`, the head of
+  /// the temporary branch, and return the last commit replayed.  On
+  /// conflict, the temporary branch is reset to `base`, and the conflicting
+  /// paths are returned.
+  async fn replay(
+    &self,
+    base: &str,
+    commits: &[Commit],
+  ) -> Result<Result<String, Vec<String>>, BackendError> {
+    // merge the commits into tmp one by one, recording the tree after each
+    let mut head = base.to_string();
+    let mut trees = vec![];
+    for commit in commits {
+      let message = format!("Merge {}", commit.sha);
+      match self
+        .backend
+        .merge(self.repo, self.tmp_branch, &commit.sha, &message)
+        .await?
+      {
+        MergeResult::Merged(hash) => head = hash,
+        MergeResult::NothingToMerge => (),
+        MergeResult::Conflict(paths) => {
+          self
+            .backend
+            .reset_branch(self.repo, self.tmp_branch, base)
+            .await?;
+          return Ok(Err(paths));
+        }
+      }
+      trees.push(self.backend.commit_tree(self.repo, &head).await?);
+    }
+    // recreate the commits with those trees as a linear history
+    let mut head = base.to_string();
+    for (commit, tree) in commits.iter().zip(&trees) {
+      head = self
+        .backend
+        .create_commit(
+          self.repo,
+          &commit.message,
+          tree,
+          &[&head],
+          Some(&commit.author),
+        )
+        .await?;
+    }
+    self
+      .backend
+      .reset_branch(self.repo, self.tmp_branch, &head)
+      .await?;
+    Ok(Ok(head))
+  }
+
+  /// Cherry-pick `commits`, those of a PR, onto `target_hash` as for the
+  /// Cherry-pick strategy, and publish the result on the staging branch.
+  /// On conflict, the staging branch is published at `target_hash`, for a
+  /// human to finish the job.
+  pub(super) async fn backport(&self, commits: Vec<Commit>) -> Result<Backported, BackendError> {
+    let commits = match topological_order(commits) {
+      Ok(commits) => commits,
+      Err(sha) => return Ok(Backported::MergeCommit(sha)),
+    };
+    let published = async {
+      self.start_branch(self.tmp_branch).await?;
+      let result = self.replay(self.target_hash, &commits).await?;
+      let head = result.as_ref().map_or(self.target_hash, String::as_str);
+      self.publish(head).await?;
+      Ok(result)
+    }
+    .await;
+    Ok(match self.clean_up_on_error(published).await? {
+      Ok(head) => Backported::Picked(head),
+      Err(paths) => Backported::Conflict(paths),
+    })
+  }
+}
+
+/// Result of backporting a PR with `Construction::backport`.
+#[derive(Debug, PartialEq)]
+pub(super) enum Backported {
+  /// Head of the published branch.
+  Picked(String),
+  /// The commits conflict with the target in these paths, so the branch
+  /// was published at the target.
+  Conflict(Vec<String>),
+  /// The PR contains this merge commit, which cannot be cherry-picked, so
+  /// nothing was published.
+  MergeCommit(String),
+}
+
+/// What a squashed commit says about a PR.
+struct Squashed {
+  number: i64,
+  title: String,
+  body: String,
+  author: GitIdentity,
+  /// Authors of the PR's commits other than `author`.
+  co_authors: Vec<GitIdentity>,
+}
+
+impl Squashed {
+  async fn fetch(client: &Client, repo: &Repository, pr: i64) -> Result<Self, ClientError> {
+    let PullRequest {
+      title,
+      body,
+      author,
+      ..
+    } = client.pr_info(repo, pr).await?;
+    let identity = client.git_identity(repo, &author).await?;
+    let commit_authors = client.pr_commit_authors(repo, pr).await?;
+    Ok(Self {
+      number: pr,
+      title,
+      body,
+      co_authors: co_authors(&author, &identity, &commit_authors),
+      author: identity,
+    })
+  }
+}
+
+/// Distinct identities in `commit_authors` which are not the PR author.
+fn co_authors(
+  login: &str,
+  identity: &GitIdentity,
+  commit_authors: &[CommitAuthor],
+) -> Vec<GitIdentity> {
+  let mut co_authors: Vec<GitIdentity> = vec![];
+  for commit_author in commit_authors {
+    let is_author = commit_author.login.as_deref() == Some(login)
+      || commit_author.identity.email == identity.email;
+    if !is_author
+      && !co_authors
+        .iter()
+        .any(|c| c.email == commit_author.identity.email)
+    {
+      co_authors.push(commit_author.identity.clone());
+    }
+  }
+  co_authors
+}
+
+/// Whether `line` looks like a trailer, such as `Reviewed-by: ...`.
+fn is_trailer(line: &str) -> bool {
+  match line.find(": ") {
+    Some(colon) => {
+      colon > 0
+        && line[..colon]
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    }
+    None => false,
+  }
+}
+
+/// Append `Co-authored-by` trailers to `message`, joining its trailers if
+/// it ends with any.
+fn with_trailers(mut message: String, co_authors: &[GitIdentity]) -> String {
+  if !co_authors.is_empty() {
+    message = message.trim_end().to_string();
+    match message.lines().last() {
+      Some(line) if is_trailer(line) => (),
+      _ => message.push('\n'),
+    }
+    for co_author in co_authors {
+      message.push_str(&format!("\nCo-authored-by: {}", co_author));
+    }
+  }
+  message
+}
+
+/// Message of the commit squashing a whole batch.  Authors of every PR but
+/// the first are credited as co-authors.
+fn batch_squash_message(prs: &[Squashed]) -> String {
+  let list: Vec<String> = prs.iter().map(|pr| format!("#{}", pr.number)).collect();
+  let mut message = format!("Merge {}\n", list.join(", "));
+  for pr in prs {
+    message.push_str(&format!("\n{} (#{})\n", pr.title, pr.number));
+    if !pr.body.trim().is_empty() {
+      message.push_str(&format!("\n{}\n", pr.body.trim()));
+    }
+  }
+  let mut co_authors: Vec<GitIdentity> = vec![];
+  let main_author = prs.first().map(|pr| &pr.author);
+  for pr in prs {
+    for identity in std::iter::once(&pr.author).chain(&pr.co_authors) {
+      if Some(identity) != main_author && !co_authors.iter().any(|c| c.email == identity.email) {
+        co_authors.push(identity.clone());
+      }
+    }
+  }
+  with_trailers(message, &co_authors)
+}
+
+/// Message of an intermediate merge into the temporary branch, which does
+/// not end up on the staging branch.
+fn tmp_message(pr: &BatchPr) -> String {
+  format!("Merge #{}", pr.number)
+}
+
+/// Sort `commits` so that each comes after its parents among them, keeping
+/// their order otherwise.  Fails with the hash of any merge commit.
+fn topological_order(mut commits: Vec<Commit>) -> Result<Vec<Commit>, String> {
+  if let Some(merge) = commits.iter().find(|c| c.parents.len() > 1) {
+    return Err(merge.sha.clone());
+  }
+  let mut sorted = Vec::with_capacity(commits.len());
+  while !commits.is_empty() {
+    let next = commits
+      .iter()
+      .position(|c| {
+        !c.parents
+          .iter()
+          .any(|parent| commits.iter().any(|other| &other.sha == parent))
+      })
+      .expect("commit graph has a cycle");
+    sorted.push(commits.remove(next));
+  }
+  Ok(sorted)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn identity(name: &str) -> GitIdentity {
+    GitIdentity {
+      name: name.to_string(),
+      email: format!("{}@example.com", name),
+    }
+  }
+
+  fn commit_author(name: &str, login: Option<&str>) -> CommitAuthor {
+    CommitAuthor {
+      identity: identity(name),
+      login: login.map(str::to_string),
+    }
+  }
+
+  #[test]
+  fn test_squash_message() {
+    let squash_message = |pr: &Squashed| {
+      let details = TemplatePr {
+        number: pr.number,
+        title: pr.title.clone(),
+        body: pr.body.clone(),
+        ..TemplatePr::default()
+      };
+      let template = &RepoConfig::default().messages.squash;
+      with_trailers(template.render(Some(&details), &[]), &pr.co_authors)
+    };
+    let alice = GitIdentity {
+      name: "Alice".to_string(),
+      email: "1+alice@users.noreply.github.com".to_string(),
+    };
+    let commits = vec![
+      commit_author("alice-laptop", Some("alice")),
+      commit_author("bob", Some("bob")),
+      commit_author("carol", None),
+      commit_author("bob", None),
+    ];
+    let pr = Squashed {
+      number: 12,
+      title: "Fix the frobnicator".to_string(),
+      body: "It was broken.\n".to_string(),
+      co_authors: co_authors("alice", &alice, &commits),
+      author: alice,
+    };
+    assert_eq!(
+      squash_message(&pr),
+      "Fix the frobnicator (#12)\n\nIt was broken.\n\nCo-authored-by: bob <bob@example.com>\nCo-authored-by: carol <carol@example.com>"
+    );
+
+    let other = Squashed {
+      number: 13,
+      title: "Add a widget".to_string(),
+      body: "".to_string(),
+      author: identity("bob"),
+      co_authors: vec![],
+    };
+    assert_eq!(squash_message(&other), "Add a widget (#13)");
+    assert_eq!(
+      with_trailers(
+        "Add a widget\n\nApproved-by: carol <carol@example.com>".to_string(),
+        &[identity("bob")]
+      ),
+      "Add a widget\n\nApproved-by: carol <carol@example.com>\nCo-authored-by: bob <bob@example.com>"
+    );
+    assert_eq!(
+      batch_squash_message(&[pr, other]),
+      "Merge #12, #13\n\nFix the frobnicator (#12)\n\nIt was broken.\n\nAdd a widget (#13)\n\nCo-authored-by: bob <bob@example.com>\nCo-authored-by: carol <carol@example.com>"
+    );
+  }
+
+  #[test]
+  fn test_conflict_report() {
+    let conflict = Conflict {
+      number: 12,
+      paths: vec!["src/lib.rs".to_string(), "README.md".to_string()],
+      with_target: true,
+      with_prs: vec![],
+    };
+    assert_eq!(
+      conflict.report("main"),
+      "Merge failed: this PR conflicts with `main`.\n\nFiles involved:\n- `src/lib.rs`\n- `README.md`\n\nPlease rebase onto `main`, resolve the conflicts and request the merge again."
+    );
+
+    let conflict = Conflict {
+      number: 13,
+      paths: vec![],
+      with_target: false,
+      with_prs: vec![10, 11],
+    };
+    assert_eq!(
+      conflict.report("main"),
+      "Merge conflict: this PR conflicts with #10, #11, merged before it in its batch.\n\nIt merges cleanly into `main` on its own, so no rebase is needed: it will be retried in a later batch."
+    );
+  }
+
+  fn commit(sha: &str, parents: &[&str]) -> Commit {
+    Commit {
+      sha: sha.to_string(),
+      parents: parents.iter().map(|p| p.to_string()).collect(),
+      author: identity("alice"),
+      message: sha.to_string(),
+    }
+  }
+
+  #[test]
+  fn test_topological_order() {
+    let commits = vec![
+      commit("c", &["b"]),
+      commit("a", &["base"]),
+      commit("b", &["a"]),
+    ];
+    let sorted: Vec<String> = topological_order(commits)
+      .unwrap()
+      .into_iter()
+      .map(|c| c.sha)
+      .collect();
+    assert_eq!(sorted, vec!["a", "b", "c"]);
+
+    let commits = vec![commit("a", &["base"]), commit("m", &["a", "other"])];
+    assert_eq!(topological_order(commits).unwrap_err(), "m");
+  }
+}
@@ -0,0 +1,664 @@
+use super::{attempt_state, pr_row, row_repo, Controller, ControllerError, MergeState, PrState};
+use crate::control::command::LinkedPr;
+use crate::github::client::ClientError;
+use crate::github::types::{PrState as GHPrState, PullRequest, Repository};
+
+use std::convert::TryInto;
+
+use actix_web::http::StatusCode;
+use log::{error, info};
+use quaint::ast::{Comparable, Delete, Orderable, ParameterizedValue, Select, Update};
+use quaint::connector::{Queryable, TransactionCapable};
+
+/// A PR of a merge group, which lands together with the PRs linked to it in
+/// other repositories.
+#[derive(Debug, Clone)]
+pub(super) struct Member {
+  pub(super) repo: Repository,
+  pub(super) number: i64,
+  pub(super) state: PrState,
+  pub(super) merge_attempt: Option<String>,
+}
+
+/// A PR in another repository which a merge request links to, checked to be
+/// able to join the group.
+#[derive(Debug)]
+pub(super) struct Linked {
+  pub(super) repo: Repository,
+  pub(super) number: i64,
+  pub(super) info: PullRequest,
+  /// Unmet conditions which prevent the PR from being queued.
+  pub(super) blockers: Vec<String>,
+}
+
+/// Whether all `size` PRs of a group are still in the queue and past
+/// REQUESTED, so that the group's part in each repository can be built.
+pub(super) fn is_ready(size: usize, members: &[Member]) -> bool {
+  members.len() == size
+    && members
+      .iter()
+      .all(|m| !matches!(m.state, PrState::Requested))
+}
+
+/// A merge attempt with PRs of a merge group, which passed its checks.
+#[derive(Debug, Clone)]
+pub(super) struct Part {
+  pub(super) repo: Repository,
+  pub(super) attempt: String,
+  pub(super) target: String,
+  pub(super) staging_hash: String,
+  pub(super) staging_branch: String,
+}
+
+impl Part {
+  /// Target branch of the part, as shown in reports.
+  pub(super) fn name(&self) -> String {
+    format!("`{}` in {}", self.target, self.repo)
+  }
+}
+
+/// What happened to one target branch when a group landed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Landing {
+  Landed,
+  /// Not fast-forwarded, because the branch moved or the push failed.
+  Rejected,
+  /// Not attempted, because an earlier push failed.
+  Skipped,
+  /// Fast-forwarded, then reset to where it was.
+  RolledBack,
+  /// Fast-forwarded, and could not be reset to where it was.
+  RollbackFailed,
+}
+
+/// Describe the outcome for each `(target, landing)` of a group which could
+/// not land, one line per target.
+pub(super) fn report(parts: &[(String, Landing)]) -> String {
+  parts
+    .iter()
+    .map(|(target, landing)| {
+      let outcome = match landing {
+        Landing::Landed => "landed",
+        Landing::Rejected => "could not be fast-forwarded",
+        Landing::Skipped => "not pushed",
+        Landing::RolledBack => "rolled back",
+        Landing::RollbackFailed => "**landed, and could not be rolled back**",
+      };
+      format!("\n- {}: {}", target, outcome)
+    })
+    .collect()
+}
+
+impl<Q> Controller<Q>
+where
+  Q: Queryable + TransactionCapable + 'static,
+{
+  /// PRs in other repositories which a PR is to land together with, as
+  /// requested with `with=`.  `Err` explains why they cannot be linked.
+  pub(super) async fn linked_prs(
+    &self,
+    repo: &Repository,
+    with: &[LinkedPr],
+  ) -> Result<Result<Vec<Linked>, String>, ControllerError> {
+    let mut linked = vec![];
+    for requested in with {
+      let other = match self
+        .client
+        .linked_repository(repo, &requested.owner, &requested.repo)
+        .await?
+      {
+        Some(other) => other,
+        None => {
+          return Ok(Err(format!(
+            "Error: cherry cannot access {}/{} from this repository.",
+            requested.owner, requested.repo
+          )))
+        }
+      };
+      if other == *repo {
+        return Ok(Err(format!(
+          "Error: {} is in this repository.  Only PRs in other repositories can be linked.",
+          requested
+        )));
+      }
+      let info = match self.client.pr_info(&other, requested.number).await {
+        Ok(info) => info,
+        Err(ClientError::ServerErrorResponse(StatusCode::NOT_FOUND, _)) => {
+          return Ok(Err(format!("Error: {} does not exist.", requested)))
+        }
+        Err(e) => return Err(e.into()),
+      };
+      if matches!(info.state, GHPrState::Closed) {
+        return Ok(Err(format!("Error: {} is closed.", requested)));
+      }
+      let default_branch = self.client.default_branch(&other).await?;
+      if !self
+        .config(&other)
+        .await?
+        .allows_target(&info.base_ref, &default_branch)
+      {
+        return Ok(Err(format!(
+          "Error: PRs into `{}` are not merged by cherry in {}.",
+          info.base_ref, other
+        )));
+      }
+      let blockers = self.blockers(&other, requested.number, &info).await?;
+      linked.push(Linked {
+        repo: other,
+        number: requested.number,
+        info,
+        blockers,
+      });
+    }
+    Ok(Ok(linked))
+  }
+
+  /// Label the PRs `linked` to `pr` in a new merge group, and tell each PR
+  /// of the group which PRs it lands together with.
+  pub(super) async fn announce_group(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    linked: &[Linked],
+  ) -> Result<(), ControllerError> {
+    let names: Vec<String> = linked
+      .iter()
+      .map(|other| format!("{}#{}", other.repo, other.number))
+      .collect();
+    self
+      .comment(
+        repo,
+        pr,
+        format!(
+          "This PR lands together with {}, once all of them pass their checks.",
+          names.join(", ")
+        )
+        .as_str(),
+      )
+      .await?;
+    for (i, other) in linked.iter().enumerate() {
+      let state = if other.blockers.is_empty() {
+        PrState::Queued
+      } else {
+        PrState::Requested
+      };
+      self
+        .sync_state_label(&other.repo, other.number, Some(state.into()))
+        .await?;
+      let others: Vec<String> = Some(format!("{}#{}", repo, pr))
+        .into_iter()
+        .chain(
+          names
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, name)| name.clone()),
+        )
+        .collect();
+      let mut message = format!(
+        "A merge was requested in {}#{}: this PR lands together with {}, once all of them pass their checks.",
+        repo,
+        pr,
+        others.join(", ")
+      );
+      if !other.blockers.is_empty() {
+        let conditions: String = other
+          .blockers
+          .iter()
+          .map(|b| format!("\n- {}", b))
+          .collect();
+        message.push_str(&format!(
+          "\n\nThis PR cannot be merged yet.  It will be merged automatically once the following conditions are resolved:{}",
+          conditions
+        ));
+      }
+      self
+        .comment(&other.repo, other.number, message.as_str())
+        .await?;
+    }
+    Ok(())
+  }
+
+  /// Cancel the merge groups with PRs in `repo` which lost a PR, because it
+  /// left the queue without landing.  Returns the other repositories whose
+  /// PRs were dropped.
+  pub(super) async fn drop_broken_groups(
+    &self,
+    repo: &Repository,
+  ) -> Result<Vec<Repository>, ControllerError> {
+    let tx = self.db.start_transaction().await?;
+    let rows = tx
+      .select(
+        Select::from_table("pull_request")
+          .column("merge_group")
+          .so_that("owner".equals(repo.owner.as_str()))
+          .and_where("repo".equals(repo.repo.as_str())),
+      )
+      .await?;
+    let mut groups: Vec<String> = rows
+      .into_iter()
+      .filter_map(|row| row["merge_group"].to_string())
+      .collect();
+    groups.sort();
+    groups.dedup();
+    let mut split = vec![];
+    let mut dropped = vec![];
+    for group in &groups {
+      let (size, members) = group_members(&tx, group).await?;
+      if members.len() >= size {
+        continue;
+      }
+      for member in &members {
+        if let Some(attempt) = &member.merge_attempt {
+          let reason = "a PR linked with it left the queue";
+          let others = self
+            .leave_attempt(&tx, &member.repo, member.number, attempt, reason)
+            .await?;
+          split.extend(others.into_iter().map(|pr| (member.repo.clone(), pr)));
+        }
+        tx.delete(Delete::from_table("pull_request").so_that(pr_row(&member.repo, member.number)))
+          .await?;
+        dropped.push((member.repo.clone(), member.number));
+      }
+      tx.delete(Delete::from_table("merge_group").so_that("id".equals(group.as_str())))
+        .await?;
+    }
+    tx.commit().await?;
+    if dropped.is_empty() {
+      return Ok(vec![]);
+    }
+    info!("dropped PRs of broken merge groups: {:?}", dropped);
+
+    for (other, pr) in split.iter().filter(|pr| !dropped.contains(pr)) {
+      self
+        .sync_state_label(other, *pr, Some(PrState::Split.into()))
+        .await?;
+    }
+    let mut others = vec![];
+    for (other, pr) in dropped {
+      self.sync_state_label(&other, pr, None).await?;
+      self
+        .comment(
+          &other,
+          pr,
+          "Merge cancelled: a PR linked with this one left the queue without landing.",
+        )
+        .await?;
+      if other != *repo && !others.contains(&other) {
+        others.push(other);
+      }
+    }
+    Ok(others)
+  }
+
+  /// Other repositories with QUEUED PRs of merge groups which are ready to
+  /// be built and have PRs in `repo`.
+  pub(super) async fn ready_group_repos(
+    &self,
+    repo: &Repository,
+  ) -> Result<Vec<Repository>, ControllerError> {
+    let rows = self
+      .db
+      .select(
+        Select::from_table("pull_request")
+          .column("merge_group")
+          .so_that("owner".equals(repo.owner.as_str()))
+          .and_where("repo".equals(repo.repo.as_str())),
+      )
+      .await?;
+    let groups: Vec<String> = rows
+      .into_iter()
+      .filter_map(|row| row["merge_group"].to_string())
+      .collect();
+    let mut others = vec![];
+    for group in &groups {
+      let (size, members) = group_members(&self.db, group).await?;
+      if !is_ready(size, &members) {
+        continue;
+      }
+      for member in members {
+        if member.repo != *repo
+          && matches!(member.state, PrState::Queued)
+          && !others.contains(&member.repo)
+        {
+          others.push(member.repo);
+        }
+      }
+    }
+    Ok(others)
+  }
+
+  /// Land a merge group once the attempts with its PRs all passed their
+  /// checks and are at the head of their trains.  The targets are
+  /// fast-forwarded in turn; if one cannot be, the targets already
+  /// fast-forwarded are reset, and the group is queued again.
+  pub(super) async fn complete_group(&self, group: &str) -> Result<(), ControllerError> {
+    let (size, members) = group_members(&self.db, group).await?;
+    // a group which lost a PR is cancelled by Construct
+    if members.len() < size {
+      return Ok(());
+    }
+    let mut attempts: Vec<(Repository, String)> = vec![];
+    for member in members {
+      match member.merge_attempt {
+        Some(attempt) if !attempts.iter().any(|(_, a)| *a == attempt) => {
+          attempts.push((member.repo, attempt))
+        }
+        Some(_) => (),
+        None => return Ok(()),
+      }
+    }
+    let mut parts = vec![];
+    for (repo, attempt) in attempts {
+      let rows = self
+        .db
+        .select(Select::from_table("merge_attempt").so_that("id".equals(attempt.as_str())))
+        .await?;
+      let (target, staging_hash, staging_branch, base) = match rows.into_iter().next() {
+        Some(row) if matches!((&row["state"]).try_into()?, MergeState::Success) => (
+          row["base_ref"].to_string().unwrap_or_default(),
+          row["staging_hash"].to_string().unwrap_or_default(),
+          row["staging_branch"].to_string().unwrap_or_default(),
+          row["base_attempt"].to_string(),
+        ),
+        _ => return Ok(()),
+      };
+      if let Some(base) = base {
+        if attempt_state(&self.db, &base).await?.is_some() {
+          return Ok(());
+        }
+      }
+      let config = self.target_config(&repo, &target).await?;
+      let part = Part {
+        repo,
+        attempt,
+        target,
+        staging_hash,
+        staging_branch,
+      };
+      parts.push((part, config));
+    }
+    info!(
+      "completing merge group {}: {:?}",
+      group,
+      parts
+        .iter()
+        .map(|(part, _)| part.attempt.as_str())
+        .collect::<Vec<_>>()
+    );
+
+    // the commits each target was at before it was fast-forwarded
+    let mut landings: Vec<(Landing, Option<String>)> = vec![];
+    for (part, config) in &parts {
+      if landings
+        .iter()
+        .any(|(landing, _)| *landing != Landing::Landed)
+      {
+        landings.push((Landing::Skipped, None));
+        continue;
+      }
+      let landing = match self.push_part(part).await {
+        Ok(Some(old)) => (Landing::Landed, Some(old)),
+        Ok(None) => (Landing::Rejected, None),
+        Err(e) => {
+          error!("landing `{}` in {}: {}", part.target, part.repo, e);
+          (Landing::Rejected, None)
+        }
+      };
+      landings.push(landing);
+      if part.staging_branch != config.staging_branch {
+        self
+          .delete_train_branch(&part.repo, &part.staging_branch)
+          .await;
+      }
+    }
+
+    if landings
+      .iter()
+      .all(|(landing, _)| *landing == Landing::Landed)
+    {
+      // the PRs of the group now land one attempt at a time, which must not
+      // look like the group losing PRs
+      let tx = self.db.start_transaction().await?;
+      tx.update(
+        Update::table("pull_request")
+          .set("merge_group", ParameterizedValue::Null)
+          .so_that("merge_group".equals(group)),
+      )
+      .await?;
+      tx.delete(Delete::from_table("merge_group").so_that("id".equals(group)))
+        .await?;
+      tx.commit().await?;
+      let mut next = vec![];
+      for (part, config) in &parts {
+        self
+          .log_attempt(
+            &part.repo,
+            &part.attempt,
+            &format!("landed together with merge group `{}`", group),
+          )
+          .await?;
+        if let Some(attempt) = self
+          .land(
+            &part.repo,
+            config,
+            &part.target,
+            &part.attempt,
+            &part.staging_hash,
+          )
+          .await?
+        {
+          next.push((part.repo.clone(), attempt));
+        }
+      }
+      for (repo, attempt) in &next {
+        self.complete(repo, attempt).await?;
+      }
+      let mut repos: Vec<&Repository> = vec![];
+      for (part, _) in &parts {
+        if !repos.contains(&&part.repo) {
+          repos.push(&part.repo);
+        }
+      }
+      for repo in repos {
+        self.construct(repo).await?;
+      }
+      return Ok(());
+    }
+
+    // best effort: a target which moved again since cannot be reset
+    for ((part, _), (landing, old)) in parts.iter().zip(landings.iter_mut()).rev() {
+      if let (Landing::Landed, Some(old)) = (*landing, old) {
+        *landing = match self.roll_back_part(part, old).await {
+          Ok(true) => Landing::RolledBack,
+          Ok(false) => Landing::RollbackFailed,
+          Err(e) => {
+            error!("rolling back `{}` in {}: {}", part.target, part.repo, e);
+            Landing::RollbackFailed
+          }
+        };
+      }
+    }
+    let report = report(
+      &parts
+        .iter()
+        .zip(&landings)
+        .map(|((part, _), (landing, _))| (part.name(), *landing))
+        .collect::<Vec<_>>(),
+    );
+    error!("merge group {} could not land:{}", group, report);
+    let mut message = format!(
+      "Merge delayed: the PRs linked with this one could not land together, so they were queued again.  Target branches:{}",
+      report
+    );
+    if landings
+      .iter()
+      .any(|(landing, _)| *landing == Landing::RollbackFailed)
+    {
+      message.push_str(
+        "\n\nSome target branches could not be rolled back, and need to be reset by hand.",
+      );
+    }
+    for (part, _) in &parts {
+      self
+        .log_attempt(
+          &part.repo,
+          &part.attempt,
+          &format!("merge group `{}` could not land:{}", group, report),
+        )
+        .await?;
+      self
+        .requeue(
+          &part.repo,
+          &part.attempt,
+          "could not land together with the PRs linked with it",
+          &message,
+        )
+        .await?;
+    }
+    Ok(())
+  }
+
+  /// Fast-forward the target of a part of a merge group.  Returns the
+  /// commit the target was at, or `None` if it could not be fast-forwarded.
+  pub(super) async fn push_part(&self, part: &Part) -> Result<Option<String>, ControllerError> {
+    let old = match self.client.branch_hash(&part.repo, &part.target).await? {
+      Some(old) => old,
+      None => return Ok(None),
+    };
+    let intent = || {
+      format!(
+        "fast-forward `{}` to merge attempt {}",
+        part.target, part.attempt
+      )
+    };
+    if !self.should_write(&part.repo, None, intent).await? {
+      return Ok(Some(old));
+    }
+    let landed = self
+      .client
+      .fast_forward_branch(&part.repo, &part.target, &part.staging_hash)
+      .await?;
+    Ok(if landed { Some(old) } else { None })
+  }
+
+  /// Reset the target of a part of a merge group which was fast-forwarded
+  /// back to `old`.  Returns `false` if the target moved since.
+  pub(super) async fn roll_back_part(
+    &self,
+    part: &Part,
+    old: &str,
+  ) -> Result<bool, ControllerError> {
+    let intent = || format!("reset `{}` back to {}", part.target, old);
+    // in shadow mode, the target was never fast-forwarded
+    if !self.should_write(&part.repo, None, intent).await? {
+      return Ok(true);
+    }
+    let current = self.client.branch_hash(&part.repo, &part.target).await?;
+    if current.as_deref() != Some(part.staging_hash.as_str()) {
+      return Ok(false);
+    }
+    self
+      .client
+      .force_update_branch(&part.repo, &part.target, old)
+      .await?;
+    Ok(true)
+  }
+}
+
+/// Number of PRs a merge group had when it was requested, or 0 if it no
+/// longer exists, and its PRs still in the queue.
+pub(super) async fn group_members(
+  db: &impl Queryable,
+  group: &str,
+) -> Result<(usize, Vec<Member>), ControllerError> {
+  let size = db
+    .select(Select::from_table("merge_group").so_that("id".equals(group)))
+    .await?
+    .into_iter()
+    .next()
+    .and_then(|row| row["size"].as_i64())
+    .unwrap_or(0) as usize;
+  let rows = db
+    .select(
+      Select::from_table("pull_request")
+        .so_that("merge_group".equals(group))
+        .order_by("owner".ascend())
+        .order_by("repo".ascend())
+        .order_by("number".ascend()),
+    )
+    .await?;
+  let members = rows
+    .into_iter()
+    .map(|row| {
+      Ok(Member {
+        repo: row_repo(&row),
+        number: row["number"].as_i64().unwrap(),
+        state: (&row["state"]).try_into()?,
+        merge_attempt: row["merge_attempt"].to_string(),
+      })
+    })
+    .collect::<Result<_, ControllerError>>()?;
+  Ok((size, members))
+}
+
+/// Merge group of the PRs of a merge attempt, if they are in one.
+pub(super) async fn attempt_group(
+  db: &impl Queryable,
+  attempt: &str,
+) -> Result<Option<String>, ControllerError> {
+  let rows = db
+    .select(
+      Select::from_table("pull_request")
+        .column("merge_group")
+        .so_that("merge_attempt".equals(attempt)),
+    )
+    .await?;
+  Ok(
+    rows
+      .into_iter()
+      .find_map(|row| row["merge_group"].to_string()),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn member(repo: &str, number: i64, state: PrState) -> Member {
+    Member {
+      repo: Repository {
+        id: 1,
+        owner: "o".to_string(),
+        repo: repo.to_string(),
+      },
+      number,
+      state,
+      merge_attempt: None,
+    }
+  }
+
+  #[test]
+  fn test_is_ready() {
+    let members = vec![
+      member("a", 1, PrState::Queued),
+      member("b", 2, PrState::Merging),
+    ];
+    assert!(is_ready(2, &members));
+    assert!(!is_ready(3, &members));
+    let members = vec![members[0].clone(), member("b", 2, PrState::Requested)];
+    assert!(!is_ready(2, &members));
+  }
+
+  #[test]
+  fn test_report() {
+    assert_eq!(
+      report(&[
+        ("`main` in o/a".to_string(), Landing::RolledBack),
+        ("`main` in o/b".to_string(), Landing::Rejected),
+        ("`main` in o/c".to_string(), Landing::Skipped),
+      ]),
+      "\n- `main` in o/a: rolled back\n- `main` in o/b: could not be fast-forwarded\n- `main` in o/c: not pushed"
+    );
+  }
+}
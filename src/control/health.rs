@@ -0,0 +1,89 @@
+/// The failing checks at the head of a target branch, from `target_health`.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Health {
+  pub(super) head_hash: String,
+  pub(super) checks: Vec<String>,
+  /// Merge attempt which landed at `head_hash`, if any.
+  pub(super) attempt: Option<String>,
+  /// User who let the queue go on despite the failure.
+  pub(super) overridden_by: Option<String>,
+  /// Time the failure was first seen, even at an older head.
+  pub(super) since: i64,
+}
+
+impl Health {
+  /// Whether the queue into the target is paused.
+  pub(super) fn pauses(&self) -> bool {
+    self.overridden_by.is_none()
+  }
+
+  /// Why the PRs into `target` are held, for status replies.
+  pub(super) fn hold(&self, target: &str) -> String {
+    format!(
+      "the checks {} fail on `{}` at {}",
+      checks_list(&self.checks),
+      target,
+      self.head_hash
+    )
+  }
+}
+
+/// `names` of checks, quoted and separated by commas.
+pub(super) fn checks_list(names: &[String]) -> String {
+  names
+    .iter()
+    .map(|name| format!("`{}`", name))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// Comment on the PRs of the merge attempt `attempt`, which landed in
+/// `target` at `head_hash` and broke `checks` there.
+pub(super) fn failure_report(
+  target: &str,
+  head_hash: &str,
+  checks: &[String],
+  attempt: &str,
+) -> String {
+  format!(
+    "The checks {} fail on `{}` at {}, where merge attempt {} with this PR landed.\n\n\
+     The queue into `{}` is paused until they pass.  Revert the batch with \
+     `cherry revert {}`, or let the queue go on with `cherry overridebase {}`.",
+    checks_list(checks),
+    target,
+    head_hash,
+    attempt,
+    target,
+    attempt,
+    target
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_messages() {
+    let mut health = Health {
+      head_hash: "abc".to_string(),
+      checks: vec!["ci/test".to_string(), "ci/lint".to_string()],
+      attempt: None,
+      overridden_by: None,
+      since: 0,
+    };
+    assert!(health.pauses());
+    assert_eq!(
+      health.hold("main"),
+      "the checks `ci/test`, `ci/lint` fail on `main` at abc"
+    );
+    health.overridden_by = Some("alice".to_string());
+    assert!(!health.pauses());
+    assert_eq!(
+      failure_report("main", "abc", &health.checks, "a1"),
+      "The checks `ci/test`, `ci/lint` fail on `main` at abc, where merge attempt a1 with this PR landed.\n\n\
+       The queue into `main` is paused until they pass.  Revert the batch with \
+       `cherry revert a1`, or let the queue go on with `cherry overridebase main`."
+    );
+  }
+}
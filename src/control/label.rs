@@ -0,0 +1,93 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LabelConfig {
+  /// Labels which hold a PR in the REQUESTED state while present.
+  pub blocking: Vec<String>,
+  /// Labels which request a merge when added, like `cherry merge`.
+  pub merge: Vec<String>,
+  /// Prefix for the state labels kept in sync on each PR.  `None`
+  /// disables state labels.
+  pub state_prefix: Option<String>,
+}
+
+impl Default for LabelConfig {
+  fn default() -> Self {
+    Self {
+      blocking: vec!["do-not-merge".to_string()],
+      merge: vec![],
+      state_prefix: Some("cherry:".to_string()),
+    }
+  }
+}
+
+impl LabelConfig {
+  pub fn is_blocking(&self, label: &str) -> bool {
+    self.blocking.iter().any(|l| l == label)
+  }
+
+  pub fn is_merge(&self, label: &str) -> bool {
+    self.merge.iter().any(|l| l == label)
+  }
+
+  /// Blocking labels among `labels`.
+  pub fn blocking_labels<'a>(&self, labels: &'a [String]) -> Vec<&'a str> {
+    labels
+      .iter()
+      .filter(|l| self.is_blocking(l))
+      .map(String::as_str)
+      .collect()
+  }
+
+  pub fn state_label(&self, state: StateLabel) -> Option<String> {
+    self
+      .state_prefix
+      .as_ref()
+      .map(|prefix| format!("{}{}", prefix, state))
+  }
+
+  /// Whether `label` is one of the state labels managed by cherry.
+  pub fn is_state_label(&self, label: &str) -> bool {
+    StateLabel::ALL
+      .iter()
+      .any(|s| self.state_label(*s).as_deref() == Some(label))
+  }
+}
+
+/// Externally visible queue state of a PR, mirrored as a label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateLabel {
+  Requested,
+  Queued,
+  Merging,
+  Testing,
+  Split,
+  Failed,
+}
+
+impl StateLabel {
+  pub const ALL: [Self; 6] = [
+    Self::Requested,
+    Self::Queued,
+    Self::Merging,
+    Self::Testing,
+    Self::Split,
+    Self::Failed,
+  ];
+}
+
+impl fmt::Display for StateLabel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Requested => write!(f, "requested"),
+      Self::Queued => write!(f, "queued"),
+      Self::Merging => write!(f, "merging"),
+      Self::Testing => write!(f, "testing"),
+      Self::Split => write!(f, "split"),
+      Self::Failed => write!(f, "failed"),
+    }
+  }
+}
@@ -1,19 +1,68 @@
+use crate::clock::Clock;
+use crate::config::repo::{
+  ConfigCache, RepoConfig, RepoConfigError, SplitStrategy, Strategy, CONFIG_PATH,
+};
+use crate::control::command::{MergeOptions, MAX_PRIORITY};
 use crate::github::client::Client;
 use crate::github::client::ClientError;
-use crate::github::types::{PrState as GHPrState, Repository};
+use crate::github::types::{PrState as GHPrState, PullRequest, Repository, StatusState};
+use approval::Approval;
+use backend::{BackendError, MergeBackend};
+use checks::Outcome;
+use construct::{BatchPr, Construction};
+use group::{attempt_group, group_members};
+use health::Health;
+use label::StateLabel;
+use poll::{Action, AttemptRow, PrRow};
+use split::FailedPr;
 
+use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
 
-use chrono::Utc;
+use chrono::Duration;
 use futures::future::LocalBoxFuture;
-use log::info;
-use quaint::ast::{Comparable, Conjuctive, Delete, Insert, ParameterizedValue, Select, Update};
+use log::{error, info};
+use quaint::ast::{
+  Comparable, ConditionTree, Conjuctive, Delete, Insert, Orderable, ParameterizedValue, Select,
+  Update,
+};
 use quaint::connector::{Queryable, TransactionCapable};
 use thiserror::Error;
+use tokio::sync::Mutex;
 
+pub mod approval;
+pub mod backend;
+mod backport;
+mod batching;
+mod checks;
 pub mod command;
+mod construct;
+mod group;
+mod health;
+pub mod label;
+mod poll;
+mod post_merge;
+mod revert;
+mod shadow;
+mod split;
+mod stack;
+mod train;
+
+/// Status context used to report problems with the configuration file.
+const CONFIG_STATUS_CONTEXT: &str = "cherry/config";
+/// Number of split history entries shown by `cherry status`.
+const STATUS_HISTORY_LENGTH: usize = 10;
+/// Maximum number of PRs in a stack, to stop at cycles.
+const MAX_STACK_SIZE: usize = 20;
+/// Prefix of comments posted in shadow mode, unless disabled.
+const SHADOW_PREFIX: &str = "[shadow]";
+/// Number of days covered by `cherry report`.
+const SHADOW_REPORT_DAYS: i64 = 7;
+/// Priority of the PRs opened by `cherry revert queue`, above any other.
+const REVERT_PRIORITY: i64 = MAX_PRIORITY + 1;
 
 #[derive(Debug, Clone, Copy)]
 enum PrState {
@@ -54,6 +103,17 @@ impl<'a> Into<ParameterizedValue<'a>> for PrState {
   }
 }
 
+impl From<PrState> for StateLabel {
+  fn from(state: PrState) -> Self {
+    match state {
+      PrState::Requested => Self::Requested,
+      PrState::Queued => Self::Queued,
+      PrState::Merging => Self::Merging,
+      PrState::Split => Self::Split,
+    }
+  }
+}
+
 impl<'a> TryFrom<&ParameterizedValue<'a>> for PrState {
   type Error = ControllerError;
 
@@ -103,6 +163,16 @@ impl<'a> Into<ParameterizedValue<'a>> for MergeState {
   }
 }
 
+impl From<MergeState> for StateLabel {
+  fn from(state: MergeState) -> Self {
+    match state {
+      MergeState::Constructing | MergeState::Success => Self::Merging,
+      MergeState::Testing => Self::Testing,
+      MergeState::Split => Self::Split,
+    }
+  }
+}
+
 impl<'a> TryFrom<&ParameterizedValue<'a>> for MergeState {
   type Error = ControllerError;
 
@@ -125,6 +195,98 @@ pub enum ControllerError {
   InvalidPrState(String),
   #[error("invalid merge state: {0}")]
   InvalidMergeState(String),
+  #[error(transparent)]
+  Backend(#[from] BackendError),
+  #[error("invalid strategy: {0}")]
+  InvalidStrategy(String),
+  #[error("invalid {}: {0}", CONFIG_PATH)]
+  InvalidConfig(String),
+}
+
+/// Condition selecting the rows of `repo`.
+fn repo_row(repo: &Repository) -> ConditionTree<'_> {
+  "owner"
+    .equals(repo.owner.as_str())
+    .and("repo".equals(repo.repo.as_str()))
+}
+
+/// Condition selecting the row of the target branch `target` of `repo`.
+fn target_row<'a>(repo: &'a Repository, target: &'a str) -> ConditionTree<'a> {
+  "owner"
+    .equals(repo.owner.as_str())
+    .and("repo".equals(repo.repo.as_str()))
+    .and("base_ref".equals(target))
+}
+
+/// The failing checks at the head of `target`, if any.
+async fn target_health(
+  db: &impl Queryable,
+  repo: &Repository,
+  target: &str,
+) -> Result<Option<Health>, ControllerError> {
+  Ok(
+    db.select(Select::from_table("target_health").so_that(target_row(repo, target)))
+      .await?
+      .into_iter()
+      .next()
+      .map(|row| Health {
+        head_hash: row["head_hash"].to_string().unwrap_or_default(),
+        checks: row["checks"]
+          .to_string()
+          .unwrap_or_default()
+          .split(',')
+          .filter(|name| !name.is_empty())
+          .map(str::to_string)
+          .collect(),
+        attempt: row["attempt"].to_string(),
+        overridden_by: row["overridden_by"].to_string(),
+        since: row["timestamp"].as_i64().unwrap_or(0),
+      }),
+  )
+}
+
+/// Priority below which PRs of `repo` are held by a closed tree, and since
+/// when, if the tree is closed.
+async fn tree_closed_row(
+  db: &impl Queryable,
+  repo: &Repository,
+) -> Result<Option<(i64, i64)>, ControllerError> {
+  Ok(
+    db.select(
+      Select::from_table("tree_closed")
+        .so_that("owner".equals(repo.owner.as_str()))
+        .and_where("repo".equals(repo.repo.as_str())),
+    )
+    .await?
+    .into_iter()
+    .next()
+    .map(|row| {
+      (
+        row["priority"].as_i64().unwrap_or(0),
+        row["timestamp"].as_i64().unwrap_or(0),
+      )
+    }),
+  )
+}
+
+/// Priority below which PRs of `repo` are held, if the tree is closed.
+async fn tree_closed(
+  db: &impl Queryable,
+  repo: &Repository,
+) -> Result<Option<i64>, ControllerError> {
+  Ok(
+    tree_closed_row(db, repo)
+      .await?
+      .map(|(priority, _)| priority),
+  )
+}
+
+/// Condition selecting the row of a single PR.
+fn pr_row(repo: &Repository, pr: i64) -> ConditionTree<'_> {
+  "owner"
+    .equals(repo.owner.as_str())
+    .and("repo".equals(repo.repo.as_str()))
+    .and("number".equals(pr))
 }
 
 pub struct Controller<Q>
@@ -132,75 +294,742 @@ where
   Q: Queryable + TransactionCapable + 'static,
 {
   client: Client,
+  backend: Box<dyn MergeBackend>,
   db: Q,
+  configs: Arc<Mutex<ConfigCache>>,
+  clock: Arc<dyn Clock>,
 }
 
 impl<Q> Controller<Q>
 where
   Q: Queryable + TransactionCapable + 'static,
 {
-  pub async fn request(&self, repo: &Repository, pr: i64) -> Result<(), ControllerError> {
+  pub fn new(
+    client: Client,
+    backend: Box<dyn MergeBackend>,
+    db: Q,
+    configs: Arc<Mutex<ConfigCache>>,
+    clock: Arc<dyn Clock>,
+  ) -> Self {
+    Self {
+      client,
+      backend,
+      db,
+      configs,
+      clock,
+    }
+  }
+
+  /// Current time in epoch seconds, as stored in `timestamp` columns.
+  fn timestamp(&self) -> i64 {
+    self.clock.now().timestamp()
+  }
+
+  /// Comment on a PR or issue, marking the comment in shadow mode.
+  pub async fn comment(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    message: &str,
+  ) -> Result<(), ControllerError> {
+    let shadow = &self.config(repo).await?.shadow;
+    if shadow.enabled && shadow.prefix_comments {
+      let message = format!("{} {}", SHADOW_PREFIX, message);
+      self.client.comment_on_pr(repo, pr, &message).await?;
+    } else {
+      self.client.comment_on_pr(repo, pr, message).await?;
+    }
+    Ok(())
+  }
+
+  /// Whether to make a GitHub write in `repo`, described by `intent`.  In
+  /// shadow mode, the write is only recorded as an intent.
+  async fn should_write(
+    &self,
+    repo: &Repository,
+    pr: Option<i64>,
+    intent: impl FnOnce() -> String,
+  ) -> Result<bool, ControllerError> {
+    if !self.config(repo).await?.shadow.enabled {
+      return Ok(true);
+    }
+    let intent = intent();
+    info!("shadow intent in {}: {}", repo, intent);
+    self
+      .db
+      .insert(
+        Insert::single_into("shadow_intent")
+          .value("owner", repo.owner.as_str())
+          .value("repo", repo.repo.as_str())
+          .value("number", pr.map_or(ParameterizedValue::Null, Into::into))
+          .value("intent", intent)
+          .value("timestamp", self.timestamp())
+          .build(),
+      )
+      .await?;
+    Ok(false)
+  }
+
+  /// Configuration of `repo`, loaded from its default branch on first use.
+  /// An invalid configuration is reported on the head of the default branch,
+  /// and the last valid configuration saved in the database stays in effect.
+  /// Only if no valid configuration was ever seen is nothing done in `repo`
+  /// until a valid one is pushed.
+  pub async fn config(&self, repo: &Repository) -> Result<Arc<RepoConfig>, ControllerError> {
+    {
+      let configs = self.configs.lock().await;
+      if let Some(config) = configs.get(repo) {
+        return Ok(config);
+      }
+      if let Some(e) = configs.error(repo) {
+        return Err(ControllerError::InvalidConfig(e));
+      }
+    }
+    let default_branch = self.client.default_branch(repo).await?;
+    let head = self.client.branch_hash(repo, &default_branch).await?;
+    let content = self
+      .client
+      .file_contents(repo, CONFIG_PATH, head.as_deref())
+      .await?;
+    let config = match RepoConfig::load(content.as_deref(), &default_branch) {
+      Ok(config) => {
+        self
+          .save_config(repo, head.as_deref(), content.as_deref())
+          .await?;
+        config
+      }
+      Err(e) => {
+        error!("invalid configuration in {}: {}", repo, e);
+        // Whether the repository is in shadow mode is unknown, so the error
+        // is reported regardless.
+        if let Some(head) = &head {
+          self.report_invalid_config(repo, head, &e).await?;
+        }
+        match self.saved_config(repo).await? {
+          Some(config) => config,
+          None => {
+            self
+              .configs
+              .lock()
+              .await
+              .insert_error(repo.clone(), e.to_string());
+            return Err(ControllerError::InvalidConfig(e.to_string()));
+          }
+        }
+      }
+    };
+    let config = Arc::new(config);
+    self
+      .configs
+      .lock()
+      .await
+      .insert(repo.clone(), config.clone());
+    Ok(config)
+  }
+
+  /// Save the source of the valid configuration of `repo` loaded from
+  /// `commit_hash`, or `None` if the file does not exist, so that it stays in
+  /// effect across restarts if the file becomes invalid.
+  async fn save_config(
+    &self,
+    repo: &Repository,
+    commit_hash: Option<&str>,
+    content: Option<&[u8]>,
+  ) -> Result<(), ControllerError> {
+    let tx = self.db.start_transaction().await?;
+    tx.delete(Delete::from_table("repo_config").so_that(repo_row(repo)))
+      .await?;
+    tx.insert(
+      Insert::single_into("repo_config")
+        .value("repo_id", repo.id)
+        .value("owner", repo.owner.as_str())
+        .value("repo", repo.repo.as_str())
+        .value(
+          "commit_hash",
+          commit_hash.map_or(ParameterizedValue::Null, Into::into),
+        )
+        .value(
+          "content",
+          match content {
+            Some(content) => String::from_utf8_lossy(content).into_owned().into(),
+            None => ParameterizedValue::Null,
+          },
+        )
+        .value("timestamp", self.timestamp())
+        .build(),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+  }
+
+  /// The last valid configuration of `repo` saved by `save_config`, if any.
+  async fn saved_config(&self, repo: &Repository) -> Result<Option<RepoConfig>, ControllerError> {
+    let row = match self
+      .db
+      .select(Select::from_table("repo_config").so_that(repo_row(repo)))
+      .await?
+      .into_iter()
+      .next()
+    {
+      Some(row) => row,
+      None => return Ok(None),
+    };
+    let commit_hash = row["commit_hash"].to_string().unwrap_or_default();
+    let parsed = match row["content"].to_string() {
+      Some(content) => RepoConfig::parse(content.as_bytes()),
+      None => Ok(RepoConfig::default()),
+    };
+    match parsed {
+      Ok(config) => {
+        info!(
+          "using the last valid configuration of {} from {}",
+          repo, commit_hash
+        );
+        Ok(Some(config))
+      }
+      Err(e) => {
+        error!(
+          "saved configuration of {} from {} is invalid: {}",
+          repo, commit_hash, e
+        );
+        Ok(None)
+      }
+    }
+  }
+
+  /// Report an invalid configuration on `commit_hash`.
+  async fn report_invalid_config(
+    &self,
+    repo: &Repository,
+    commit_hash: &str,
+    e: &RepoConfigError,
+  ) -> Result<(), ControllerError> {
+    self
+      .client
+      .set_status(
+        repo,
+        commit_hash,
+        StatusState::Error,
+        CONFIG_STATUS_CONTEXT,
+        format!("Invalid {}: {}", CONFIG_PATH, e).as_str(),
+      )
+      .await?;
+    Ok(())
+  }
+
+  /// Configuration of the queue into `target` in `repo`.
+  async fn target_config(
+    &self,
+    repo: &Repository,
+    target: &str,
+  ) -> Result<RepoConfig, ControllerError> {
+    let config = self.config(repo).await?;
+    let default_branch = self.client.default_branch(repo).await?;
+    let config = config.for_target(target, &default_branch);
+    // checked when the configuration is loaded, but a staging branch which is
+    // the target itself must never be force-pushed
+    if config.staging_branch == target {
+      return Err(ControllerError::InvalidConfig(format!(
+        "staging branch `{}` of `{}` is a target branch",
+        target, target
+      )));
+    }
+    Ok(config)
+  }
+
+  /// Reload the configuration of `repo` after a push of `commit_hash` to the
+  /// default branch.  An invalid configuration is reported on the commit and
+  /// the previous configuration, or the last valid one saved in the
+  /// database, stays in effect.
+  pub async fn reload_config(
+    &self,
+    repo: &Repository,
+    commit_hash: &str,
+  ) -> Result<(), ControllerError> {
+    info!("reloading configuration: {} {}", repo, commit_hash);
+    let content = self
+      .client
+      .file_contents(repo, CONFIG_PATH, Some(commit_hash))
+      .await?;
+    let default_branch = self.client.default_branch(repo).await?;
+    match RepoConfig::load(content.as_deref(), &default_branch) {
+      Ok(config) => {
+        self
+          .save_config(repo, Some(commit_hash), content.as_deref())
+          .await?;
+        self
+          .configs
+          .lock()
+          .await
+          .insert(repo.clone(), Arc::new(config));
+        let intent = || format!("report the configuration loaded on {}", commit_hash);
+        if !self.should_write(repo, None, intent).await? {
+          return Ok(());
+        }
+        self
+          .client
+          .set_status(
+            repo,
+            commit_hash,
+            StatusState::Success,
+            CONFIG_STATUS_CONTEXT,
+            "Configuration loaded",
+          )
+          .await?;
+      }
+      Err(e) => {
+        error!("invalid configuration in {} {}: {}", repo, commit_hash, e);
+        let mut loaded = self.configs.lock().await.get(repo).is_some();
+        if !loaded {
+          match self.saved_config(repo).await? {
+            Some(config) => {
+              self
+                .configs
+                .lock()
+                .await
+                .insert(repo.clone(), Arc::new(config));
+              loaded = true;
+            }
+            None => self
+              .configs
+              .lock()
+              .await
+              .insert_error(repo.clone(), e.to_string()),
+          }
+        }
+        let intent = || format!("report the invalid configuration on {}", commit_hash);
+        if loaded && !self.should_write(repo, None, intent).await? {
+          return Ok(());
+        }
+        self.report_invalid_config(repo, commit_hash, &e).await?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Unmet conditions which prevent the PR from being queued.
+  async fn blockers(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    pr_info: &PullRequest,
+  ) -> Result<Vec<String>, ControllerError> {
+    let config = self.config(repo).await?;
+    let mut blockers = vec![];
+    if pr_info.draft {
+      blockers.push("PR not marked as draft".to_string());
+    }
+    for label in config.labels.blocking_labels(&pr_info.labels) {
+      blockers.push(format!("PR not labeled `{}`", label));
+    }
+    if config.approval.count > 0 {
+      let reviews = self.client.reviews(repo, pr).await?;
+      let approval = Approval::evaluate(&config.approval, &reviews, &pr_info.commit_hash);
+      if !approval.approved(&config.approval) {
+        blockers.push(format!(
+          "PR approved by at least {} reviewer(s), with no changes requested",
+          config.approval.count
+        ));
+      }
+    }
+    Ok(blockers)
+  }
+
+  /// Replace the state label on a PR, or remove it if `state` is `None`.
+  async fn sync_state_label(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    state: Option<StateLabel>,
+  ) -> Result<(), ControllerError> {
+    let config = self.config(repo).await?;
+    if config.labels.state_prefix.is_none() {
+      return Ok(());
+    }
+    let wanted = state.and_then(|s| config.labels.state_label(s));
+    let current = self.client.labels(repo, pr).await?;
+    for label in &current {
+      if config.labels.is_state_label(label)
+        && Some(label) != wanted.as_ref()
+        && self
+          .should_write(repo, Some(pr), || format!("remove label `{}`", label))
+          .await?
+      {
+        self.client.remove_label(repo, pr, label).await?;
+      }
+    }
+    if let Some(wanted) = wanted {
+      if !current.contains(&wanted)
+        && self
+          .should_write(repo, Some(pr), || format!("add label `{}`", wanted))
+          .await?
+      {
+        self.client.add_labels(repo, pr, &[wanted.as_str()]).await?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Update the state label on every PR in a merge attempt.
+  async fn sync_attempt_state_label(
+    &self,
+    repo: &Repository,
+    attempt: &str,
+    state: MergeState,
+  ) -> Result<(), ControllerError> {
+    let rows = self
+      .db
+      .select(
+        Select::from_table("pull_request")
+          .column("number")
+          .so_that("merge_attempt".equals(attempt)),
+      )
+      .await?;
+    for row in rows {
+      if let Some(pr) = row["number"].as_i64() {
+        self.sync_state_label(repo, pr, Some(state.into())).await?;
+      }
+    }
+    Ok(())
+  }
+
+  pub async fn request(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    options: MergeOptions,
+  ) -> Result<(), ControllerError> {
     info!("request: {} #{}", repo, pr);
     let pr_info = self.client.pr_info(repo, pr).await?;
+    let default_branch = self.client.default_branch(repo).await?;
+    let config = self.config(repo).await?;
+    let stack = match self
+      .lower_stack(repo, &config, &default_branch, pr, &pr_info)
+      .await?
+    {
+      Ok(stack) => stack,
+      Err(error) => {
+        self.comment(repo, pr, error.as_str()).await?;
+        return Ok(());
+      }
+    };
+    // a stack is merged into the base of its bottom PR
+    let target = match stack.first() {
+      Some((_, bottom)) => bottom.base_ref.clone(),
+      None => pr_info.base_ref.clone(),
+    };
+    if !config.allows_target(&target, &default_branch) {
+      self
+        .comment(
+          repo,
+          pr,
+          format!(
+            "Error: PRs into `{}` are not merged by cherry in this repository.",
+            target
+          )
+          .as_str(),
+        )
+        .await?;
+      return Ok(());
+    }
+    let config = config.for_target(&target, &default_branch);
+    let strategy = options.strategy;
+    if let Some(strategy) = strategy {
+      if !config.allows_strategy(strategy) {
+        self
+          .comment(
+            repo,
+            pr,
+            format!(
+              "Error: the `{}` strategy is not allowed in this repository.",
+              strategy
+            )
+            .as_str(),
+          )
+          .await?;
+        return Ok(());
+      }
+    }
 
     match pr_info.state {
       GHPrState::Open => (),
       GHPrState::Closed => {
         self
-          .client
-          .comment_on_pr(repo, pr, "Error: Refusing to merge PR in closed state.")
+          .comment(repo, pr, "Error: Refusing to merge PR in closed state.")
           .await?;
         return Ok(());
       }
     }
 
-    // TODO readiness check
-    let ready = !pr_info.draft;
+    if !options.with.is_empty() && !stack.is_empty() {
+      self
+        .comment(
+          repo,
+          pr,
+          "Error: a stacked PR cannot be linked with PRs in other repositories.",
+        )
+        .await?;
+      return Ok(());
+    }
+    let linked = match self.linked_prs(repo, &options.with).await? {
+      Ok(linked) => linked,
+      Err(error) => {
+        self.comment(repo, pr, error.as_str()).await?;
+        return Ok(());
+      }
+    };
+
+    let blockers = self.blockers(repo, pr, &pr_info).await?;
+    let ready = blockers.is_empty();
 
     let state = if ready {
       PrState::Queued
     } else {
       PrState::Requested
     };
-    match self
-      .db
-      .insert(
-        Insert::single_into("pull_request")
-          .value("owner", repo.owner.as_str())
-          .value("repo", repo.repo.as_str())
-          .value("number", pr)
-          .value("commit_hash", pr_info.commit_hash)
-          .value("state", state)
-          .value("timestamp", Utc::now().timestamp())
+    let strategy = strategy.map_or(ParameterizedValue::Null, |s| s.to_string().into());
+    let tx = self.db.start_transaction().await?;
+    for other in &linked {
+      let rows = tx
+        .select(Select::from_table("pull_request").so_that(pr_row(&other.repo, other.number)))
+        .await?;
+      if !rows.is_empty() {
+        tx.commit().await?;
+        let message = format!(
+          "Error: {}#{} is already being merged.",
+          other.repo, other.number
+        );
+        self.comment(repo, pr, message.as_str()).await?;
+        return Ok(());
+      }
+    }
+    let mut rows = vec![];
+    for number in stack.iter().map(|(number, _)| *number).chain(Some(pr)) {
+      rows.push(
+        tx.select(Select::from_table("pull_request").so_that(pr_row(repo, number)))
+          .await?
+          .into_iter()
+          .next(),
+      );
+    }
+    if rows.last().unwrap().is_some() {
+      tx.commit().await?;
+      self
+        .comment(repo, pr, "This PR is already being merged.")
+        .await?;
+      return Ok(());
+    }
+    // PRs of the stack already queued on their own join it
+    for ((number, _), row) in stack.iter().zip(&rows) {
+      if let Some(row) = row {
+        let state: PrState = (&row["state"]).try_into()?;
+        let joinable = matches!(state, PrState::Requested | PrState::Queued)
+          && row["merge_attempt"].is_null()
+          && row["stack_below"].is_null();
+        if !joinable {
+          tx.commit().await?;
+          let message = format!(
+            "Error: this PR is stacked on #{}, which is already being merged.  Request the merge again once it has landed.",
+            number
+          );
+          self.comment(repo, pr, message.as_str()).await?;
+          return Ok(());
+        }
+      }
+    }
+    let group = if linked.is_empty() {
+      None
+    } else {
+      let group = uuid::Uuid::new_v4().to_string();
+      tx.insert(
+        Insert::single_into("merge_group")
+          .value("id", group.as_str())
+          .value("size", linked.len() as i64 + 1)
+          .value("timestamp", self.timestamp())
           .build(),
       )
-      .await
-    {
-      Ok(_) => (),
-      Err(quaint::error::Error::UniqueConstraintViolation { .. }) => {
-        self
-          .client
-          .comment_on_pr(repo, pr, "This PR is already being merged.")
-          .await?;
-        return Ok(());
+      .await?;
+      for other in &linked {
+        let state = if other.blockers.is_empty() {
+          PrState::Queued
+        } else {
+          PrState::Requested
+        };
+        tx.insert(
+          Insert::single_into("pull_request")
+            .value("repo_id", other.repo.id)
+            .value("owner", other.repo.owner.as_str())
+            .value("repo", other.repo.repo.as_str())
+            .value("number", other.number)
+            .value("commit_hash", other.info.commit_hash.as_str())
+            .value("base_ref", other.info.base_ref.as_str())
+            .value("state", state)
+            .value("merge_group", group.as_str())
+            .value("timestamp", self.timestamp())
+            .build(),
+        )
+        .await?;
       }
-      Err(e) => {
-        return Err(e.into());
+      Some(group)
+    };
+    let group_value = group
+      .as_deref()
+      .map_or(ParameterizedValue::Null, ParameterizedValue::from);
+    // the PRs below get the priority of the top, so that it is never batched
+    // without them
+    let below_priorities: Vec<Option<i64>> = rows[..stack.len()]
+      .iter()
+      .map(|row| row.as_ref().and_then(|row| row["priority"].as_i64()))
+      .collect();
+    let priorities = stack::priorities(options.priority, &below_priorities)
+      .into_iter()
+      .chain(Some(options.priority));
+    let mut below = None;
+    for (((number, info), row), priority) in stack
+      .iter()
+      .map(|(n, i)| (*n, i))
+      .chain(Some((pr, &pr_info)))
+      .zip(&rows)
+      .zip(priorities)
+    {
+      let below_value = below.map_or(ParameterizedValue::Null, ParameterizedValue::from);
+      if row.is_some() {
+        tx.update(
+          Update::table("pull_request")
+            .set("base_ref", target.as_str())
+            .set("state", state)
+            .set("strategy", strategy.clone())
+            .set("priority", priority)
+            .set("stack_below", below_value)
+            .set("timestamp", self.timestamp())
+            .so_that(pr_row(repo, number)),
+        )
+        .await?;
+      } else {
+        tx.insert(
+          Insert::single_into("pull_request")
+            .value("repo_id", repo.id)
+            .value("owner", repo.owner.as_str())
+            .value("repo", repo.repo.as_str())
+            .value("number", number)
+            .value("commit_hash", info.commit_hash.as_str())
+            .value("base_ref", target.as_str())
+            .value("state", state)
+            .value("strategy", strategy.clone())
+            .value("alone", i64::from(number == pr && options.alone))
+            .value("priority", priority)
+            .value("stack_below", below_value)
+            .value("merge_group", group_value.clone())
+            .value("timestamp", self.timestamp())
+            .build(),
+        )
+        .await?;
       }
+      below = Some(number);
     }
+    tx.commit().await?;
     info!("added {} #{} in {} state", repo, pr, state);
+    if let Some(group) = &group {
+      info!(
+        "linked {} #{} with {:?} in merge group {}",
+        repo,
+        pr,
+        linked
+          .iter()
+          .map(|other| format!("{}#{}", other.repo, other.number))
+          .collect::<Vec<_>>(),
+        group
+      );
+      self.announce_group(repo, pr, &linked).await?;
+    }
+    for (number, _) in &stack {
+      self
+        .sync_state_label(repo, *number, Some(state.into()))
+        .await?;
+    }
+    self.sync_state_label(repo, pr, Some(state.into())).await?;
+    if !stack.is_empty() {
+      let numbers: Vec<i64> = stack.iter().map(|(number, _)| *number).collect();
+      self
+        .comment(
+          repo,
+          pr,
+          format!(
+            "This PR is stacked on {}, which will be merged with it in one batch, in order.",
+            pr_list(&numbers)
+          )
+          .as_str(),
+        )
+        .await?;
+    }
     if ready {
+      if let Some(hold) = self
+        .hold_reason(repo, &config, &target, options.priority)
+        .await?
+      {
+        let message = format!(
+          "This PR is queued, but {}.  It will be merged once that ends, and you will be told here.",
+          hold
+        );
+        self.comment(repo, pr, message.as_str()).await?;
+      }
       self.construct(repo).await
     } else {
-      // TODO list applicable conditions
-      self
-        .client
-        .comment_on_pr(repo, pr, "This PR cannot be merged yet.  It will be merged automatically once the following conditions are resolved:\n- PR not marked as draft")
+      let conditions: String = blockers.iter().map(|b| format!("\n- {}", b)).collect();
+      self.comment(repo, pr, format!("This PR cannot be merged yet.  It will be merged automatically once the following conditions are resolved:{}", conditions).as_str())
         .await?;
       Ok(())
     }
   }
 
+  /// PRs which `pr` is stacked on, bottom first, found from base and head
+  /// branches or from `depends on #N` lines.  `Err` explains why the stack
+  /// cannot be merged.
+  async fn lower_stack(
+    &self,
+    repo: &Repository,
+    config: &RepoConfig,
+    default_branch: &str,
+    pr: i64,
+    pr_info: &PullRequest,
+  ) -> Result<Result<Vec<(i64, PullRequest)>, String>, ControllerError> {
+    let mut stack: Vec<(i64, PullRequest)> = vec![];
+    let mut number = pr;
+    loop {
+      let info = stack.last().map_or(pr_info, |(_, info)| info);
+      let below = match stack::depends_on(&info.body) {
+        Some(below) => Some(below),
+        None if config.allows_target(&info.base_ref, default_branch) => None,
+        None => self.client.pr_with_head(repo, &info.base_ref).await?,
+      };
+      let below = match below {
+        Some(below) => below,
+        None => break,
+      };
+      if below == pr || stack.iter().any(|(n, _)| *n == below) || stack.len() >= MAX_STACK_SIZE {
+        return Ok(Err(format!(
+          "Error: the stack below #{} is too deep or circular.",
+          number
+        )));
+      }
+      let below_info = self.client.pr_info(repo, below).await?;
+      if below_info.merged {
+        break;
+      }
+      if matches!(below_info.state, GHPrState::Closed) {
+        return Ok(Err(format!(
+          "Error: #{} is stacked on #{}, which is closed.",
+          number, below
+        )));
+      }
+      stack.push((below, below_info));
+      number = below;
+    }
+    stack.reverse();
+    Ok(Ok(stack))
+  }
+
   pub async fn initiate(&self, repo: &Repository, pr: i64) -> Result<(), ControllerError> {
     info!("initiate: {} #{}", repo, pr);
     let pr_info = self.client.pr_info(repo, pr).await?;
@@ -219,14 +1048,27 @@ where
             ),
           )
           .await?;
+        self.sync_state_label(repo, pr, None).await?;
         return Ok(());
       }
     }
 
-    // TODO readiness check
-    let ready = !pr_info.draft;
-    if !ready {
-      return Ok(());
+    // a stack is queued once the PRs at its top are ready
+    let links = stack_links(&self.db, repo).await?;
+    let members = stack::members(&links, pr);
+    for &top in members
+      .iter()
+      .filter(|&&m| !links.iter().any(|l| l.below == Some(m)))
+    {
+      let blockers = if top == pr {
+        self.blockers(repo, pr, &pr_info).await?
+      } else {
+        let top_info = self.client.pr_info(repo, top).await?;
+        self.blockers(repo, top, &top_info).await?
+      };
+      if !blockers.is_empty() {
+        return Ok(());
+      }
     }
 
     let tx = self.db.start_transaction().await?;
@@ -260,102 +1102,2659 @@ where
       .await?;
       tx.commit().await?;
       self
-        .client
-        .comment_on_pr(
+        .comment(
           repo,
           pr,
           "Merge cancelled: a new commit was pushed to the PR.",
         )
         .await?;
+      self.sync_state_label(repo, pr, None).await?;
+      return Ok(());
     }
 
-    tx.update(
-      Update::table("pull_request")
-        .set("state", PrState::Queued)
-        .set("timestamp", Utc::now().timestamp())
-        .so_that(
-          "owner"
-            .equals(repo.owner.as_str())
-            .and("repo".equals(repo.repo.as_str()))
-            .and("number".equals(pr)),
-        ),
-    )
-    .await?;
+    let mut queued = vec![];
+    for &member in &members {
+      let rows = tx
+        .select(Select::from_table("pull_request").so_that(pr_row(repo, member)))
+        .await?;
+      match rows.first() {
+        Some(row) if matches!((&row["state"]).try_into()?, PrState::Requested) => (),
+        _ => continue,
+      }
+      tx.update(
+        Update::table("pull_request")
+          .set("state", PrState::Queued)
+          .set("timestamp", self.timestamp())
+          .so_that(pr_row(repo, member)),
+      )
+      .await?;
+      queued.push(member);
+    }
     tx.commit().await?;
-    info!("queued {} #{}", repo, pr);
+    info!("queued {} {:?}", repo, queued);
+    for member in queued {
+      self
+        .sync_state_label(repo, member, Some(PrState::Queued.into()))
+        .await?;
+    }
+    self.construct(repo).await
+  }
+
+  pub async fn labeled(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    label: &str,
+  ) -> Result<(), ControllerError> {
+    let config = self.config(repo).await?;
+    if config.labels.is_merge(label) {
+      info!("merge label `{}` added to {} #{}", label, repo, pr);
+      self.request(repo, pr, MergeOptions::default()).await?;
+    }
+    if config.labels.is_blocking(label) {
+      self.hold(repo, pr, label).await?;
+    }
     Ok(())
   }
 
-  pub async fn construct(&self, repo: &Repository) -> Result<(), ControllerError> {
+  pub async fn unlabeled(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    label: &str,
+  ) -> Result<(), ControllerError> {
+    if self.config(repo).await?.labels.is_blocking(label) {
+      info!("blocking label `{}` removed from {} #{}", label, repo, pr);
+      self.initiate(repo, pr).await?;
+    }
+    Ok(())
+  }
+
+  /// Move a QUEUED PR back to REQUESTED because a blocking label was added.
+  async fn hold(&self, repo: &Repository, pr: i64, label: &str) -> Result<(), ControllerError> {
     let tx = self.db.start_transaction().await?;
-    if !tx
-      .select(
-        Select::from_table("merge_attempt")
+    let rows = tx
+      .select(Select::from_table("pull_request").so_that(pr_row(repo, pr)))
+      .await?;
+    let queued = match rows.first() {
+      Some(row) => matches!((&row["state"]).try_into()?, PrState::Queued),
+      None => false,
+    };
+    if !queued {
+      tx.commit().await?;
+      return Ok(());
+    }
+    tx.update(
+      Update::table("pull_request")
+        .set("state", PrState::Requested)
+        .set("timestamp", self.timestamp())
+        .so_that(pr_row(repo, pr)),
+    )
+    .await?;
+    tx.commit().await?;
+    info!("held {} #{} by label `{}`", repo, pr, label);
+    self
+      .sync_state_label(repo, pr, Some(PrState::Requested.into()))
+      .await?;
+    self
+      .comment(
+        repo,
+        pr,
+        format!(
+          "Merge on hold: PR labeled `{}`.  It will be queued again once the label is removed.",
+          label
+        )
+        .as_str(),
+      )
+      .await?;
+    Ok(())
+  }
+
+  /// Move a PR whose base branch changed to `base` to the queue into
+  /// `base`, or cancel its merge if PRs into `base` are not merged.
+  pub async fn retarget(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    base: &str,
+  ) -> Result<(), ControllerError> {
+    let config = self.config(repo).await?;
+    let default_branch = self.client.default_branch(repo).await?;
+    let allowed = config.allows_target(base, &default_branch);
+    let tx = self.db.start_transaction().await?;
+    let rows = tx
+      .select(Select::from_table("pull_request").so_that(pr_row(repo, pr)))
+      .await?;
+    let (state, attempt, grouped) = match rows.into_iter().next() {
+      Some(row) if row["base_ref"].to_string().as_deref() != Some(base) => (
+        PrState::try_from(&row["state"])?,
+        row["merge_attempt"].to_string(),
+        !row["merge_group"].is_null(),
+      ),
+      _ => {
+        tx.commit().await?;
+        return Ok(());
+      }
+    };
+    // the PRs of a merge group were linked for their targets at the time
+    let allowed = allowed && !grouped;
+    info!("retarget: {} #{} to `{}`", repo, pr, base);
+    let mut split = vec![];
+    if let Some(attempt) = &attempt {
+      let reason = format!("its base branch changed to `{}`", base);
+      split = self.leave_attempt(&tx, repo, pr, attempt, &reason).await?;
+    }
+    let state = match state {
+      PrState::Requested => PrState::Requested,
+      _ => PrState::Queued,
+    };
+    if allowed {
+      tx.update(
+        Update::table("pull_request")
+          .set("base_ref", base)
+          .set("state", state)
+          .set("merge_attempt", ParameterizedValue::Null)
+          .set("timestamp", self.timestamp())
+          .so_that(pr_row(repo, pr)),
+      )
+      .await?;
+    } else {
+      tx.delete(Delete::from_table("pull_request").so_that(pr_row(repo, pr)))
+        .await?;
+    }
+    tx.commit().await?;
+
+    for other in split {
+      self
+        .sync_state_label(repo, other, Some(PrState::Split.into()))
+        .await?;
+    }
+    let (label, message) = if allowed {
+      (
+        Some(state.into()),
+        format!(
+          "The base branch changed, so this PR moved to the queue into `{}`.",
+          base
+        ),
+      )
+    } else if grouped {
+      (
+        None,
+        "Merge cancelled: the base branch changed while this PR was linked with PRs in other repositories.  Request the merge again to land them together.".to_string(),
+      )
+    } else {
+      (
+        None,
+        format!(
+          "Merge cancelled: PRs into `{}` are not merged by cherry in this repository.",
+          base
+        ),
+      )
+    };
+    self.sync_state_label(repo, pr, label).await?;
+    self.comment(repo, pr, message.as_str()).await?;
+    self.construct(repo).await
+  }
+
+  /// Take a PR out of its merge attempt, because `reason`.  The rest of the
+  /// attempt, and the attempts of the train built on it, go back to SPLIT
+  /// to be rebuilt.  Returns the PRs sent back to SPLIT.
+  async fn leave_attempt(
+    &self,
+    db: &impl Queryable,
+    repo: &Repository,
+    pr: i64,
+    attempt: &str,
+    reason: &str,
+  ) -> Result<Vec<i64>, ControllerError> {
+    self
+      .record_split(
+        db,
+        repo,
+        pr,
+        attempt,
+        None,
+        &format!("left because {}", reason),
+      )
+      .await?;
+    match attempt_state(db, attempt).await? {
+      None | Some(MergeState::Split) => return Ok(vec![]),
+      Some(_) => (),
+    }
+    let mut split = self
+      .rebuild_after(db, repo, attempt, &format!("lost #{}", pr))
+      .await?;
+    db.update(
+      Update::table("merge_attempt")
+        .set("state", MergeState::Split)
+        .set("base_attempt", ParameterizedValue::Null)
+        .set("staging_hash", ParameterizedValue::Null)
+        .set("timestamp", self.timestamp())
+        .so_that("id".equals(attempt)),
+    )
+    .await?;
+    let rows = db
+      .select(Select::from_table("pull_request").so_that("merge_attempt".equals(attempt)))
+      .await?;
+    let history = format!("rebuilt without #{}, which left because {}", pr, reason);
+    for row in rows {
+      let other = row["number"].as_i64().unwrap();
+      if other == pr {
+        continue;
+      }
+      db.update(
+        Update::table("pull_request")
+          .set("state", PrState::Split)
+          .set("timestamp", self.timestamp())
+          .so_that(pr_row(repo, other)),
+      )
+      .await?;
+      self
+        .record_split(db, repo, other, attempt, Some(attempt), &history)
+        .await?;
+      split.push(other);
+    }
+    Ok(split)
+  }
+
+  /// Run Construct in `repo`, then in the other repositories with PRs of a
+  /// merge group which became ready or was cancelled.
+  pub async fn construct(&self, repo: &Repository) -> Result<(), ControllerError> {
+    let mut repos = vec![repo.clone()];
+    let mut result = Ok(());
+    let mut i = 0;
+    while i < repos.len() {
+      let repo = repos[i].clone();
+      i += 1;
+      match self.construct_repo(&repo).await {
+        Ok(others) => {
+          for other in others {
+            if !repos.contains(&other) {
+              repos.push(other);
+            }
+          }
+        }
+        Err(e) => {
+          error!("constructing merge attempts in {}: {}", repo, e);
+          result = Err(e);
+        }
+      }
+    }
+    result
+  }
+
+  /// Run Construct on the queue of each target branch with PRs in `repo`.
+  /// An error in one queue does not hold up the others.  Returns the other
+  /// repositories whose queues changed because of merge groups.
+  async fn construct_repo(&self, repo: &Repository) -> Result<Vec<Repository>, ControllerError> {
+    self.drop_orphans(repo).await?;
+    let mut others = self.drop_broken_groups(repo).await?;
+    let mut targets = vec![];
+    for table in &["pull_request", "merge_attempt"] {
+      let rows = self
+        .db
+        .select(
+          Select::from_table(*table)
+            .column("base_ref")
+            .so_that("owner".equals(repo.owner.as_str()))
+            .and_where("repo".equals(repo.repo.as_str())),
+        )
+        .await?;
+      targets.extend(
+        rows
+          .into_iter()
+          .filter_map(|row| row["base_ref"].to_string()),
+      );
+    }
+    targets.sort();
+    targets.dedup();
+    let mut result = Ok(());
+    for target in &targets {
+      loop {
+        match self.construct_once(repo, target).await {
+          Ok(true) => (),
+          Ok(false) => break,
+          Err(e) => {
+            error!("constructing merge into `{}` in {}: {}", target, repo, e);
+            result = Err(e);
+            break;
+          }
+        }
+      }
+    }
+    result?;
+    others.extend(self.ready_group_repos(repo).await?);
+    Ok(others)
+  }
+
+  /// Drop the PRs stacked on a PR which left the queue without landing.
+  async fn drop_orphans(&self, repo: &Repository) -> Result<(), ControllerError> {
+    let tx = self.db.start_transaction().await?;
+    let links = stack_links(&tx, repo).await?;
+    let orphans = stack::orphans(&links);
+    let mut split = vec![];
+    let mut dropped = vec![];
+    for link in links.iter().filter(|l| orphans.contains(&l.number)) {
+      let below = link.below.unwrap();
+      let rows = tx
+        .select(Select::from_table("pull_request").so_that(pr_row(repo, link.number)))
+        .await?;
+      let attempt = rows
+        .into_iter()
+        .next()
+        .and_then(|row| row["merge_attempt"].to_string());
+      if let Some(attempt) = attempt {
+        let reason = format!("#{} below it left the queue", below);
+        split.extend(
+          self
+            .leave_attempt(&tx, repo, link.number, &attempt, &reason)
+            .await?,
+        );
+      }
+      tx.delete(Delete::from_table("pull_request").so_that(pr_row(repo, link.number)))
+        .await?;
+      dropped.push((link.number, below));
+    }
+    tx.commit().await?;
+    if dropped.is_empty() {
+      return Ok(());
+    }
+    info!("dropped orphaned stacked PRs in {}: {:?}", repo, dropped);
+
+    for pr in split.into_iter().filter(|pr| !orphans.contains(pr)) {
+      self
+        .sync_state_label(repo, pr, Some(PrState::Split.into()))
+        .await?;
+    }
+    for (pr, below) in dropped {
+      self.sync_state_label(repo, pr, None).await?;
+      self
+        .comment(
+          repo,
+          pr,
+          format!(
+            "Merge cancelled: this PR is stacked on #{}, which left the queue without landing.",
+            below
+          )
+          .as_str(),
+        )
+        .await?;
+    }
+    Ok(())
+  }
+
+  /// Construct one merge attempt into `target`.  Returns whether Construct
+  /// should run again, because the attempt ended without anything to test,
+  /// or because the train may have room for another attempt.
+  async fn construct_once(&self, repo: &Repository, target: &str) -> Result<bool, ControllerError> {
+    let config = self.target_config(repo, target).await?;
+    if let Some(window) = config.active_freeze(target, self.clock.now()) {
+      info!(
+        "not constructing in {}: `{}` is frozen {}",
+        repo, target, window
+      );
+      self.record_freeze(repo, target).await?;
+      return Ok(false);
+    }
+    if let Some(health) = target_health(&self.db, repo, target).await? {
+      if health.pauses() {
+        info!("not constructing in {}: {}", repo, health.hold(target));
+        return Ok(false);
+      }
+    }
+    let tx = self.db.start_transaction().await?;
+    let attempts = tx
+      .select(
+        Select::from_table("merge_attempt")
           .so_that("owner".equals(repo.owner.as_str()))
           .and_where("repo".equals(repo.repo.as_str()))
-          .and_where("state".not_equals(MergeState::Split)),
+          .and_where("base_ref".equals(target))
+          .order_by("timestamp".ascend()),
       )
-      .await?
-      .is_empty()
+      .await?;
+    let attempts = attempts
+      .into_iter()
+      .map(|row| {
+        Ok(train::Attempt {
+          id: row["id"].to_string().unwrap(),
+          state: (&row["state"]).try_into()?,
+          base_attempt: row["base_attempt"].to_string(),
+          // NULL in shadow mode, where nothing is constructed
+          staging_hash: row["staging_hash"].to_string().unwrap_or_default(),
+        })
+      })
+      .collect::<Result<Vec<_>, ControllerError>>()?;
+    // speculatively build on the last attempt of the train, if any
+    let train::Next {
+      split_attempt,
+      base,
+    } = match train::next(config.train.depth, &attempts) {
+      Some(next) => next,
+      None => {
+        tx.commit().await?;
+        info!("not constructing merge attempt because merge attempt is already in progress");
+        return Ok(false);
+      }
+    };
+
+    let (id, rows) = match split_attempt {
+      Some(id) => {
+        let rows = tx
+          .select(
+            Select::from_table("pull_request")
+              .so_that("merge_attempt".equals(id.as_str()))
+              .order_by("timestamp".ascend())
+              .order_by("number".ascend()),
+          )
+          .await?;
+        if rows.is_empty() {
+          tx.delete(Delete::from_table("merge_attempt").so_that("id".equals(id.as_str())))
+            .await?;
+          tx.commit().await?;
+          return Ok(true);
+        }
+        (id, rows.into_iter().collect())
+      }
+      None => {
+        let rows = tx
+          .select(
+            Select::from_table("pull_request")
+              .so_that("owner".equals(repo.owner.as_str()))
+              .and_where("repo".equals(repo.repo.as_str()))
+              .and_where("base_ref".equals(target))
+              .and_where("state".equals(PrState::Queued))
+              .order_by("priority".descend())
+              .order_by("timestamp".ascend())
+              .order_by("number".ascend()),
+          )
+          .await?;
+        // only the PRs with the highest priority are batched, if the tree is
+        // open to them
+        let closed = tree_closed(&tx, repo).await?.unwrap_or(0);
+        let priority = rows.first().and_then(|row| row["priority"].as_i64());
+        let rows: Vec<_> = rows
+          .into_iter()
+          .filter(|row| row["priority"].as_i64() == priority)
+          .filter(|row| row["priority"].as_i64().unwrap_or(0) >= closed)
+          .collect();
+        let links = stack_links(&tx, repo).await?;
+        let number = |row: &quaint::connector::ResultRow| row["number"].as_i64().unwrap();
+        let (oldest_stack, oldest_group) = match rows.first() {
+          Some(row) => (
+            stack::members(&links, row["number"].as_i64().unwrap()),
+            row["merge_group"].to_string(),
+          ),
+          None => {
+            tx.commit().await?;
+            return Ok(false);
+          }
+        };
+        let group_ready = match &oldest_group {
+          Some(group) => {
+            let (size, members) = group_members(&tx, group).await?;
+            group::is_ready(size, &members)
+          }
+          None => false,
+        };
+        // a stack is merged in a batch of its own, bottom first, once all of
+        // it is queued, and so is the part of a merge group in this queue once
+        // the whole group is; until then their PRs wait
+        let (numbers, batch_rows) =
+          if oldest_stack.len() > 1 && stack::is_queued(&links, &oldest_stack) {
+            (oldest_stack, rows.into_iter().collect())
+          } else if group_ready {
+            let rows: Vec<_> = rows
+              .into_iter()
+              .filter(|row| row["merge_group"].to_string() == oldest_group)
+              .collect();
+            (rows.iter().map(number).collect(), rows)
+          } else {
+            let rows: Vec<_> = rows
+              .into_iter()
+              .filter(|row| stack::members(&links, number(row)).len() == 1)
+              .filter(|row| row["merge_group"].is_null())
+              .collect();
+            // a batch only contains PRs using the same strategy as the oldest
+            let strategy = match rows.first() {
+              Some(row) => row_strategy(&config, &row["strategy"])?,
+              None => {
+                tx.commit().await?;
+                return Ok(false);
+              }
+            };
+            let mut candidates = vec![];
+            let mut batch_rows = vec![];
+            for row in rows {
+              if row_strategy(&config, &row["strategy"])? == strategy {
+                candidates.push(batching::Candidate {
+                  number: row["number"].as_i64().unwrap(),
+                  timestamp: row["timestamp"].as_i64().unwrap(),
+                  alone: row["alone"].as_i64() == Some(1),
+                });
+                batch_rows.push(row);
+              }
+            }
+            if let Err(e) = self.mark_alone(repo, &config, &mut candidates).await {
+              tx.commit().await?;
+              return Err(e);
+            }
+            let recent = if config.batching.adaptive {
+              self
+                .recent_batch_results(&tx, repo, target, config.batching.adaptive_window)
+                .await?
+            } else {
+              vec![]
+            };
+            let limit = batching::size_limit(&config.batching, &recent);
+            let numbers =
+              match batching::select(&config.batching, &candidates, limit, self.clock.now()) {
+                Some(numbers) => numbers,
+                None => {
+                  tx.commit().await?;
+                  info!("waiting for more PRs to batch in {}", repo);
+                  return Ok(false);
+                }
+              };
+            (numbers, batch_rows)
+          };
+        let mut rows: Vec<_> = batch_rows
+          .into_iter()
+          .filter(|row| numbers.contains(&number(row)))
+          .collect();
+        rows.sort_by_key(|row| numbers.iter().position(|n| *n == number(row)));
+        let id = uuid::Uuid::new_v4().to_string();
+        tx.insert(
+          Insert::single_into("merge_attempt")
+            .value("id", id.as_str())
+            .value("repo_id", repo.id)
+            .value("owner", repo.owner.as_str())
+            .value("repo", repo.repo.as_str())
+            .value("base_ref", target)
+            .value("state", MergeState::Constructing)
+            .value("timestamp", self.timestamp())
+            .build(),
+        )
+        .await?;
+        (id, rows)
+      }
+    };
+    let staging_branch = config.attempt_staging_branch(&id);
+    let tmp_branch = format!("{}-tmp-{}", config.staging_branch, id);
+    tx.update(
+      Update::table("merge_attempt")
+        .set("state", MergeState::Constructing)
+        .set("staging_branch", staging_branch.as_str())
+        .set("tmp_branch", tmp_branch.as_str())
+        .set(
+          "base_attempt",
+          base
+            .as_ref()
+            .map_or(ParameterizedValue::Null, |(base, _)| base.as_str().into()),
+        )
+        .set("timestamp", self.timestamp())
+        .so_that("id".equals(id.as_str())),
+    )
+    .await?;
+    let strategy = row_strategy(&config, &rows[0]["strategy"])?;
+    let batch: Vec<BatchPr> = rows
+      .iter()
+      .map(|row| BatchPr {
+        number: row["number"].as_i64().unwrap(),
+        commit_hash: row["commit_hash"].to_string().unwrap(),
+      })
+      .collect();
+    for pr in &batch {
+      tx.update(
+        Update::table("pull_request")
+          .set("state", PrState::Merging)
+          .set("merge_attempt", id.as_str())
+          .set("timestamp", self.timestamp())
+          .so_that(pr_row(repo, pr.number)),
+      )
+      .await?;
+    }
+    tx.commit().await?;
+    info!(
+      "constructing merge attempt {} in {}: {:?}",
+      id,
+      repo,
+      batch.iter().map(|pr| pr.number).collect::<Vec<_>>()
+    );
+    for pr in &batch {
+      self
+        .sync_state_label(repo, pr.number, Some(PrState::Merging.into()))
+        .await?;
+    }
+    if config.shadow.enabled {
+      return self
+        .shadow_construct(
+          repo,
+          &config,
+          target,
+          &id,
+          strategy,
+          &batch,
+          &staging_branch,
+        )
+        .await;
+    }
+
+    let target_branch = target.to_string();
+    let numbers: Vec<i64> = batch.iter().map(|pr| pr.number).collect();
+    let target_hash = self.backend.fetch(repo, &target_branch, &numbers).await?;
+    let target_hash = match &base {
+      Some((_, staging_hash)) => staging_hash.clone(),
+      None => target_hash,
+    };
+    let constructed = Construction {
+      backend: self.backend.as_ref(),
+      client: &self.client,
+      config: &config,
+      repo,
+      target_hash: &target_hash,
+      tmp_branch: &tmp_branch,
+      staging_branch: &staging_branch,
+    }
+    .run(strategy, &batch)
+    .await?;
+    let conflicts = &constructed.conflicts;
+
+    let tx = self.db.start_transaction().await?;
+    let rows = tx
+      .select(Select::from_table("merge_attempt").so_that("id".equals(id.as_str())))
+      .await?;
+    match rows
+      .first()
+      .map(|row| (&row["state"]).try_into())
+      .transpose()?
     {
-      info!("not constructing merge attempt because merge attempt is already in progress");
+      Some(MergeState::Constructing) => (),
+      _ => {
+        tx.commit().await?;
+        info!("merge attempt {} in {} was cancelled", id, repo);
+        return Ok(false);
+      }
+    }
+    for (pr, reason) in &constructed.rejected {
+      tx.delete(Delete::from_table("pull_request").so_that(pr_row(repo, *pr)))
+        .await?;
+      self
+        .record_split(&tx, repo, *pr, &id, None, &format!("rejected: {}", reason))
+        .await?;
+    }
+    // PRs which conflict with the target need a rebase, and the others are
+    // retried without the PRs they conflict with
+    let (rebase, split): (Vec<_>, Vec<_>) = conflicts.iter().partition(|c| c.with_target);
+    for conflict in &rebase {
+      tx.delete(Delete::from_table("pull_request").so_that(pr_row(repo, conflict.number)))
+        .await?;
+      let reason = format!("conflicted with `{}`, so it needs a rebase", target_branch);
+      self
+        .record_split(&tx, repo, conflict.number, &id, None, &reason)
+        .await?;
+    }
+    let split_id = uuid::Uuid::new_v4().to_string();
+    if !split.is_empty() {
+      tx.insert(
+        Insert::single_into("merge_attempt")
+          .value("id", split_id.as_str())
+          .value("repo_id", repo.id)
+          .value("owner", repo.owner.as_str())
+          .value("repo", repo.repo.as_str())
+          .value("base_ref", target)
+          .value("state", MergeState::Split)
+          .value("timestamp", self.timestamp())
+          .build(),
+      )
+      .await?;
+      for conflict in &split {
+        tx.update(
+          Update::table("pull_request")
+            .set("state", PrState::Split)
+            .set("merge_attempt", split_id.as_str())
+            .set("timestamp", self.timestamp())
+            .so_that(pr_row(repo, conflict.number)),
+        )
+        .await?;
+        let reason = format!(
+          "conflicted with {} in its batch, so it is retried without them",
+          pr_list(&conflict.with_prs)
+        );
+        self
+          .record_split(&tx, repo, conflict.number, &id, Some(&split_id), &reason)
+          .await?;
+      }
+    }
+    let retry = conflicts.len() + constructed.rejected.len() == batch.len();
+    if retry {
+      tx.delete(Delete::from_table("merge_attempt").so_that("id".equals(id.as_str())))
+        .await?;
+    } else {
+      tx.update(
+        Update::table("merge_attempt")
+          .set("state", MergeState::Testing)
+          .set("target_hash", target_hash.as_str())
+          .set("staging_hash", constructed.staging_hash.as_str())
+          .set("timestamp", self.timestamp())
+          .so_that("id".equals(id.as_str())),
+      )
+      .await?;
+      // kept once the PRs land, so that they can be reverted on their own
+      for pr in &batch {
+        let range = constructed.ranges.iter().find(|r| r.number == pr.number);
+        tx.update(
+          Update::table("pull_request")
+            .set(
+              "staging_from",
+              range.map_or(ParameterizedValue::Null, |r| r.from.as_str().into()),
+            )
+            .set(
+              "staging_to",
+              range.map_or(ParameterizedValue::Null, |r| r.to.as_str().into()),
+            )
+            .so_that(pr_row(repo, pr.number)),
+        )
+        .await?;
+      }
+    }
+    tx.commit().await?;
+
+    for (pr, reason) in &constructed.rejected {
+      self.sync_state_label(repo, *pr, None).await?;
+      self
+        .comment(repo, *pr, format!("Merge failed: {}.", reason).as_str())
+        .await?;
+    }
+    for conflict in conflicts {
+      let label = if conflict.with_target {
+        None
+      } else {
+        Some(PrState::Split.into())
+      };
+      self.sync_state_label(repo, conflict.number, label).await?;
+      self
+        .comment(
+          repo,
+          conflict.number,
+          conflict.report(&target_branch).as_str(),
+        )
+        .await?;
+    }
+    if !retry {
+      info!(
+        "testing merge attempt {} in {} at {}",
+        id, repo, constructed.staging_hash
+      );
+      self
+        .sync_attempt_state_label(repo, &id, MergeState::Testing)
+        .await?;
+    }
+    // with speculation, the next attempt can be built on this one
+    Ok(retry || config.train.depth > 1)
+  }
+
+  /// Record the construction of a merge attempt as an intent, and test it
+  /// on the checks of its PRs instead.
+  #[allow(clippy::too_many_arguments)]
+  async fn shadow_construct(
+    &self,
+    repo: &Repository,
+    config: &RepoConfig,
+    target: &str,
+    attempt: &str,
+    strategy: Strategy,
+    batch: &[BatchPr],
+    staging_branch: &str,
+  ) -> Result<bool, ControllerError> {
+    let numbers: Vec<i64> = batch.iter().map(|pr| pr.number).collect();
+    let intent = || {
+      format!(
+        "construct the {} merge of {} into `{}` on `{}`",
+        strategy,
+        pr_list(&numbers),
+        target,
+        staging_branch
+      )
+    };
+    self.should_write(repo, None, intent).await?;
+    let tx = self.db.start_transaction().await?;
+    if !matches!(
+      attempt_state(&tx, attempt).await?,
+      Some(MergeState::Constructing)
+    ) {
+      tx.commit().await?;
+      info!("merge attempt {} in {} was cancelled", attempt, repo);
+      return Ok(false);
+    }
+    tx.update(
+      Update::table("merge_attempt")
+        .set("state", MergeState::Testing)
+        .set("timestamp", self.timestamp())
+        .so_that("id".equals(attempt)),
+    )
+    .await?;
+    tx.commit().await?;
+    info!(
+      "testing merge attempt {} in {} in shadow mode: {:?}",
+      attempt, repo, numbers
+    );
+    self
+      .sync_attempt_state_label(repo, attempt, MergeState::Testing)
+      .await?;
+    Ok(config.train.depth > 1)
+  }
+
+  pub async fn test(&self, repo: &Repository, attempt: &str) -> Result<(), ControllerError> {
+    let rows = self
+      .db
+      .select(Select::from_table("merge_attempt").so_that("id".equals(attempt)))
+      .await?;
+    let (target, staging_hash, staging_branch) = match rows.into_iter().next() {
+      Some(row) if matches!((&row["state"]).try_into()?, MergeState::Testing) => (
+        row["base_ref"].to_string().unwrap_or_default(),
+        row["staging_hash"].to_string().unwrap_or_default(),
+        row["staging_branch"].to_string().unwrap_or_default(),
+      ),
+      _ => return Ok(()),
+    };
+    let config = self.target_config(repo, &target).await?;
+    let outcome = if config.shadow.enabled {
+      // nothing was constructed, so the checks of each PR stand in
+      let rows = self
+        .db
+        .select(Select::from_table("pull_request").so_that("merge_attempt".equals(attempt)))
+        .await?;
+      let mut outcomes = vec![];
+      for row in rows {
+        let commit_hash = row["commit_hash"].to_string().unwrap_or_default();
+        let checks = self.client.checks(repo, &commit_hash).await?;
+        outcomes.push(checks::evaluate(&config.required_checks, &checks));
+      }
+      checks::combine(outcomes)
+    } else {
+      let checks = self.client.checks(repo, &staging_hash).await?;
+      checks::evaluate(&config.required_checks, &checks)
+    };
+    match outcome {
+      Outcome::Pending => Ok(()),
+      Outcome::Success => self.succeed(repo, attempt).await,
+      Outcome::Failure(failed) => {
+        self.fail(repo, &config, &target, attempt, &failed).await?;
+        if staging_branch != config.staging_branch {
+          self.delete_train_branch(repo, &staging_branch).await;
+        }
+        Ok(())
+      }
+    }
+  }
+
+  /// Mark a merge attempt whose checks passed as SUCCESS, and trigger
+  /// Complete if it is at the head of the train.
+  async fn succeed(&self, repo: &Repository, attempt: &str) -> Result<(), ControllerError> {
+    let tx = self.db.start_transaction().await?;
+    let rows = tx
+      .select(Select::from_table("merge_attempt").so_that("id".equals(attempt)))
+      .await?;
+    let (base, target) = match rows.into_iter().next() {
+      Some(row) if matches!((&row["state"]).try_into()?, MergeState::Testing) => (
+        row["base_attempt"].to_string(),
+        row["base_ref"].to_string().unwrap_or_default(),
+      ),
+      _ => {
+        tx.commit().await?;
+        return Ok(());
+      }
+    };
+    let prs = tx
+      .select(Select::from_table("pull_request").so_that("merge_attempt".equals(attempt)))
+      .await?;
+    self
+      .record_batch_result(&tx, repo, &target, prs.len(), true)
+      .await?;
+    tx.update(
+      Update::table("merge_attempt")
+        .set("state", MergeState::Success)
+        .set("timestamp", self.timestamp())
+        .so_that("id".equals(attempt)),
+    )
+    .await?;
+    tx.commit().await?;
+    info!("checks passed for merge attempt {} in {}", attempt, repo);
+    self
+      .sync_attempt_state_label(repo, attempt, MergeState::Success)
+      .await?;
+    if let Some(base) = base {
+      if attempt_state(&self.db, &base).await?.is_some() {
+        info!(
+          "merge attempt {} in {} waits for merge attempt {} to complete",
+          attempt, repo, base
+        );
+        return Ok(());
+      }
+    }
+    self.complete(repo, attempt).await
+  }
+
+  /// Split a merge attempt whose `failed` checks failed into SPLIT attempts
+  /// following `splitting.strategy`, and drop the PRs which failed alone or
+  /// too many times.
+  async fn fail(
+    &self,
+    repo: &Repository,
+    config: &RepoConfig,
+    target: &str,
+    attempt: &str,
+    failed: &[String],
+  ) -> Result<(), ControllerError> {
+    let checks = failed
+      .iter()
+      .map(|c| format!("`{}`", c))
+      .collect::<Vec<_>>()
+      .join(", ");
+    let mut paths = match config.splitting.strategy {
+      SplitStrategy::Paths => self.attempt_changed_paths(repo, target, attempt).await?,
+      _ => HashMap::new(),
+    };
+
+    let tx = self.db.start_transaction().await?;
+    if !matches!(
+      attempt_state(&tx, attempt).await?,
+      Some(MergeState::Testing)
+    ) {
+      tx.commit().await?;
       return Ok(());
     }
+    let rows = tx
+      .select(
+        Select::from_table("pull_request")
+          .so_that("merge_attempt".equals(attempt))
+          .order_by("timestamp".ascend())
+          .order_by("number".ascend()),
+      )
+      .await?;
+    let rows: Vec<_> = rows.into_iter().collect();
+    // a stack is never split, so that no PR lands before the PR below it
+    let stacked = rows.len() > 1 && rows.iter().any(|row| !row["stack_below"].is_null());
+    // a PR of a merge group fails its whole group, which is cancelled
+    let grouped = rows.iter().any(|row| !row["merge_group"].is_null());
+    let prs: Vec<FailedPr> = rows
+      .into_iter()
+      .map(|row| {
+        let number = row["number"].as_i64().unwrap();
+        FailedPr {
+          number,
+          failures: row["failures"].as_i64().unwrap_or(0) as u32 + 1,
+          paths: paths.remove(&number).unwrap_or_default(),
+        }
+      })
+      .collect();
+    let numbers: Vec<i64> = prs.iter().map(|pr| pr.number).collect();
+    self
+      .record_batch_result(&tx, repo, target, prs.len(), false)
+      .await?;
+    let rebuilt = self
+      .rebuild_after(&tx, repo, attempt, "failed its checks")
+      .await?;
+    tx.delete(Delete::from_table("merge_attempt").so_that("id".equals(attempt)))
+      .await?;
 
-    let split_rows = tx
+    let alone = prs.len() == 1;
+    let max_failures = config.splitting.max_failures;
+    let (dropped, retried): (Vec<_>, Vec<_>) = prs
+      .into_iter()
+      .partition(|pr| alone || stacked || grouped || pr.failures >= max_failures);
+    for pr in &dropped {
+      tx.delete(Delete::from_table("pull_request").so_that(pr_row(repo, pr.number)))
+        .await?;
+      let reason = if grouped {
+        format!("failed {} with the PRs linked with it", checks)
+      } else if alone {
+        format!("failed {} on its own", checks)
+      } else if stacked {
+        format!("failed {} with its stack {}", checks, pr_list(&numbers))
+      } else {
+        format!(
+          "dropped after {} failed batches, the last one {} failing {}",
+          pr.failures,
+          pr_list(&numbers),
+          checks
+        )
+      };
+      self
+        .record_split(&tx, repo, pr.number, attempt, None, &reason)
+        .await?;
+    }
+    let (groups, how) = split::split(config.splitting.strategy, &retried);
+    for group in &groups {
+      let split_id = uuid::Uuid::new_v4().to_string();
+      tx.insert(
+        Insert::single_into("merge_attempt")
+          .value("id", split_id.as_str())
+          .value("repo_id", repo.id)
+          .value("owner", repo.owner.as_str())
+          .value("repo", repo.repo.as_str())
+          .value("base_ref", target)
+          .value("state", MergeState::Split)
+          .value("timestamp", self.timestamp())
+          .build(),
+      )
+      .await?;
+      let reason = format!(
+        "batch {} failed {}, {}: retried with {}",
+        pr_list(&numbers),
+        checks,
+        how,
+        pr_list(group)
+      );
+      for pr in retried.iter().filter(|pr| group.contains(&pr.number)) {
+        tx.update(
+          Update::table("pull_request")
+            .set("state", PrState::Split)
+            .set("merge_attempt", split_id.as_str())
+            .set("failures", i64::from(pr.failures))
+            .set("timestamp", self.timestamp())
+            .so_that(pr_row(repo, pr.number)),
+        )
+        .await?;
+        self
+          .record_split(&tx, repo, pr.number, attempt, Some(&split_id), &reason)
+          .await?;
+      }
+    }
+    tx.commit().await?;
+    info!(
+      "merge attempt {} in {} failed {}: dropped {:?}, retrying {:?}",
+      attempt,
+      repo,
+      checks,
+      dropped.iter().map(|pr| pr.number).collect::<Vec<_>>(),
+      groups
+    );
+
+    for pr in &dropped {
+      self
+        .sync_state_label(repo, pr.number, Some(StateLabel::Failed))
+        .await?;
+      let message = if grouped {
+        format!(
+          "Merge failed: checks failed on the staging branch: {}.  The merge of the PRs linked with this one is cancelled.",
+          checks
+        )
+      } else if alone {
+        format!(
+          "Merge failed: checks failed on the staging branch: {}.",
+          checks
+        )
+      } else if stacked {
+        format!(
+          "Merge failed: checks failed on the staging branch for the stack {}: {}.",
+          pr_list(&numbers),
+          checks
+        )
+      } else {
+        format!(
+          "Merge failed: this PR was in {} batches whose checks failed, the last \
+           one failing {}.  Run `cherry status` for the details.",
+          pr.failures, checks
+        )
+      };
+      self.comment(repo, pr.number, message.as_str()).await?;
+    }
+    let retried = retried.iter().map(|pr| pr.number).chain(rebuilt);
+    for pr in retried {
+      self
+        .sync_state_label(repo, pr, Some(PrState::Split.into()))
+        .await?;
+    }
+    self.construct(repo).await
+  }
+
+  /// Send the attempts of the train built on `attempt`, which will not land
+  /// because it `reason`, back to SPLIT to be rebuilt.  Returns their PRs.
+  async fn rebuild_after(
+    &self,
+    db: &impl Queryable,
+    repo: &Repository,
+    attempt: &str,
+    reason: &str,
+  ) -> Result<Vec<i64>, ControllerError> {
+    let rows = db
       .select(
         Select::from_table("merge_attempt")
+          .so_that("owner".equals(repo.owner.as_str()))
+          .and_where("repo".equals(repo.repo.as_str())),
+      )
+      .await?;
+    let attempts: Vec<(String, Option<String>)> = rows
+      .into_iter()
+      .map(|row| {
+        (
+          row["id"].to_string().unwrap(),
+          row["base_attempt"].to_string(),
+        )
+      })
+      .collect();
+    let history = format!(
+      "rebuilt because merge attempt `{}` before it {}",
+      attempt, reason
+    );
+    let mut prs = vec![];
+    for id in train::built_on(&attempts, attempt) {
+      db.update(
+        Update::table("merge_attempt")
+          .set("state", MergeState::Split)
+          .set("base_attempt", ParameterizedValue::Null)
+          .set("staging_hash", ParameterizedValue::Null)
+          .set("timestamp", self.timestamp())
+          .so_that("id".equals(id.as_str())),
+      )
+      .await?;
+      let rows = db
+        .select(Select::from_table("pull_request").so_that("merge_attempt".equals(id.as_str())))
+        .await?;
+      for row in rows {
+        let pr = row["number"].as_i64().unwrap();
+        db.update(
+          Update::table("pull_request")
+            .set("state", PrState::Split)
+            .set("timestamp", self.timestamp())
+            .so_that(pr_row(repo, pr)),
+        )
+        .await?;
+        self
+          .record_split(db, repo, pr, &id, Some(&id), &history)
+          .await?;
+        prs.push(pr);
+      }
+      info!("rebuilding merge attempt {} in {}", id, repo);
+    }
+    Ok(prs)
+  }
+
+  /// Delete the staging branch of a speculative merge attempt which will not
+  /// land.  Failures are only logged, since the branch is reset if the
+  /// attempt is rebuilt.
+  async fn delete_train_branch(&self, repo: &Repository, branch: &str) {
+    let result = match self
+      .should_write(repo, None, || format!("delete branch `{}`", branch))
+      .await
+    {
+      Ok(true) => self
+        .client
+        .delete_branch(repo, branch)
+        .await
+        .map_err(Into::into),
+      result => result.map(|_| ()),
+    };
+    if let Err(e) = result {
+      error!("deleting branch `{}` in {}: {}", branch, repo, e);
+    }
+  }
+
+  /// Paths changed by each PR of a merge attempt, relative to its `target`
+  /// branch.
+  async fn attempt_changed_paths(
+    &self,
+    repo: &Repository,
+    target: &str,
+    attempt: &str,
+  ) -> Result<HashMap<i64, Vec<String>>, ControllerError> {
+    let rows = self
+      .db
+      .select(Select::from_table("pull_request").so_that("merge_attempt".equals(attempt)))
+      .await?;
+    let prs: Vec<(i64, String)> = rows
+      .into_iter()
+      .map(|row| {
+        (
+          row["number"].as_i64().unwrap(),
+          row["commit_hash"].to_string().unwrap(),
+        )
+      })
+      .collect();
+    let numbers: Vec<i64> = prs.iter().map(|(number, _)| *number).collect();
+    let target_hash = self.backend.fetch(repo, target, &numbers).await?;
+    let mut paths = HashMap::new();
+    for (number, commit_hash) in prs {
+      let changed = self
+        .backend
+        .changed_paths(repo, &target_hash, &commit_hash)
+        .await?;
+      paths.insert(number, changed);
+    }
+    Ok(paths)
+  }
+
+  /// Describe where a PR is in the queue and why, for `cherry status`.
+  pub async fn status(&self, repo: &Repository, pr: i64) -> Result<String, ControllerError> {
+    let rows = self
+      .db
+      .select(Select::from_table("pull_request").so_that(pr_row(repo, pr)))
+      .await?;
+    let mut message = match rows.into_iter().next() {
+      None => "This PR is not in the merge queue.".to_string(),
+      Some(row) => {
+        let state: PrState = (&row["state"]).try_into()?;
+        let mut message = format!(
+          "This PR is `{}` in the queue into `{}`",
+          state,
+          row["base_ref"].to_string().unwrap_or_default()
+        );
+        if let Some(attempt) = row["merge_attempt"].to_string() {
+          if let Some(attempt_state) = attempt_state(&self.db, &attempt).await? {
+            message.push_str(&format!(
+              ", in merge attempt `{}`, which is `{}`",
+              attempt, attempt_state
+            ));
+          }
+        }
+        message.push('.');
+        if let Some(group) = row["merge_group"].to_string() {
+          let (_, members) = group_members(&self.db, &group).await?;
+          let linked: Vec<String> = members
+            .iter()
+            .filter(|m| !(m.repo == *repo && m.number == pr))
+            .map(|m| format!("{}#{}", m.repo, m.number))
+            .collect();
+          message.push_str(&format!("  It lands together with {}.", linked.join(", ")));
+        }
+        let failures = row["failures"].as_i64().unwrap_or(0);
+        if failures > 0 {
+          message.push_str(&format!(
+            "  It was in {} batch(es) whose checks failed.",
+            failures
+          ));
+        }
+        let config = self.config(repo).await?;
+        let target = row["base_ref"].to_string().unwrap_or_default();
+        let priority = row["priority"].as_i64().unwrap_or(0);
+        if let Some(hold) = self.hold_reason(repo, &config, &target, priority).await? {
+          message.push_str(&format!("  It is held: {}.", hold));
+        }
+        message
+      }
+    };
+    let history = self
+      .db
+      .select(
+        Select::from_table("split_history")
+          .so_that(pr_row(repo, pr))
+          .order_by("timestamp".descend())
+          .limit(STATUS_HISTORY_LENGTH),
+      )
+      .await?;
+    if !history.is_empty() {
+      message.push_str("\n\nRecent splits, most recent first:");
+      for row in history {
+        message.push_str(&format!(
+          "\n- {}",
+          row["reason"].to_string().unwrap_or_default()
+        ));
+      }
+    }
+    Ok(message)
+  }
+
+  /// Close the tree of `repo` to PRs with a priority below `priority`, or
+  /// open it if `None`, on behalf of `user`.  Returns the reply.
+  pub async fn set_tree(
+    &self,
+    repo: &Repository,
+    priority: Option<i64>,
+    user: &str,
+  ) -> Result<String, ControllerError> {
+    let tree = Delete::from_table("tree_closed").so_that(
+      "owner"
+        .equals(repo.owner.as_str())
+        .and("repo".equals(repo.repo.as_str())),
+    );
+    let priority = match priority {
+      Some(priority) => {
+        let tx = self.db.start_transaction().await?;
+        let since = match tree_closed_row(&tx, repo).await? {
+          Some((_, since)) => since,
+          None => self.timestamp(),
+        };
+        tx.delete(tree).await?;
+        tx.insert(
+          Insert::single_into("tree_closed")
+            .value("repo_id", repo.id)
+            .value("owner", repo.owner.as_str())
+            .value("repo", repo.repo.as_str())
+            .value("priority", priority)
+            .value("closed_by", user)
+            .value("timestamp", since)
+            .build(),
+        )
+        .await?;
+        tx.commit().await?;
+        info!(
+          "{} closed the tree of {} below priority {}",
+          user, repo, priority
+        );
+        return Ok(format!(
+          "The tree is closed: only PRs with priority {} or more will be merged.",
+          priority
+        ));
+      }
+      None => match tree_closed_row(&self.db, repo).await? {
+        Some(closed) => closed,
+        None => return Ok("The tree is already open.".to_string()),
+      },
+    };
+    self.db.delete(tree).await?;
+    info!("{} opened the tree of {}", user, repo);
+    let (priority, since) = priority;
+    self
+      .release(repo, None, Some(priority), since, "The tree is open again")
+      .await?;
+    self.construct(repo).await?;
+    Ok("The tree is open.".to_string())
+  }
+
+  /// Why a PR into `target` with `priority` is not merged for now, if it is
+  /// held by a closed tree or a freeze window.
+  async fn hold_reason(
+    &self,
+    repo: &Repository,
+    config: &RepoConfig,
+    target: &str,
+    priority: i64,
+  ) -> Result<Option<String>, ControllerError> {
+    if let Some(window) = config.active_freeze(target, self.clock.now()) {
+      return Ok(Some(format!("`{}` is frozen {}", target, window)));
+    }
+    if let Some(health) = target_health(&self.db, repo, target).await? {
+      if health.pauses() {
+        return Ok(Some(health.hold(target)));
+      }
+    }
+    Ok(match tree_closed(&self.db, repo).await? {
+      Some(closed) if priority < closed => Some(format!(
+        "the tree is closed to PRs with a priority below {}",
+        closed
+      )),
+      _ => None,
+    })
+  }
+
+  /// Remember that a freeze window paused Construct on `target`, so that
+  /// its PRs are told when it ends.
+  async fn record_freeze(&self, repo: &Repository, target: &str) -> Result<(), ControllerError> {
+    let frozen = Select::from_table("freeze")
+      .so_that("owner".equals(repo.owner.as_str()))
+      .and_where("repo".equals(repo.repo.as_str()))
+      .and_where("base_ref".equals(target));
+    if self.db.select(frozen).await?.is_empty() {
+      self
+        .db
+        .insert(
+          Insert::single_into("freeze")
+            .value("repo_id", repo.id)
+            .value("owner", repo.owner.as_str())
+            .value("repo", repo.repo.as_str())
+            .value("base_ref", target)
+            .value("timestamp", self.timestamp())
+            .build(),
+        )
+        .await?;
+    }
+    Ok(())
+  }
+
+  /// Test the merge attempts whose staging commit is `sha`, after checks
+  /// were reported on it.  Poll tests every attempt in TESTING as well, in
+  /// case an event is missed.
+  pub async fn staging_checked(&self, repo: &Repository, sha: &str) -> Result<(), ControllerError> {
+    let rows = self
+      .db
+      .select(
+        Select::from_table("merge_attempt")
+          .column("id")
+          .so_that(repo_row(repo).and("staging_hash".equals(sha))),
+      )
+      .await?;
+    for row in rows {
+      let id = row["id"].to_string().unwrap_or_default();
+      self.test(repo, &id).await?;
+    }
+    Ok(())
+  }
+
+  /// Evaluate the checks reported on `sha`, if it is the head of the target
+  /// branch `target`, pausing the queue into `target` while they fail.
+  pub async fn target_checked(
+    &self,
+    repo: &Repository,
+    target: &str,
+    sha: &str,
+  ) -> Result<(), ControllerError> {
+    let config = self.config(repo).await?;
+    let default_branch = self.client.default_branch(repo).await?;
+    if !config.allows_target(target, &default_branch) {
+      return Ok(());
+    }
+    if self.client.branch_hash(repo, target).await?.as_deref() != Some(sha) {
+      return Ok(());
+    }
+    self.check_target(repo, target, sha).await
+  }
+
+  /// Record whether the checks on `head_hash`, the head of `target`, fail,
+  /// telling the PRs of the merge attempt which landed there if so, and
+  /// resuming the queue once they pass.
+  async fn check_target(
+    &self,
+    repo: &Repository,
+    target: &str,
+    head_hash: &str,
+  ) -> Result<(), ControllerError> {
+    let config = self.target_config(repo, target).await?;
+    let checks = self.client.checks(repo, head_hash).await?;
+    let health = target_health(&self.db, repo, target).await?;
+    match checks::evaluate(&config.required_checks, &checks) {
+      Outcome::Pending => Ok(()),
+      Outcome::Success => {
+        let health = match health {
+          Some(health) => health,
+          None => return Ok(()),
+        };
+        self
+          .db
+          .delete(Delete::from_table("target_health").so_that(target_row(repo, target)))
+          .await?;
+        info!(
+          "checks pass on `{}` in {} again at {}",
+          target, repo, head_hash
+        );
+        if health.pauses() {
+          let reason = format!("The checks pass on `{}` again", target);
+          self
+            .release(repo, Some(target), None, health.since, &reason)
+            .await?;
+        }
+        self.construct(repo).await
+      }
+      Outcome::Failure(failed) => {
+        let since = match health {
+          Some(health) if health.head_hash == head_hash => return Ok(()),
+          Some(health) if health.pauses() => health.since,
+          _ => self.timestamp(),
+        };
+        let attempt = self
+          .db
+          .select(
+            Select::from_table("landed_attempt")
+              .so_that("owner".equals(repo.owner.as_str()))
+              .and_where("repo".equals(repo.repo.as_str()))
+              .and_where("base_ref".equals(target))
+              .and_where("staging_hash".equals(head_hash))
+              .order_by("timestamp".descend()),
+          )
+          .await?
+          .into_iter()
+          .next()
+          .and_then(|row| row["id"].to_string());
+        let tx = self.db.start_transaction().await?;
+        tx.delete(Delete::from_table("target_health").so_that(target_row(repo, target)))
+          .await?;
+        tx.insert(
+          Insert::single_into("target_health")
+            .value("repo_id", repo.id)
+            .value("owner", repo.owner.as_str())
+            .value("repo", repo.repo.as_str())
+            .value("base_ref", target)
+            .value("head_hash", head_hash)
+            .value("checks", failed.join(","))
+            .value(
+              "attempt",
+              match &attempt {
+                Some(attempt) => ParameterizedValue::from(attempt.as_str()),
+                None => ParameterizedValue::Null,
+              },
+            )
+            .value("timestamp", since)
+            .build(),
+        )
+        .await?;
+        tx.commit().await?;
+        info!(
+          "checks {} fail on `{}` in {} at {}, pausing its queue",
+          health::checks_list(&failed),
+          target,
+          repo,
+          head_hash
+        );
+        if let Some(attempt) = attempt {
+          let message = health::failure_report(target, head_hash, &failed, &attempt);
+          let prs = self
+            .db
+            .select(Select::from_table("landed_pr").so_that("attempt".equals(attempt.as_str())))
+            .await?;
+          for row in prs {
+            let number = row["number"].as_i64().unwrap();
+            self.comment(repo, number, message.as_str()).await?;
+          }
+        }
+        Ok(())
+      }
+    }
+  }
+
+  /// Let the queue into `target`, or the base branch of `pr`, go on despite
+  /// its failing checks, on behalf of `user`.  Returns the reply.
+  pub async fn override_base(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    target: Option<String>,
+    user: &str,
+  ) -> Result<String, ControllerError> {
+    let target = match target {
+      Some(target) => target,
+      None => self.client.pr_info(repo, pr).await?.base_ref,
+    };
+    let health = match target_health(&self.db, repo, &target).await? {
+      Some(health) if health.pauses() => health,
+      _ => {
+        return Ok(format!(
+          "The queue into `{}` is not paused by failing checks.",
+          target
+        ))
+      }
+    };
+    self
+      .db
+      .update(
+        Update::table("target_health")
+          .set("overridden_by", user)
+          .so_that(target_row(repo, &target)),
+      )
+      .await?;
+    info!(
+      "{} overrode the failing checks of `{}` in {}",
+      user, target, repo
+    );
+    let reason = format!(
+      "@{} let the queue into `{}` go on despite its failing checks",
+      user, target
+    );
+    self
+      .release(repo, Some(&target), None, health.since, &reason)
+      .await?;
+    self.construct(repo).await?;
+    Ok(format!(
+      "The queue into `{}` goes on, until checks fail at another commit of `{}`.",
+      target, target
+    ))
+  }
+
+  /// Evaluate the checks on the target branches whose queues are paused
+  /// again, in case a webhook was missed.
+  async fn recheck_targets(&self) -> Result<(), ControllerError> {
+    for row in self.db.select(Select::from_table("target_health")).await? {
+      let repo = row_repo(&row);
+      let target = row["base_ref"].to_string().unwrap_or_default();
+      match self.client.branch_hash(&repo, &target).await? {
+        Some(head_hash) => self.check_target(&repo, &target, &head_hash).await?,
+        None => {
+          self
+            .db
+            .delete(Delete::from_table("target_health").so_that(target_row(&repo, &target)))
+            .await?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Forget the freezes which ended, telling the PRs they held.
+  async fn end_freezes(&self) -> Result<(), ControllerError> {
+    let now = self.clock.now();
+    for row in self.db.select(Select::from_table("freeze")).await? {
+      let repo = row_repo(&row);
+      let target = row["base_ref"].to_string().unwrap_or_default();
+      let config = self.config(&repo).await?;
+      if config.active_freeze(&target, now).is_some() {
+        continue;
+      }
+      self
+        .db
+        .delete(
+          Delete::from_table("freeze").so_that(
+            "owner"
+              .equals(repo.owner.as_str())
+              .and("repo".equals(repo.repo.as_str()))
+              .and("base_ref".equals(target.as_str())),
+          ),
+        )
+        .await?;
+      info!("freeze of `{}` in {} ended", target, repo);
+      let since = row["timestamp"].as_i64().unwrap_or_default();
+      let reason = format!("The freeze of `{}` ended", target);
+      self
+        .release(&repo, Some(&target), None, since, &reason)
+        .await?;
+      self.construct(&repo).await?;
+    }
+    Ok(())
+  }
+
+  /// Tell the PRs waiting in `repo` (into `target`, below `priority`, if
+  /// given) that they are no longer held since `since`, and push their
+  /// queue timeouts back by as long as they were held.
+  async fn release(
+    &self,
+    repo: &Repository,
+    target: Option<&str>,
+    priority: Option<i64>,
+    since: i64,
+    reason: &str,
+  ) -> Result<(), ControllerError> {
+    let rows = self
+      .db
+      .select(
+        Select::from_table("pull_request")
           .so_that("owner".equals(repo.owner.as_str()))
           .and_where("repo".equals(repo.repo.as_str()))
-          .and_where("state".equals(MergeState::Split)),
+          .and_where("merge_attempt".is_null()),
       )
       .await?;
+    let now = self.timestamp();
+    for row in rows {
+      let number = row["number"].as_i64().unwrap();
+      let state: PrState = (&row["state"]).try_into()?;
+      let held = matches!(state, PrState::Requested | PrState::Queued)
+        && target.map_or(true, |t| row["base_ref"].as_str() == Some(t))
+        && priority.map_or(true, |p| row["priority"].as_i64().unwrap_or(0) < p);
+      if !held {
+        continue;
+      }
+      if matches!(state, PrState::Queued) {
+        let timestamp = row["timestamp"].as_i64().unwrap();
+        let timestamp = (timestamp + now - since.max(timestamp)).min(now);
+        self
+          .db
+          .update(
+            Update::table("pull_request")
+              .set("timestamp", timestamp)
+              .so_that(pr_row(repo, number)),
+          )
+          .await?;
+      }
+      let message = format!("{}, so this PR can be merged.", reason);
+      self.comment(repo, number, message.as_str()).await?;
+    }
+    Ok(())
+  }
+
+  /// Fast-forward the target branch to the staging commit of a SUCCESS
+  /// merge attempt at the head of its train, then Complete the next attempt
+  /// of the train if it already passed its checks.
+  pub fn complete<'a>(
+    &'a self,
+    repo: &'a Repository,
+    attempt: &'a str,
+  ) -> LocalBoxFuture<'a, Result<(), ControllerError>> {
+    Box::pin(async move {
+      let rows = self
+        .db
+        .select(Select::from_table("merge_attempt").so_that("id".equals(attempt)))
+        .await?;
+      let (target, staging_hash, staging_branch, base) = match rows.into_iter().next() {
+        Some(row) if matches!((&row["state"]).try_into()?, MergeState::Success) => (
+          row["base_ref"].to_string().unwrap_or_default(),
+          row["staging_hash"].to_string().unwrap_or_default(),
+          row["staging_branch"].to_string().unwrap_or_default(),
+          row["base_attempt"].to_string(),
+        ),
+        _ => return Ok(()),
+      };
+      if let Some(base) = base {
+        if attempt_state(&self.db, &base).await?.is_some() {
+          return Ok(());
+        }
+      }
+      // PRs linked across repositories land together with their group
+      if let Some(group) = attempt_group(&self.db, attempt).await? {
+        return self.complete_group(&group).await;
+      }
+      let config = self.target_config(repo, &target).await?;
+      info!(
+        "completing merge attempt {} in {}: `{}` to {}",
+        attempt, repo, target, staging_hash
+      );
+      let intent = || format!("fast-forward `{}` to merge attempt {}", target, attempt);
+      let landed = if self.should_write(repo, None, intent).await? {
+        self
+          .client
+          .fast_forward_branch(repo, &target, &staging_hash)
+          .await?
+      } else {
+        true
+      };
+      if staging_branch != config.staging_branch {
+        self.delete_train_branch(repo, &staging_branch).await;
+      }
+      if !landed {
+        let reason = format!("could not be fast-forwarded because `{}` moved", target);
+        let message = format!(
+          "Merge delayed: `{}` moved before the merge could land, so this PR was queued again.",
+          target
+        );
+        return self.requeue(repo, attempt, &reason, &message).await;
+      }
+
+      if let Some(next) = self
+        .land(repo, &config, &target, attempt, &staging_hash)
+        .await?
+      {
+        self.complete(repo, &next).await?;
+      }
+      self.construct(repo).await
+    })
+  }
 
-    let id = if let Some(split_row) = split_rows.first() {
-      let id = split_row["id"].as_str().unwrap();
+  /// Clean up after a merge attempt landed in `target` at `staging_hash`:
+  /// remove it and its PRs from the queue, report the merge on each PR and
+  /// run the post-merge actions.  Returns the next attempt of the train if
+  /// it already passed its checks, to be completed next.
+  async fn land(
+    &self,
+    repo: &Repository,
+    config: &RepoConfig,
+    target: &str,
+    attempt: &str,
+    staging_hash: &str,
+  ) -> Result<Option<String>, ControllerError> {
+    let tx = self.db.start_transaction().await?;
+    let rows = tx
+      .select(
+        Select::from_table("pull_request")
+          .so_that("merge_attempt".equals(attempt))
+          .order_by("timestamp".ascend())
+          .order_by("number".ascend()),
+      )
+      .await?;
+    let rows: Vec<_> = rows.into_iter().collect();
+    let prs: Vec<i64> = rows
+      .iter()
+      .map(|row| row["number"].as_i64().unwrap())
+      .collect();
+    // what landed is kept after the live rows are deleted, for `cherry revert`
+    if !config.shadow.enabled {
+      let target_hash = tx
+        .select(Select::from_table("merge_attempt").so_that("id".equals(attempt)))
+        .await?
+        .into_iter()
+        .next()
+        .and_then(|row| row["target_hash"].to_string())
+        .unwrap_or_default();
+      tx.insert(
+        Insert::single_into("landed_attempt")
+          .value("id", attempt)
+          .value("owner", repo.owner.as_str())
+          .value("repo", repo.repo.as_str())
+          .value("base_ref", target)
+          .value("target_hash", target_hash)
+          .value("staging_hash", staging_hash)
+          .value("timestamp", self.timestamp())
+          .build(),
+      )
+      .await?;
+      for row in &rows {
+        tx.insert(
+          Insert::single_into("landed_pr")
+            .value("owner", repo.owner.as_str())
+            .value("repo", repo.repo.as_str())
+            .value("number", row["number"].as_i64().unwrap())
+            .value("attempt", attempt)
+            .value("staging_from", row["staging_from"].clone())
+            .value("staging_to", row["staging_to"].clone())
+            .value("timestamp", self.timestamp())
+            .build(),
+        )
+        .await?;
+      }
+    }
+    tx.delete(Delete::from_table("pull_request").so_that("merge_attempt".equals(attempt)))
+      .await?;
+    tx.delete(Delete::from_table("merge_attempt").so_that("id".equals(attempt)))
+      .await?;
+    // PRs stacked on the landed ones are no longer stacked
+    let mut dependents = vec![];
+    for &pr in &prs {
+      let rows = tx
+        .select(
+          Select::from_table("pull_request")
+            .so_that("owner".equals(repo.owner.as_str()))
+            .and_where("repo".equals(repo.repo.as_str()))
+            .and_where("stack_below".equals(pr)),
+        )
+        .await?;
+      for row in rows {
+        let dependent = row["number"].as_i64().unwrap();
+        tx.update(
+          Update::table("pull_request")
+            .set("stack_below", ParameterizedValue::Null)
+            .so_that(pr_row(repo, dependent)),
+        )
+        .await?;
+        dependents.push(dependent);
+      }
+    }
+    // the next attempt of the train is now at its head
+    let next = tx
+      .select(Select::from_table("merge_attempt").so_that("base_attempt".equals(attempt)))
+      .await?
+      .into_iter()
+      .next()
+      .map(|row| {
+        (
+          row["id"].to_string().unwrap(),
+          matches!((&row["state"]).try_into(), Ok(MergeState::Success)),
+        )
+      });
+    if let Some((next, _)) = &next {
       tx.update(
         Update::table("merge_attempt")
-          .set("state", MergeState::Constructing)
-          .set("timestamp", Utc::now().timestamp())
-          .so_that("id".equals(id)),
+          .set("base_attempt", ParameterizedValue::Null)
+          .so_that("id".equals(next.as_str())),
       )
       .await?;
-      todo!("need to record the branch name??");
-      id
-    } else {
-      let id = uuid::Uuid::new_v4().to_string();
+    }
+    tx.commit().await?;
+    info!(
+      "merged attempt {} into `{}` in {} at {}: {:?}",
+      attempt, target, repo, staging_hash, prs
+    );
+
+    self
+      .log_attempt(
+        repo,
+        attempt,
+        &format!(
+          "merged {} into `{}` at {}",
+          pr_list(&prs),
+          target,
+          staging_hash
+        ),
+      )
+      .await?;
+    if config.shadow.enabled {
+      return self.shadow_land(repo, target, attempt, &prs, next).await;
+    }
+    let mut merged = vec![];
+    for &pr in &prs {
+      self.sync_state_label(repo, pr, None).await?;
+      self
+        .comment(
+          repo,
+          pr,
+          format!("Merged into `{}` in {}.", target, staging_hash).as_str(),
+        )
+        .await?;
+      let mut pr_info = self.client.pr_info(repo, pr).await?;
+      // stacked PRs are based on the head branch of the PR below them
+      if pr_info.base_ref != target {
+        self.client.set_pr_base(repo, pr, target).await?;
+        pr_info = self.client.pr_info(repo, pr).await?;
+      }
+      // GitHub marks a PR merged, asynchronously, once its head commit lands;
+      // one whose changes landed in a different commit has to be closed
+      if !pr_info.merged
+        && matches!(pr_info.state, GHPrState::Open)
+        && !self
+          .landed_head(repo, staging_hash, &pr_info.commit_hash)
+          .await?
+      {
+        self
+          .comment(
+            repo,
+            pr,
+            "Closing this PR, since its changes landed in a different commit.",
+          )
+          .await?;
+        self.client.close_issue(repo, pr).await?;
+      }
+      merged.push((pr, pr_info));
+    }
+    for &pr in &dependents {
+      if self.client.pr_info(repo, pr).await?.base_ref != target {
+        self.client.set_pr_base(repo, pr, target).await?;
+      }
+    }
+    self
+      .post_merge(repo, config, target, attempt, &merged)
+      .await;
+    for &pr in &prs {
+      if let Err(e) = self.run_backports(repo, pr).await {
+        error!("backporting {} #{}: {}", repo, pr, e);
+      }
+    }
+    Ok(match next {
+      Some((next, true)) => Some(next),
+      _ => None,
+    })
+  }
+
+  /// Whether `head`, the head commit of a PR, is an ancestor of
+  /// `staging_hash`, which landed.
+  async fn landed_head(
+    &self,
+    repo: &Repository,
+    staging_hash: &str,
+    head: &str,
+  ) -> Result<bool, ControllerError> {
+    let range = self
+      .client
+      .compare_commits(repo, staging_hash, head)
+      .await?;
+    Ok(range.total_commits == 0)
+  }
+
+  /// Record the PRs of a merge attempt which would have landed in shadow
+  /// mode, for `cherry report`.
+  async fn shadow_land(
+    &self,
+    repo: &Repository,
+    target: &str,
+    attempt: &str,
+    prs: &[i64],
+    next: Option<(String, bool)>,
+  ) -> Result<Option<String>, ControllerError> {
+    for &pr in prs {
       self
         .db
         .insert(
-          Insert::single_into("merge_attempt")
-            .value("id", id)
+          Insert::single_into("shadow_merge")
             .value("owner", repo.owner.as_str())
             .value("repo", repo.repo.as_str())
-            .value("state", MergeState::Constructing)
-            .value("timestamp", Utc::now().timestamp())
+            .value("number", pr)
+            .value("attempt", attempt)
+            .value("base_ref", target)
+            .value("timestamp", self.timestamp())
             .build(),
         )
         .await?;
-      todo!("need to record the branch name??");
-      id.as_str()
+      self.sync_state_label(repo, pr, None).await?;
+      self
+        .comment(
+          repo,
+          pr,
+          format!("Would have merged into `{}`.", target).as_str(),
+        )
+        .await?;
+      if let Err(e) = self.run_backports(repo, pr).await {
+        error!("backporting {} #{}: {}", repo, pr, e);
+      }
+    }
+    Ok(match next {
+      Some((next, true)) => Some(next),
+      _ => None,
+    })
+  }
+
+  /// Compare the PRs cherry would have merged in shadow mode with the PRs
+  /// merged by hand, over the last few days.
+  pub async fn shadow_report(&self, repo: &Repository) -> Result<String, ControllerError> {
+    let config = self.config(repo).await?;
+    let since = self.clock.now() - Duration::days(SHADOW_REPORT_DAYS);
+    let shadow: Vec<_> = self
+      .db
+      .select(
+        Select::from_table("shadow_merge")
+          .so_that("owner".equals(repo.owner.as_str()))
+          .and_where("repo".equals(repo.repo.as_str()))
+          .and_where("timestamp".greater_than_or_equals(since.timestamp()))
+          .order_by("timestamp".ascend()),
+      )
+      .await?
+      .into_iter()
+      .map(|row| shadow::ShadowMerge {
+        number: row["number"].as_i64().unwrap(),
+        timestamp: row["timestamp"].as_i64().unwrap(),
+      })
+      .collect();
+    let human: Vec<_> = self
+      .client
+      .merged_prs(repo, since)
+      .await?
+      .into_iter()
+      .map(|(number, merged_at)| shadow::HumanMerge {
+        number,
+        timestamp: merged_at.timestamp(),
+      })
+      .collect();
+    let queued: Vec<(i64, String)> = self
+      .db
+      .select(
+        Select::from_table("pull_request")
+          .so_that("owner".equals(repo.owner.as_str()))
+          .and_where("repo".equals(repo.repo.as_str())),
+      )
+      .await?
+      .into_iter()
+      .map(|row| {
+        (
+          row["number"].as_i64().unwrap(),
+          row["state"].to_string().unwrap_or_default(),
+        )
+      })
+      .collect();
+    let report = shadow::report(SHADOW_REPORT_DAYS, &shadow, &human, |number| {
+      queued
+        .iter()
+        .find(|(n, _)| *n == number)
+        .map(|(_, state)| state.clone())
+    });
+    Ok(if config.shadow.enabled {
+      report
+    } else {
+      format!("Shadow mode is not enabled in {}.\n\n{}", repo, report)
+    })
+  }
+
+  /// Run the `[post_merge]` actions on the PRs of a merge attempt which
+  /// landed in `target`, and record each result in the attempt's log.
+  /// Failures are only logged, since the merge itself succeeded.
+  async fn post_merge(
+    &self,
+    repo: &Repository,
+    config: &RepoConfig,
+    target: &str,
+    attempt: &str,
+    prs: &[(i64, PullRequest)],
+  ) {
+    let actions = &config.post_merge;
+    if !actions.delete_branches
+      && !actions.close_issues
+      && actions.label.is_none()
+      && actions.milestone.is_none()
+    {
+      return;
+    }
+    let default_branch = match self.client.default_branch(repo).await {
+      Ok(default_branch) => default_branch,
+      Err(e) => {
+        error!("post-merge actions in {}: {}", repo, e);
+        return;
+      }
     };
+    for (pr, info) in prs {
+      let results = vec![
+        (
+          "deleting head branch",
+          self
+            .delete_head_branch(repo, config, &default_branch, info)
+            .await,
+        ),
+        (
+          "closing linked issues",
+          self
+            .close_linked_issues(repo, config, target, &default_branch, *pr, info)
+            .await,
+        ),
+        (
+          "adding label",
+          self.add_merged_label(repo, config, *pr).await,
+        ),
+        (
+          "setting milestone",
+          self.set_merged_milestone(repo, config, *pr).await,
+        ),
+      ];
+      for (action, result) in results {
+        let entry = match result {
+          Ok(None) => continue,
+          Ok(Some(entry)) => format!("#{}: {}", pr, entry),
+          Err(e) => format!("#{}: {} failed: {}", pr, action, e),
+        };
+        info!("post-merge in {}: {}", repo, entry);
+        if let Err(e) = self.log_attempt(repo, attempt, &entry).await {
+          error!("logging merge attempt {} in {}: {}", attempt, repo, e);
+        }
+      }
+    }
+  }
 
-    todo!()
+  /// Delete the head branch of a merged PR, unless it is in a fork,
+  /// protected, or a target branch.
+  async fn delete_head_branch(
+    &self,
+    repo: &Repository,
+    config: &RepoConfig,
+    default_branch: &str,
+    info: &PullRequest,
+  ) -> Result<Option<String>, ControllerError> {
+    if !config.post_merge.delete_branches {
+      return Ok(None);
+    }
+    let branch = &info.head_ref;
+    let kept = if info.head_repo_id != Some(repo.id) {
+      Some("it is in a fork")
+    } else if branch == default_branch || config.allows_target(branch, default_branch) {
+      Some("it is a target branch")
+    } else if self.client.branch_protected(repo, branch).await? {
+      Some("it is protected")
+    } else {
+      None
+    };
+    if let Some(reason) = kept {
+      return Ok(Some(format!("kept head branch `{}`: {}", branch, reason)));
+    }
+    let intent = || format!("delete head branch `{}`", branch);
+    if self.should_write(repo, None, intent).await? {
+      self.client.delete_branch(repo, branch).await?;
+    }
+    Ok(Some(format!("deleted head branch `{}`", branch)))
   }
 
-  pub async fn test(&self) {
-    todo!()
+  /// Close the issues a PR merged into `target` links to, which GitHub only
+  /// does for merges into the default branch.
+  async fn close_linked_issues(
+    &self,
+    repo: &Repository,
+    config: &RepoConfig,
+    target: &str,
+    default_branch: &str,
+    pr: i64,
+    info: &PullRequest,
+  ) -> Result<Option<String>, ControllerError> {
+    if !config.post_merge.close_issues || target == default_branch {
+      return Ok(None);
+    }
+    let issues = post_merge::linked_issues(&info.body);
+    if issues.is_empty() {
+      return Ok(None);
+    }
+    for &issue in &issues {
+      let intent = || format!("close #{}, closed by #{}", issue, pr);
+      if !self.should_write(repo, Some(issue), intent).await? {
+        continue;
+      }
+      self
+        .comment(
+          repo,
+          issue,
+          format!("Closed by #{}, merged into `{}`.", pr, target).as_str(),
+        )
+        .await?;
+      self.client.close_issue(repo, issue).await?;
+    }
+    Ok(Some(format!("closed {}", pr_list(&issues))))
   }
 
-  pub fn complete(&self) -> LocalBoxFuture<'_, ()> {
-    todo!()
+  async fn add_merged_label(
+    &self,
+    repo: &Repository,
+    config: &RepoConfig,
+    pr: i64,
+  ) -> Result<Option<String>, ControllerError> {
+    let label = match &config.post_merge.label {
+      Some(label) => label,
+      None => return Ok(None),
+    };
+    if self
+      .should_write(repo, Some(pr), || format!("add label `{}`", label))
+      .await?
+    {
+      self.client.add_labels(repo, pr, &[label.as_str()]).await?;
+    }
+    Ok(Some(format!("added label `{}`", label)))
+  }
+
+  async fn set_merged_milestone(
+    &self,
+    repo: &Repository,
+    config: &RepoConfig,
+    pr: i64,
+  ) -> Result<Option<String>, ControllerError> {
+    let title = match &config.post_merge.milestone {
+      Some(title) => title,
+      None => return Ok(None),
+    };
+    match self.client.milestone_number(repo, title).await? {
+      Some(milestone) => {
+        if self
+          .should_write(repo, Some(pr), || format!("set milestone `{}`", title))
+          .await?
+        {
+          self.client.set_milestone(repo, pr, milestone).await?;
+        }
+        Ok(Some(format!("set milestone `{}`", title)))
+      }
+      None => Ok(Some(format!("no milestone titled `{}`", title))),
+    }
+  }
+
+  /// Add `entry` to the audit log of a merge attempt.
+  async fn log_attempt(
+    &self,
+    repo: &Repository,
+    attempt: &str,
+    entry: &str,
+  ) -> Result<(), ControllerError> {
+    self
+      .db
+      .insert(
+        Insert::single_into("attempt_log")
+          .value("owner", repo.owner.as_str())
+          .value("repo", repo.repo.as_str())
+          .value("attempt", attempt)
+          .value("entry", entry)
+          .value("timestamp", self.timestamp())
+          .build(),
+      )
+      .await?;
+    Ok(())
+  }
+
+  /// Send the PRs of a SUCCESS merge attempt which could not land, because
+  /// it `reason`, back to QUEUED with `message`, and rebuild the attempts
+  /// built on it.
+  async fn requeue(
+    &self,
+    repo: &Repository,
+    attempt: &str,
+    reason: &str,
+    message: &str,
+  ) -> Result<(), ControllerError> {
+    let tx = self.db.start_transaction().await?;
+    if !matches!(
+      attempt_state(&tx, attempt).await?,
+      Some(MergeState::Success)
+    ) {
+      tx.commit().await?;
+      return Ok(());
+    }
+    let rebuilt = self.rebuild_after(&tx, repo, attempt, reason).await?;
+    let rows = tx
+      .select(Select::from_table("pull_request").so_that("merge_attempt".equals(attempt)))
+      .await?;
+    let prs: Vec<i64> = rows
+      .into_iter()
+      .map(|row| row["number"].as_i64().unwrap())
+      .collect();
+    for &pr in &prs {
+      tx.update(
+        Update::table("pull_request")
+          .set("state", PrState::Queued)
+          .set("merge_attempt", ParameterizedValue::Null)
+          .set("timestamp", self.timestamp())
+          .so_that(pr_row(repo, pr)),
+      )
+      .await?;
+      self
+        .record_split(
+          &tx,
+          repo,
+          pr,
+          attempt,
+          None,
+          &format!("queued again: {}", reason),
+        )
+        .await?;
+    }
+    tx.delete(Delete::from_table("merge_attempt").so_that("id".equals(attempt)))
+      .await?;
+    tx.commit().await?;
+    info!(
+      "merge attempt {} in {} {}: queued {:?} again",
+      attempt, repo, reason, prs
+    );
+
+    for pr in rebuilt {
+      self
+        .sync_state_label(repo, pr, Some(PrState::Split.into()))
+        .await?;
+    }
+    for &pr in &prs {
+      self
+        .sync_state_label(repo, pr, Some(PrState::Queued.into()))
+        .await?;
+      self.comment(repo, pr, message).await?;
+    }
+    self.construct(repo).await
   }
 
   pub async fn cancel(&self) {
     todo!()
   }
 
-  pub async fn poll(&self) {
-    todo!()
+  /// One tick of the Poll timer: expire PRs and merge attempts which have
+  /// been in the same state for too long, and retry stalled transitions.
+  pub async fn poll(&self) -> Result<(), ControllerError> {
+    if let Err(e) = self.end_freezes().await {
+      error!("poll: ending freezes: {}", e);
+    }
+    if let Err(e) = self.recheck_targets().await {
+      error!("poll: checking target branches: {}", e);
+    }
+    let now = self.clock.now();
+    let mut repos: HashMap<Repository, (Vec<PrRow>, Vec<AttemptRow>)> = HashMap::new();
+    // target branch and priority of each PR, to tell whether it is held
+    let mut holds: HashMap<(Repository, i64), (String, i64)> = HashMap::new();
+    // merge group and attempt of each PR in a merge group
+    let mut grouped: Vec<(String, Option<String>)> = vec![];
+    for row in self.db.select(Select::from_table("pull_request")).await? {
+      if let Some(group) = row["merge_group"].to_string() {
+        grouped.push((group, row["merge_attempt"].to_string()));
+      }
+      let number = row["number"].as_i64().unwrap();
+      holds.insert(
+        (row_repo(&row), number),
+        (
+          row["base_ref"].to_string().unwrap_or_default(),
+          row["priority"].as_i64().unwrap_or(0),
+        ),
+      );
+      repos.entry(row_repo(&row)).or_default().0.push(PrRow {
+        number,
+        state: (&row["state"]).try_into()?,
+        merge_attempt: row["merge_attempt"].to_string(),
+        held: false,
+        timestamp: row["timestamp"].as_i64().unwrap(),
+      });
+    }
+    let attempts: Vec<(Repository, AttemptRow)> = self
+      .db
+      .select(Select::from_table("merge_attempt"))
+      .await?
+      .into_iter()
+      .map(|row| {
+        Ok((
+          row_repo(&row),
+          AttemptRow {
+            id: row["id"].to_string().unwrap(),
+            state: (&row["state"]).try_into()?,
+            base_attempt: row["base_attempt"].to_string(),
+            group_pending: false,
+            timestamp: row["timestamp"].as_i64().unwrap(),
+          },
+        ))
+      })
+      .collect::<Result<_, ControllerError>>()?;
+    let passed = |attempt: &Option<String>| {
+      attempts
+        .iter()
+        .any(|(_, a)| Some(&a.id) == attempt.as_ref() && matches!(a.state, MergeState::Success))
+    };
+    let pending_groups: Vec<&str> = grouped
+      .iter()
+      .filter(|(_, attempt)| !passed(attempt))
+      .map(|(group, _)| group.as_str())
+      .collect();
+    for (repo, mut attempt) in attempts.iter().cloned() {
+      attempt.group_pending = grouped.iter().any(|(group, a)| {
+        a.as_ref() == Some(&attempt.id) && pending_groups.contains(&group.as_str())
+      });
+      repos.entry(repo).or_default().1.push(attempt);
+    }
+
+    for (repo, (mut prs, attempts)) in repos {
+      let config = match self.config(&repo).await {
+        Ok(config) => config,
+        Err(e) => {
+          error!("poll: loading configuration of {}: {}", repo, e);
+          continue;
+        }
+      };
+      // a closed tree, a freeze or failing checks on the target branch do
+      // not cancel the PRs they hold
+      let closed = tree_closed(&self.db, &repo).await?.unwrap_or(0);
+      let mut paused: HashMap<String, bool> = HashMap::new();
+      for pr in &mut prs {
+        if let Some((target, priority)) = holds.get(&(repo.clone(), pr.number)) {
+          if !paused.contains_key(target) {
+            let health = target_health(&self.db, &repo, target).await?;
+            paused.insert(target.clone(), health.map_or(false, |h| h.pauses()));
+          }
+          pr.held =
+            *priority < closed || config.active_freeze(target, now).is_some() || paused[target];
+        }
+      }
+      for action in poll::plan(&config.timeouts, &prs, &attempts, now) {
+        if let Err(e) = self.poll_action(&repo, action).await {
+          error!("poll: {}: {}", repo, e);
+        }
+      }
+    }
+    Ok(())
+  }
+
+  async fn poll_action(&self, repo: &Repository, action: Action) -> Result<(), ControllerError> {
+    match action {
+      Action::Expire {
+        prs,
+        attempt,
+        reason,
+      } => self.expire(repo, &prs, attempt.as_deref(), reason).await,
+      Action::Initiate(pr) => self.initiate(repo, pr).await,
+      Action::Test(attempt) => self.test(repo, &attempt).await,
+      Action::Complete(attempt) => self.complete(repo, &attempt).await,
+      Action::Construct => self.construct(repo).await,
+    }
+  }
+
+  /// Mark the candidates carrying one of the repo's `alone_labels` as merged
+  /// alone.
+  async fn mark_alone(
+    &self,
+    repo: &Repository,
+    config: &RepoConfig,
+    candidates: &mut [batching::Candidate],
+  ) -> Result<(), ControllerError> {
+    if config.batching.alone_labels.is_empty() {
+      return Ok(());
+    }
+    for candidate in candidates.iter_mut().filter(|c| !c.alone) {
+      let labels = self.client.labels(repo, candidate.number).await?;
+      candidate.alone = labels
+        .iter()
+        .any(|label| config.batching.alone_labels.contains(label));
+    }
+    Ok(())
+  }
+
+  /// Outcomes of the last `count` tested batches into `target` in `repo`,
+  /// most recent first, with `true` for success.
+  async fn recent_batch_results(
+    &self,
+    db: &impl Queryable,
+    repo: &Repository,
+    target: &str,
+    count: usize,
+  ) -> Result<Vec<bool>, ControllerError> {
+    let rows = db
+      .select(
+        Select::from_table("batch_result")
+          .so_that(target_row(repo, target))
+          .order_by("timestamp".descend())
+          .limit(count),
+      )
+      .await?;
+    Ok(
+      rows
+        .into_iter()
+        .map(|row| row["success"].as_i64() == Some(1))
+        .collect(),
+    )
+  }
+
+  /// Record the outcome of testing a batch of `size` PRs into `target`, for
+  /// adaptive batch sizes.
+  async fn record_batch_result(
+    &self,
+    db: &impl Queryable,
+    repo: &Repository,
+    target: &str,
+    size: usize,
+    success: bool,
+  ) -> Result<(), ControllerError> {
+    db.insert(
+      Insert::single_into("batch_result")
+        .value("owner", repo.owner.as_str())
+        .value("repo", repo.repo.as_str())
+        .value("base_ref", target)
+        .value("size", size as i64)
+        .value("success", i64::from(success))
+        .value("timestamp", self.timestamp())
+        .build(),
+    )
+    .await?;
+    Ok(())
+  }
+
+  /// Record why `pr` left merge attempt `from`, and the SPLIT attempt it was
+  /// moved to, if any.
+  async fn record_split(
+    &self,
+    db: &impl Queryable,
+    repo: &Repository,
+    pr: i64,
+    from: &str,
+    to: Option<&str>,
+    reason: &str,
+  ) -> Result<(), ControllerError> {
+    db.insert(
+      Insert::single_into("split_history")
+        .value("owner", repo.owner.as_str())
+        .value("repo", repo.repo.as_str())
+        .value("number", pr)
+        .value("from_attempt", from)
+        .value(
+          "to_attempt",
+          to.map_or(ParameterizedValue::Null, Into::into),
+        )
+        .value("reason", reason)
+        .value("timestamp", self.timestamp())
+        .build(),
+    )
+    .await?;
+    Ok(())
+  }
+
+  /// Delete timed-out PRs and their merge attempt, and report it on each PR.
+  async fn expire(
+    &self,
+    repo: &Repository,
+    prs: &[i64],
+    attempt: Option<&str>,
+    reason: &str,
+  ) -> Result<(), ControllerError> {
+    info!("expiring {} {:?} ({:?}): {}", repo, prs, attempt, reason);
+    let tx = self.db.start_transaction().await?;
+    for &pr in prs {
+      tx.delete(Delete::from_table("pull_request").so_that(pr_row(repo, pr)))
+        .await?;
+    }
+    let mut rebuilt = vec![];
+    if let Some(attempt) = attempt {
+      let row = tx
+        .select(Select::from_table("merge_attempt").so_that("id".equals(attempt)))
+        .await?
+        .into_iter()
+        .next();
+      if let Some(row) = row {
+        if matches!((&row["state"]).try_into()?, MergeState::Testing) {
+          let target = row["base_ref"].to_string().unwrap_or_default();
+          self
+            .record_batch_result(&tx, repo, &target, prs.len(), false)
+            .await?;
+        }
+      }
+      rebuilt = self.rebuild_after(&tx, repo, attempt, "timed out").await?;
+      tx.delete(Delete::from_table("merge_attempt").so_that("id".equals(attempt)))
+        .await?;
+    }
+    tx.commit().await?;
+    for pr in rebuilt {
+      self
+        .sync_state_label(repo, pr, Some(PrState::Split.into()))
+        .await?;
+    }
+    for &pr in prs {
+      self.sync_state_label(repo, pr, None).await?;
+      self
+        .comment(
+          repo,
+          pr,
+          format!("Merge cancelled: timed out because {}.", reason).as_str(),
+        )
+        .await?;
+    }
+    Ok(())
+  }
+}
+
+/// Links between the PRs of `repo` and the PRs they are stacked on.
+async fn stack_links(
+  db: &impl Queryable,
+  repo: &Repository,
+) -> Result<Vec<stack::Link>, ControllerError> {
+  let rows = db
+    .select(
+      Select::from_table("pull_request")
+        .so_that("owner".equals(repo.owner.as_str()))
+        .and_where("repo".equals(repo.repo.as_str())),
+    )
+    .await?;
+  rows
+    .into_iter()
+    .map(|row| {
+      Ok(stack::Link {
+        number: row["number"].as_i64().unwrap(),
+        below: row["stack_below"].as_i64(),
+        queued: matches!((&row["state"]).try_into()?, PrState::Queued),
+      })
+    })
+    .collect()
+}
+
+/// State of a merge attempt, or `None` if it does not exist.
+async fn attempt_state(
+  db: &impl Queryable,
+  attempt: &str,
+) -> Result<Option<MergeState>, ControllerError> {
+  let rows = db
+    .select(Select::from_table("merge_attempt").so_that("id".equals(attempt)))
+    .await?;
+  rows
+    .into_iter()
+    .next()
+    .map(|row| (&row["state"]).try_into())
+    .transpose()
+}
+
+/// PR numbers as `#1, #2`.
+fn pr_list(prs: &[i64]) -> String {
+  prs
+    .iter()
+    .map(|pr| format!("#{}", pr))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// Strategy in the `strategy` column of a `pull_request` row, or the
+/// repository's default.
+fn row_strategy(
+  config: &RepoConfig,
+  value: &ParameterizedValue<'_>,
+) -> Result<Strategy, ControllerError> {
+  match value.as_str() {
+    Some(s) => s
+      .parse()
+      .map_err(|_| ControllerError::InvalidStrategy(s.to_string())),
+    None => Ok(config.strategy),
+  }
+}
+
+/// Repository of a `pull_request` or `merge_attempt` row.
+fn row_repo(row: &quaint::connector::ResultRow) -> Repository {
+  Repository {
+    id: row["repo_id"].as_i64().unwrap(),
+    owner: row["owner"].to_string().unwrap(),
+    repo: row["repo"].to_string().unwrap(),
   }
 }
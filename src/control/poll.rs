@@ -0,0 +1,278 @@
+use super::{MergeState, PrState};
+use crate::config::repo::Timeouts;
+
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Duration, Utc};
+
+/// A row of `pull_request` within one repository.
+#[derive(Debug, Clone)]
+pub(super) struct PrRow {
+  pub(super) number: i64,
+  pub(super) state: PrState,
+  pub(super) merge_attempt: Option<String>,
+  /// Whether a closed tree or a freeze window holds the PR, so that it does
+  /// not time out while QUEUED.
+  pub(super) held: bool,
+  pub(super) timestamp: i64,
+}
+
+/// A row of `merge_attempt` within one repository.
+#[derive(Debug, Clone)]
+pub(super) struct AttemptRow {
+  pub(super) id: String,
+  pub(super) state: MergeState,
+  /// Attempt of the train this attempt was built on.
+  pub(super) base_attempt: Option<String>,
+  /// Whether PRs linked with its PRs in a merge group have not all passed
+  /// their checks yet.
+  pub(super) group_pending: bool,
+  pub(super) timestamp: i64,
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Action {
+  /// Delete the PRs and merge attempt, and report the timeout on each PR.
+  Expire {
+    prs: Vec<i64>,
+    attempt: Option<String>,
+    reason: &'static str,
+  },
+  Initiate(i64),
+  Test(String),
+  Complete(String),
+  Construct,
+}
+
+fn expired(timestamp: i64, timeout: Duration, now: DateTime<Utc>) -> bool {
+  now.timestamp() - timestamp > timeout.num_seconds()
+}
+
+fn pr_reason(state: PrState) -> &'static str {
+  match state {
+    PrState::Requested => "the PR did not become ready to merge",
+    PrState::Queued => "the PR waited too long in the queue",
+    PrState::Merging => "the merge took too long",
+    PrState::Split => "the PR waited too long to be retried",
+  }
+}
+
+fn attempt_reason(state: MergeState) -> &'static str {
+  match state {
+    MergeState::Constructing => "constructing the merge took too long",
+    MergeState::Testing => "checks on the staging branch took too long",
+    MergeState::Success => "completing the merge took too long",
+    MergeState::Split => "the PR waited too long to be retried",
+  }
+}
+
+/// Decide what one poll tick does in a repository, following the Poll
+/// section of `docs/plan.md`.  Expirations come first, so that nothing is
+/// triggered for the PRs and attempts they remove.
+pub(super) fn plan(
+  timeouts: &Timeouts,
+  prs: &[PrRow],
+  attempts: &[AttemptRow],
+  now: DateTime<Utc>,
+) -> Vec<Action> {
+  let mut actions = vec![];
+  let mut expired_attempts = BTreeSet::new();
+  let mut expire_attempt = |actions: &mut Vec<Action>, id: &str, reason| {
+    if expired_attempts.insert(id.to_string()) {
+      actions.push(Action::Expire {
+        prs: prs
+          .iter()
+          .filter(|pr| pr.merge_attempt.as_deref() == Some(id))
+          .map(|pr| pr.number)
+          .collect(),
+        attempt: Some(id.to_string()),
+        reason,
+      });
+    }
+  };
+
+  // attempts of a train are completed in order, so a SUCCESS attempt waits
+  // for the one it was built on, and for the rest of its merge group
+  let waiting = |attempt: &AttemptRow| match &attempt.base_attempt {
+    _ if attempt.group_pending => true,
+    Some(base) => attempts.iter().any(|a| a.id == *base),
+    None => false,
+  };
+
+  for attempt in attempts {
+    let timeout = match attempt.state {
+      MergeState::Constructing => timeouts.constructing,
+      MergeState::Testing => timeouts.testing,
+      MergeState::Success if waiting(attempt) => continue,
+      MergeState::Success => timeouts.success,
+      MergeState::Split => continue,
+    };
+    if expired(attempt.timestamp, timeout, now) {
+      expire_attempt(&mut actions, &attempt.id, attempt_reason(attempt.state));
+    }
+  }
+  let mut expired_prs = BTreeSet::new();
+  for pr in prs {
+    let timeout = match pr.state {
+      PrState::Requested => timeouts.requested,
+      PrState::Queued if pr.held => continue,
+      PrState::Queued => timeouts.queued,
+      PrState::Merging => timeouts.merging,
+      PrState::Split => timeouts.split,
+    };
+    if !expired(pr.timestamp, timeout, now) {
+      continue;
+    }
+    match &pr.merge_attempt {
+      Some(id) => expire_attempt(&mut actions, id, pr_reason(pr.state)),
+      None => {
+        expired_prs.insert(pr.number);
+        actions.push(Action::Expire {
+          prs: vec![pr.number],
+          attempt: None,
+          reason: pr_reason(pr.state),
+        });
+      }
+    }
+  }
+
+  let live_prs = prs.iter().filter(|pr| match &pr.merge_attempt {
+    Some(id) => !expired_attempts.contains(id),
+    None => !expired_prs.contains(&pr.number),
+  });
+  let mut construct = false;
+  for pr in live_prs {
+    match pr.state {
+      PrState::Requested => actions.push(Action::Initiate(pr.number)),
+      PrState::Queued => construct = true,
+      PrState::Merging | PrState::Split => (),
+    }
+  }
+  for attempt in attempts {
+    if expired_attempts.contains(&attempt.id) {
+      continue;
+    }
+    match attempt.state {
+      MergeState::Constructing => (),
+      MergeState::Testing => actions.push(Action::Test(attempt.id.clone())),
+      MergeState::Success if waiting(attempt) => (),
+      MergeState::Success => actions.push(Action::Complete(attempt.id.clone())),
+      MergeState::Split => construct = true,
+    }
+  }
+  if construct {
+    actions.push(Action::Construct);
+  }
+  actions
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::TimeZone;
+
+  fn pr(number: i64, state: PrState, merge_attempt: Option<&str>, timestamp: i64) -> PrRow {
+    PrRow {
+      number,
+      state,
+      merge_attempt: merge_attempt.map(str::to_string),
+      held: false,
+      timestamp,
+    }
+  }
+
+  fn attempt(id: &str, state: MergeState, timestamp: i64) -> AttemptRow {
+    AttemptRow {
+      id: id.to_string(),
+      state,
+      base_attempt: None,
+      group_pending: false,
+      timestamp,
+    }
+  }
+
+  #[test]
+  fn test_plan() {
+    let time = Utc.timestamp(100_000, 0);
+    let timeouts = Timeouts::default();
+    let hour = 3600;
+    let now = time.timestamp();
+
+    let prs = vec![
+      pr(1, PrState::Requested, None, now - 2 * hour),
+      pr(2, PrState::Requested, None, now - 60),
+      pr(3, PrState::Queued, None, now - 60),
+      pr(4, PrState::Merging, Some("a"), now - 60),
+      pr(5, PrState::Merging, Some("a"), now - 60),
+      pr(6, PrState::Split, Some("b"), now - 25 * hour),
+    ];
+    let attempts = vec![
+      attempt("a", MergeState::Testing, now - 2 * hour),
+      attempt("b", MergeState::Split, now - 60),
+    ];
+    assert_eq!(
+      plan(&timeouts, &prs, &attempts, time),
+      vec![
+        Action::Expire {
+          prs: vec![4, 5],
+          attempt: Some("a".to_string()),
+          reason: attempt_reason(MergeState::Testing),
+        },
+        Action::Expire {
+          prs: vec![1],
+          attempt: None,
+          reason: pr_reason(PrState::Requested),
+        },
+        Action::Expire {
+          prs: vec![6],
+          attempt: Some("b".to_string()),
+          reason: pr_reason(PrState::Split),
+        },
+        Action::Initiate(2),
+        Action::Construct,
+      ]
+    );
+
+    let attempts = vec![attempt("a", MergeState::Success, now - 60)];
+    assert_eq!(
+      plan(&timeouts, &prs[3..5], &attempts, time),
+      vec![Action::Complete("a".to_string())]
+    );
+    let attempts = vec![attempt("a", MergeState::Testing, now - 60)];
+    assert_eq!(
+      plan(&timeouts, &prs[3..5], &attempts, time),
+      vec![Action::Test("a".to_string())]
+    );
+
+    // the second attempt of a train waits for the first
+    let attempts = vec![
+      attempt("a", MergeState::Testing, now - 60),
+      AttemptRow {
+        base_attempt: Some("a".to_string()),
+        ..attempt("b", MergeState::Success, now - hour)
+      },
+    ];
+    assert_eq!(
+      plan(&timeouts, &prs[3..5], &attempts, time),
+      vec![Action::Test("a".to_string())]
+    );
+
+    // a part of a merge group waits for the others, however long
+    let attempts = vec![AttemptRow {
+      group_pending: true,
+      ..attempt("a", MergeState::Success, now - 25 * hour)
+    }];
+    assert_eq!(plan(&timeouts, &prs[3..5], &attempts, time), vec![]);
+
+    // a PR held by a closed tree or a freeze does not time out
+    let held = PrRow {
+      held: true,
+      ..pr(7, PrState::Queued, None, now - 25 * hour)
+    };
+    assert_eq!(
+      plan(&timeouts, &[held], &[], time),
+      vec![Action::Construct]
+    );
+  }
+}
@@ -0,0 +1,42 @@
+/// Keywords linking a PR to the issues it closes, as recognized by GitHub.
+const KEYWORDS: [&str; 9] = [
+  "close", "closes", "closed", "fix", "fixes", "fixed", "resolve", "resolves", "resolved",
+];
+
+/// Issues of the same repository which a PR `body` closes, like `Fixes #12`,
+/// in order of appearance.
+pub(super) fn linked_issues(body: &str) -> Vec<i64> {
+  let words: Vec<&str> = body.split_whitespace().collect();
+  let mut issues = vec![];
+  for pair in words.windows(2) {
+    let keyword = pair[0].trim_end_matches(':').to_lowercase();
+    if !KEYWORDS.contains(&keyword.as_str()) {
+      continue;
+    }
+    let number = pair[1]
+      .strip_prefix('#')
+      .map(|n| n.trim_end_matches(|c: char| !c.is_ascii_digit()))
+      .and_then(|n| n.parse::<i64>().ok());
+    if let Some(number) = number {
+      if !issues.contains(&number) {
+        issues.push(number);
+      }
+    }
+  }
+  issues
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_linked_issues() {
+    assert_eq!(
+      linked_issues("Fixes #12, closes #3.\n\nResolved: #12\nsee #4, fix owner/repo#5"),
+      vec![12, 3]
+    );
+    assert_eq!(linked_issues("Fix #"), Vec::<i64>::new());
+    assert_eq!(linked_issues("prefix #7"), Vec::<i64>::new());
+  }
+}
@@ -0,0 +1,397 @@
+use super::backend::{BackendError, MergeBackend};
+use super::construct::PrRange;
+use super::{pr_list, pr_row, Controller, ControllerError, REVERT_PRIORITY};
+use crate::control::command::{MergeOptions, RevertOptions};
+use crate::github::client::MergeResult;
+use crate::github::types::Repository;
+
+use std::collections::HashMap;
+
+use log::info;
+use quaint::ast::{Comparable, Orderable, Select};
+use quaint::connector::{Queryable, TransactionCapable};
+
+/// Commits to revert, as `from..to` on the target branch, and the message
+/// of the revert commit.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Reverted {
+  pub(super) from: String,
+  pub(super) to: String,
+  pub(super) message: String,
+}
+
+/// Result of building revert commits.
+#[derive(Debug)]
+pub(super) enum Outcome {
+  /// Head of the last revert commit.
+  Built(String),
+  /// A revert conflicts with changes made since, in these paths.
+  Conflict(Vec<String>),
+}
+
+/// Build revert commits on top of `target_hash`, one for each of
+/// `reverted` in turn.  Each reverts the changes between `to` and `from`:
+/// a commit with the tree of `from` on top of `to` is merged into
+/// `tmp_branch`, and the result is committed again with a single parent.
+pub(super) async fn build(
+  backend: &dyn MergeBackend,
+  repo: &Repository,
+  tmp_branch: &str,
+  target_hash: &str,
+  reverted: &[Reverted],
+) -> Result<Outcome, BackendError> {
+  // reset rather than created, in case an interrupted revert left it behind
+  backend.reset_branch(repo, tmp_branch, target_hash).await?;
+  let mut head = target_hash.to_string();
+  for revert in reverted {
+    let tree = backend.commit_tree(repo, &revert.from).await?;
+    let inverse = backend
+      .create_commit(repo, &revert.message, &tree, &[&revert.to], None)
+      .await?;
+    let merged = match backend
+      .merge(repo, tmp_branch, &inverse, &revert.message)
+      .await?
+    {
+      MergeResult::Merged(hash) => hash,
+      MergeResult::NothingToMerge => continue,
+      MergeResult::Conflict(paths) => {
+        backend.delete_branch(repo, tmp_branch).await?;
+        return Ok(Outcome::Conflict(paths));
+      }
+    };
+    let tree = backend.commit_tree(repo, &merged).await?;
+    head = backend
+      .create_commit(repo, &revert.message, &tree, &[&head], None)
+      .await?;
+    backend.reset_branch(repo, tmp_branch, &head).await?;
+  }
+  backend.delete_branch(repo, tmp_branch).await?;
+  Ok(Outcome::Built(head))
+}
+
+/// The ranges of PRs which landed together, from the last one, which ends
+/// at `staging_hash`, back to the first.
+pub(super) fn newest_first(mut ranges: Vec<PrRange>, staging_hash: &str) -> Vec<PrRange> {
+  let mut sorted = vec![];
+  let mut head = staging_hash.to_string();
+  while let Some(i) = ranges.iter().position(|range| range.to == head) {
+    let range = ranges.remove(i);
+    head = range.from.clone();
+    sorted.push(range);
+  }
+  sorted
+}
+
+/// Message of the commit reverting `number`, titled `title`.
+pub(super) fn pr_message(number: i64, title: &str, to: &str) -> String {
+  format!(
+    "Revert \"{} (#{})\"\n\nThis reverts #{}, merged in {}.",
+    title, number, number, to
+  )
+}
+
+/// Message of the single commit reverting a whole merge attempt.
+pub(super) fn attempt_message(attempt: &str, prs: &[i64], staging_hash: &str) -> String {
+  format!(
+    "Revert {}\n\nThis reverts merge attempt {}, merged in {}.",
+    super::pr_list(prs),
+    attempt,
+    staging_hash
+  )
+}
+
+/// Description of the PR reverting `prs`, given as `(number, title)`, which
+/// landed in `target` at `staging_hash`.
+pub(super) fn description(
+  prs: &[(i64, String)],
+  target: &str,
+  attempt: &str,
+  staging_hash: &str,
+  requested_by: &str,
+  requested_on: i64,
+) -> String {
+  let mut description = match prs {
+    [(number, _)] => format!(
+      "Reverts #{}, which was merged into `{}` in {} by merge attempt {}.\n",
+      number, target, staging_hash, attempt
+    ),
+    _ => {
+      let mut description = format!(
+        "Reverts the batch merged into `{}` in {} by merge attempt {}:\n\n",
+        target, staging_hash, attempt
+      );
+      for (number, title) in prs {
+        description.push_str(&format!("- #{}: {}\n", number, title));
+      }
+      description
+    }
+  };
+  description.push_str(&format!(
+    "\nRequested by @{} in #{}.",
+    requested_by, requested_on
+  ));
+  description
+}
+
+impl<Q> Controller<Q>
+where
+  Q: Queryable + TransactionCapable + 'static,
+{
+  /// Open a PR reverting `pr`, or the merge attempt given in `options`,
+  /// after it landed, on behalf of `requested_by`.
+  pub async fn revert(
+    &self,
+    repo: &Repository,
+    pr: i64,
+    options: RevertOptions,
+    requested_by: &str,
+  ) -> Result<(), ControllerError> {
+    let attempt = match &options.attempt {
+      Some(attempt) => attempt.clone(),
+      None => {
+        let landed = self
+          .db
+          .select(
+            Select::from_table("landed_pr")
+              .so_that(pr_row(repo, pr))
+              .order_by("timestamp".descend()),
+          )
+          .await?
+          .into_iter()
+          .next();
+        match landed.and_then(|row| row["attempt"].to_string()) {
+          Some(attempt) => attempt,
+          None => {
+            self
+              .comment(repo, pr, "Error: this PR was not merged by cherry.")
+              .await?;
+            return Ok(());
+          }
+        }
+      }
+    };
+    let landed = self
+      .db
+      .select(
+        Select::from_table("landed_attempt")
+          .so_that("id".equals(attempt.as_str()))
+          .and_where("owner".equals(repo.owner.as_str()))
+          .and_where("repo".equals(repo.repo.as_str())),
+      )
+      .await?
+      .into_iter()
+      .next();
+    let (target, target_hash, staging_hash) = match landed {
+      Some(row) => (
+        row["base_ref"].to_string().unwrap_or_default(),
+        row["target_hash"].to_string().unwrap_or_default(),
+        row["staging_hash"].to_string().unwrap_or_default(),
+      ),
+      None => {
+        let message = format!(
+          "Error: merge attempt `{}` did not land in this repository.",
+          attempt
+        );
+        self.comment(repo, pr, message.as_str()).await?;
+        return Ok(());
+      }
+    };
+    let rows = self
+      .db
+      .select(Select::from_table("landed_pr").so_that("attempt".equals(attempt.as_str())))
+      .await?;
+    let mut numbers = vec![];
+    let mut ranges = vec![];
+    for row in rows {
+      let number = row["number"].as_i64().unwrap();
+      numbers.push(number);
+      if let (Some(from), Some(to)) = (
+        row["staging_from"].to_string(),
+        row["staging_to"].to_string(),
+      ) {
+        ranges.push(PrRange { number, from, to });
+      }
+    }
+    let ranges = newest_first(ranges, &staging_hash);
+    let reverted_prs: Vec<i64> = if options.attempt.is_some() {
+      numbers.sort_unstable();
+      numbers
+    } else {
+      vec![pr]
+    };
+    let mut titles = HashMap::new();
+    for &number in &reverted_prs {
+      titles.insert(number, self.client.pr_info(repo, number).await?.title);
+    }
+    let reverted = if options.attempt.is_none() || options.each {
+      let reverted: Vec<Reverted> = ranges
+        .iter()
+        .filter(|range| reverted_prs.contains(&range.number))
+        .map(|range| Reverted {
+          from: range.from.clone(),
+          to: range.to.clone(),
+          message: pr_message(range.number, &titles[&range.number], &range.to),
+        })
+        .collect();
+      if reverted.is_empty() {
+        let message = format!(
+          "Error: {} landed in a single commit with the rest of its batch.  Revert the whole batch with `cherry revert {}`.",
+          if options.attempt.is_none() { "this PR" } else { "each PR" },
+          attempt
+        );
+        self.comment(repo, pr, message.as_str()).await?;
+        return Ok(());
+      }
+      reverted
+    } else {
+      vec![Reverted {
+        from: target_hash,
+        to: staging_hash.clone(),
+        message: attempt_message(&attempt, &reverted_prs, &staging_hash),
+      }]
+    };
+
+    let branch = match &options.attempt {
+      Some(attempt) => format!("cherry/revert-{}", attempt),
+      None => format!("cherry/revert-{}", pr),
+    };
+    if self.client.branch_hash(repo, &branch).await?.is_some() {
+      let message = format!("Error: branch `{}` already exists.", branch);
+      self.comment(repo, pr, message.as_str()).await?;
+      return Ok(());
+    }
+    let intent = || {
+      format!(
+        "open a PR reverting {} into `{}` from `{}`",
+        pr_list(&reverted_prs),
+        target,
+        branch
+      )
+    };
+    if !self.should_write(repo, Some(pr), intent).await? {
+      return Ok(());
+    }
+    let head = self.backend.fetch(repo, &target, &[]).await?;
+    let tmp_branch = format!("{}-tmp", branch);
+    let head = match build(self.backend.as_ref(), repo, &tmp_branch, &head, &reverted).await? {
+      Outcome::Built(head) => head,
+      Outcome::Conflict(paths) => {
+        let mut message = format!(
+          "Error: reverting {} conflicts with changes made to `{}` since.",
+          pr_list(&reverted_prs),
+          target
+        );
+        if !paths.is_empty() {
+          message.push_str("\n\nFiles involved:");
+          for path in &paths {
+            message.push_str(&format!("\n- `{}`", path));
+          }
+        }
+        self.comment(repo, pr, message.as_str()).await?;
+        return Ok(());
+      }
+    };
+    self.backend.publish(repo, &branch, &head).await?;
+    let title = match reverted_prs.as_slice() {
+      [number] => format!("Revert \"{}\"", titles[number]),
+      _ => format!("Revert {}", pr_list(&reverted_prs)),
+    };
+    let reverted_titles: Vec<(i64, String)> = reverted_prs
+      .iter()
+      .map(|number| (*number, titles[number].clone()))
+      .collect();
+    let description = description(
+      &reverted_titles,
+      &target,
+      &attempt,
+      &staging_hash,
+      requested_by,
+      pr,
+    );
+    let revert_pr = self
+      .client
+      .create_pr(repo, &branch, &target, &title, &description)
+      .await?;
+    info!(
+      "opened {} #{} reverting {:?} of merge attempt {}",
+      repo, revert_pr, reverted_prs, attempt
+    );
+    self
+      .log_attempt(
+        repo,
+        &attempt,
+        &format!("reverted {} in #{}", pr_list(&reverted_prs), revert_pr),
+      )
+      .await?;
+    self
+      .comment(
+        repo,
+        pr,
+        format!(
+          "Opened #{} to revert {}.",
+          revert_pr,
+          pr_list(&reverted_prs)
+        )
+        .as_str(),
+      )
+      .await?;
+    if options.queue {
+      let options = MergeOptions {
+        priority: REVERT_PRIORITY,
+        ..MergeOptions::default()
+      };
+      self.request(repo, revert_pr, options).await?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn range(number: i64, from: &str, to: &str) -> PrRange {
+    PrRange {
+      number,
+      from: from.to_string(),
+      to: to.to_string(),
+    }
+  }
+
+  #[test]
+  fn test_newest_first() {
+    let ranges = vec![range(1, "t", "a"), range(3, "b", "c"), range(2, "a", "b")];
+    assert_eq!(
+      newest_first(ranges.clone(), "c"),
+      vec![range(3, "b", "c"), range(2, "a", "b"), range(1, "t", "a")]
+    );
+    assert_eq!(newest_first(ranges, "x"), vec![]);
+  }
+
+  #[test]
+  fn test_messages() {
+    assert_eq!(
+      pr_message(12, "Fix the frobnicator", "abc"),
+      "Revert \"Fix the frobnicator (#12)\"\n\nThis reverts #12, merged in abc."
+    );
+    assert_eq!(
+      attempt_message("a1", &[12, 13], "abc"),
+      "Revert #12, #13\n\nThis reverts merge attempt a1, merged in abc."
+    );
+    assert_eq!(
+      description(&[(12, "Fix".to_string())], "main", "a1", "abc", "alice", 12),
+      "Reverts #12, which was merged into `main` in abc by merge attempt a1.\n\nRequested by @alice in #12."
+    );
+    assert_eq!(
+      description(
+        &[(12, "Fix".to_string()), (13, "Add".to_string())],
+        "main",
+        "a1",
+        "abc",
+        "alice",
+        13
+      ),
+      "Reverts the batch merged into `main` in abc by merge attempt a1:\n\n- #12: Fix\n- #13: Add\n\nRequested by @alice in #13."
+    );
+  }
+}
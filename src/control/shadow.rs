@@ -0,0 +1,130 @@
+/// A PR which cherry would have merged in shadow mode, at `timestamp`.
+#[derive(Debug, Clone)]
+pub(super) struct ShadowMerge {
+  pub(super) number: i64,
+  pub(super) timestamp: i64,
+}
+
+/// A PR merged on GitHub, by hand, at `timestamp`.
+#[derive(Debug, Clone)]
+pub(super) struct HumanMerge {
+  pub(super) number: i64,
+  pub(super) timestamp: i64,
+}
+
+/// A duration in seconds as hours and minutes, like `2h 5m`.
+fn duration(seconds: i64) -> String {
+  let minutes = seconds.abs() / 60;
+  if minutes < 60 {
+    format!("{}m", minutes)
+  } else {
+    format!("{}h {}m", minutes / 60, minutes % 60)
+  }
+}
+
+/// Compare what cherry would have merged over the last `days` days with
+/// what was merged by hand.  `queue_state` tells where a PR merged by hand
+/// was in the queue, if it was.
+pub(super) fn report(
+  days: i64,
+  shadow: &[ShadowMerge],
+  human: &[HumanMerge],
+  queue_state: impl Fn(i64) -> Option<String>,
+) -> String {
+  let mut lines = vec![];
+  let mut agreed = 0;
+  for merge in human {
+    match shadow.iter().find(|s| s.number == merge.number) {
+      Some(s) => {
+        agreed += 1;
+        let delay = merge.timestamp - s.timestamp;
+        let when = if delay >= 0 { "after" } else { "before" };
+        lines.push(format!(
+          "- #{}: merged by hand {} {} cherry would have merged it",
+          merge.number,
+          duration(delay),
+          when
+        ));
+      }
+      None => {
+        let why = match queue_state(merge.number) {
+          Some(state) => format!("it was `{}` in the queue", state),
+          None => "it was not in the queue".to_string(),
+        };
+        lines.push(format!(
+          "- #{}: merged by hand, but cherry would not have merged it: {}",
+          merge.number, why
+        ));
+      }
+    }
+  }
+  for merge in shadow {
+    if !human.iter().any(|h| h.number == merge.number) {
+      lines.push(format!(
+        "- #{}: cherry would have merged it, but it was not merged by hand",
+        merge.number
+      ));
+    }
+  }
+  let mut report = format!(
+    "In the last {} days, cherry would have merged {} PR(s), and {} PR(s) were merged by hand; {} of them in both cases.",
+    days,
+    shadow.len(),
+    human.len(),
+    agreed
+  );
+  if !lines.is_empty() {
+    report.push_str("\n\n");
+    report.push_str(&lines.join("\n"));
+  }
+  report
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_report() {
+    let shadow = vec![
+      ShadowMerge {
+        number: 1,
+        timestamp: 1000,
+      },
+      ShadowMerge {
+        number: 2,
+        timestamp: 1000,
+      },
+    ];
+    let human = vec![
+      HumanMerge {
+        number: 1,
+        timestamp: 1000 + 2 * 3600 + 5 * 60,
+      },
+      HumanMerge {
+        number: 3,
+        timestamp: 500,
+      },
+      HumanMerge {
+        number: 4,
+        timestamp: 500,
+      },
+    ];
+    let state = |n: i64| match n {
+      3 => Some("requested".to_string()),
+      _ => None,
+    };
+    assert_eq!(
+      report(7, &shadow, &human, state),
+      "In the last 7 days, cherry would have merged 2 PR(s), and 3 PR(s) were merged by hand; 1 of them in both cases.\n\n\
+       - #1: merged by hand 2h 5m after cherry would have merged it\n\
+       - #3: merged by hand, but cherry would not have merged it: it was `requested` in the queue\n\
+       - #4: merged by hand, but cherry would not have merged it: it was not in the queue\n\
+       - #2: cherry would have merged it, but it was not merged by hand"
+    );
+    assert_eq!(
+      report(1, &[], &[], |_| None),
+      "In the last 1 days, cherry would have merged 0 PR(s), and 0 PR(s) were merged by hand; 0 of them in both cases."
+    );
+  }
+}
@@ -0,0 +1,129 @@
+use crate::config::repo::SplitStrategy;
+
+/// A PR of a batch which failed its checks.
+#[derive(Debug, Clone)]
+pub(super) struct FailedPr {
+  pub(super) number: i64,
+  /// Number of failed batches the PR was in, including this one.
+  pub(super) failures: u32,
+  /// Paths changed by the PR, for `SplitStrategy::Paths`.
+  pub(super) paths: Vec<String>,
+}
+
+/// Split the PRs of a failed batch, in queue order, into the batches
+/// retrying them.  A batch of more than one PR is always split in at least
+/// two.  Also returns how the PRs were split, for the split history.
+pub(super) fn split(strategy: SplitStrategy, prs: &[FailedPr]) -> (Vec<Vec<i64>>, &'static str) {
+  let groups = match strategy {
+    SplitStrategy::Halve => None,
+    SplitStrategy::Isolate => {
+      isolate(prs).map(|groups| (groups, "PRs from earlier failing batches isolated"))
+    }
+    SplitStrategy::Paths => {
+      by_paths(prs).map(|groups| (groups, "grouped by overlapping changed paths"))
+    }
+  };
+  groups.unwrap_or_else(|| (halve(prs), "halved"))
+}
+
+fn halve(prs: &[FailedPr]) -> Vec<Vec<i64>> {
+  let numbers: Vec<i64> = prs.iter().map(|pr| pr.number).collect();
+  let (first, second) = numbers.split_at((numbers.len() + 1) / 2);
+  vec![first.to_vec(), second.to_vec()]
+    .into_iter()
+    .filter(|group| !group.is_empty())
+    .collect()
+}
+
+/// Each PR which was already in a failing batch alone, and the others
+/// together, unless all or none of the PRs were.
+fn isolate(prs: &[FailedPr]) -> Option<Vec<Vec<i64>>> {
+  let (suspects, others): (Vec<_>, Vec<_>) = prs.iter().partition(|pr| pr.failures > 1);
+  if suspects.is_empty() || others.is_empty() {
+    return None;
+  }
+  let mut groups: Vec<Vec<i64>> = suspects.iter().map(|pr| vec![pr.number]).collect();
+  groups.push(others.iter().map(|pr| pr.number).collect());
+  Some(groups)
+}
+
+/// Groups of PRs connected by overlapping changed paths, unless there is
+/// only one.
+fn by_paths(prs: &[FailedPr]) -> Option<Vec<Vec<i64>>> {
+  fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+      parent[i] = parent[parent[i]];
+      i = parent[i];
+    }
+    i
+  }
+  // union-find over PR indices
+  let mut parent: Vec<usize> = (0..prs.len()).collect();
+  for i in 0..prs.len() {
+    for j in 0..i {
+      if prs[i].paths.iter().any(|path| prs[j].paths.contains(path)) {
+        let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+        parent[a] = b;
+      }
+    }
+  }
+  let mut groups: Vec<(usize, Vec<i64>)> = vec![];
+  for (i, pr) in prs.iter().enumerate() {
+    let r = root(&mut parent, i);
+    match groups.iter_mut().find(|(root, _)| *root == r) {
+      Some((_, group)) => group.push(pr.number),
+      None => groups.push((r, vec![pr.number])),
+    }
+  }
+  if groups.len() < 2 {
+    return None;
+  }
+  Some(groups.into_iter().map(|(_, group)| group).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pr(number: i64, failures: u32, paths: &[&str]) -> FailedPr {
+    FailedPr {
+      number,
+      failures,
+      paths: paths.iter().map(|p| p.to_string()).collect(),
+    }
+  }
+
+  #[test]
+  fn test_split() {
+    let prs = vec![
+      pr(1, 1, &["a.txt"]),
+      pr(2, 2, &["b.txt"]),
+      pr(3, 1, &["c.txt", "a.txt"]),
+      pr(4, 1, &[]),
+      pr(5, 2, &["b.txt"]),
+    ];
+    assert_eq!(
+      split(SplitStrategy::Halve, &prs).0,
+      vec![vec![1, 2, 3], vec![4, 5]]
+    );
+    assert_eq!(split(SplitStrategy::Halve, &prs[..1]).0, vec![vec![1]]);
+    assert_eq!(
+      split(SplitStrategy::Isolate, &prs).0,
+      vec![vec![2], vec![5], vec![1, 3, 4]]
+    );
+    // no PR was in a failing batch before
+    assert_eq!(
+      split(SplitStrategy::Isolate, &[prs[0].clone(), prs[2].clone()]).0,
+      vec![vec![1], vec![3]]
+    );
+    assert_eq!(
+      split(SplitStrategy::Paths, &prs).0,
+      vec![vec![1, 3], vec![2, 5], vec![4]]
+    );
+    // all PRs overlap
+    assert_eq!(
+      split(SplitStrategy::Paths, &[prs[0].clone(), prs[2].clone()]).0,
+      vec![vec![1], vec![3]]
+    );
+  }
+}
@@ -0,0 +1,142 @@
+/// A PR in the queue into one target branch, linked to the PR it is stacked
+/// on.
+#[derive(Debug, Clone)]
+pub(super) struct Link {
+  pub(super) number: i64,
+  /// PR this one is stacked on, which must land first.
+  pub(super) below: Option<i64>,
+  pub(super) queued: bool,
+}
+
+/// PR which a PR `body` declares it is stacked on, with a line starting with
+/// `depends on #N`.
+pub(super) fn depends_on(body: &str) -> Option<i64> {
+  body.lines().find_map(|line| {
+    let line = line.trim().to_lowercase();
+    let rest = line.strip_prefix("depends on #")?;
+    let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+  })
+}
+
+/// PRs of the stack containing `number`, bottom first, so that each PR comes
+/// after the PR it is stacked on.  Only `number` if it is not stacked.
+pub(super) fn members(links: &[Link], number: i64) -> Vec<i64> {
+  let below = |n: i64| links.iter().find(|l| l.number == n).and_then(|l| l.below);
+  let mut root = number;
+  let mut depth = 0;
+  while let Some(b) = below(root) {
+    // a cycle only happens with inconsistent data
+    if depth > links.len() || !links.iter().any(|l| l.number == b) {
+      break;
+    }
+    root = b;
+    depth += 1;
+  }
+  let mut members = vec![root];
+  let mut i = 0;
+  while i < members.len() {
+    let mut above: Vec<i64> = links
+      .iter()
+      .filter(|l| l.below == Some(members[i]) && !members.contains(&l.number))
+      .map(|l| l.number)
+      .collect();
+    above.sort_unstable();
+    members.extend(above);
+    i += 1;
+  }
+  members
+}
+
+/// Whether all of `members` are queued.
+pub(super) fn is_queued(links: &[Link], members: &[i64]) -> bool {
+  members
+    .iter()
+    .all(|n| links.iter().any(|l| l.number == *n && l.queued))
+}
+
+/// Priority of each PR below one requested with `priority`, given the
+/// priority of those already queued on their own, bottom first.  The stack
+/// is only batched once its PRs all have the priority being batched, so none
+/// may have less than a PR above it.
+pub(super) fn priorities(priority: i64, below: &[Option<i64>]) -> Vec<i64> {
+  let mut above = priority;
+  let mut priorities: Vec<i64> = below
+    .iter()
+    .rev()
+    .map(|own| {
+      above = above.max(own.unwrap_or(0));
+      above
+    })
+    .collect();
+  priorities.reverse();
+  priorities
+}
+
+/// PRs which can no longer land, because a PR below them left the queue
+/// without landing.
+pub(super) fn orphans(links: &[Link]) -> Vec<i64> {
+  let mut orphans: Vec<i64> = vec![];
+  loop {
+    let present = |n: i64| links.iter().any(|l| l.number == n) && !orphans.contains(&n);
+    let found: Vec<i64> = links
+      .iter()
+      .filter(|l| !orphans.contains(&l.number))
+      .filter(|l| matches!(l.below, Some(b) if !present(b)))
+      .map(|l| l.number)
+      .collect();
+    if found.is_empty() {
+      return orphans;
+    }
+    orphans.extend(found);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn link(number: i64, below: Option<i64>, queued: bool) -> Link {
+    Link {
+      number,
+      below,
+      queued,
+    }
+  }
+
+  #[test]
+  fn test_depends_on() {
+    assert_eq!(depends_on("Adds a thing.\r\n\r\nDepends on #12."), Some(12));
+    assert_eq!(depends_on("depends on #"), None);
+    assert_eq!(depends_on("This depends on #3"), None);
+  }
+
+  #[test]
+  fn test_stacks() {
+    let links = vec![
+      link(1, None, true),
+      link(3, Some(2), true),
+      link(2, Some(1), false),
+      link(4, None, true),
+      link(5, Some(9), true),
+      link(6, Some(5), true),
+    ];
+    assert_eq!(members(&links, 3), vec![1, 2, 3]);
+    assert_eq!(members(&links, 1), vec![1, 2, 3]);
+    assert_eq!(members(&links, 4), vec![4]);
+    assert!(!is_queued(&links, &[1, 2, 3]));
+    assert!(is_queued(&links, &[1, 3]));
+    assert_eq!(orphans(&links), vec![5, 6]);
+  }
+
+  #[test]
+  fn test_priorities() {
+    // `cherry merge p=5` on the top of a stack
+    assert_eq!(priorities(5, &[None, None]), vec![5, 5]);
+    // a PR queued on its own keeps a higher priority, which the PRs below it
+    // then need as well
+    assert_eq!(priorities(5, &[Some(0), Some(9), None]), vec![9, 9, 5]);
+    assert_eq!(priorities(0, &[Some(2)]), vec![2]);
+    assert!(priorities(5, &[]).is_empty());
+  }
+}
@@ -0,0 +1,149 @@
+use super::MergeState;
+
+/// A merge attempt into one target branch.
+#[derive(Debug, Clone)]
+pub(super) struct Attempt {
+  pub(super) id: String,
+  pub(super) state: MergeState,
+  /// Attempt of the train this attempt was built on.
+  pub(super) base_attempt: Option<String>,
+  /// Empty in shadow mode, where nothing is constructed.
+  pub(super) staging_hash: String,
+}
+
+/// Where the next merge attempt into a target branch goes.
+#[derive(Debug, PartialEq)]
+pub(super) struct Next {
+  /// The oldest SPLIT attempt, whose PRs are retried first.
+  pub(super) split_attempt: Option<String>,
+  /// The last attempt of the train, as (id, staging hash), to build on
+  /// speculatively.
+  pub(super) base: Option<(String, String)>,
+}
+
+/// Decide whether another merge attempt can be constructed, given the
+/// attempts into the target branch, oldest first, and the train depth.
+/// Returns `None` while an attempt is being constructed or the train is full.
+pub(super) fn next(depth: usize, attempts: &[Attempt]) -> Option<Next> {
+  let mut split_attempt = None;
+  // attempts being tested or completed
+  let mut in_flight: Vec<&Attempt> = vec![];
+  for attempt in attempts {
+    match attempt.state {
+      MergeState::Split => {
+        if split_attempt.is_none() {
+          split_attempt = Some(attempt.id.clone());
+        }
+      }
+      MergeState::Testing | MergeState::Success if in_flight.len() + 1 < depth => {
+        in_flight.push(attempt);
+      }
+      _ => return None,
+    }
+  }
+  let base = in_flight
+    .iter()
+    .find(|a| {
+      !in_flight
+        .iter()
+        .any(|b| b.base_attempt.as_deref() == Some(a.id.as_str()))
+    })
+    .map(|a| (a.id.clone(), a.staging_hash.clone()));
+  Some(Next {
+    split_attempt,
+    base,
+  })
+}
+
+/// Attempts built on `attempt`, directly or not, in train order, given
+/// (id, base attempt) of each attempt.  They are rebuilt when `attempt` does
+/// not land.
+pub(super) fn built_on(attempts: &[(String, Option<String>)], attempt: &str) -> Vec<String> {
+  let mut built = vec![];
+  let mut base = attempt;
+  while let Some((id, _)) = attempts
+    .iter()
+    .find(|(_, b)| b.as_deref() == Some(base))
+  {
+    built.push(id.clone());
+    base = id;
+  }
+  built
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn attempt(id: &str, state: MergeState, base_attempt: Option<&str>) -> Attempt {
+    Attempt {
+      id: id.to_string(),
+      state,
+      base_attempt: base_attempt.map(str::to_string),
+      staging_hash: format!("{}-hash", id),
+    }
+  }
+
+  fn base(id: &str) -> Option<(String, String)> {
+    Some((id.to_string(), format!("{}-hash", id)))
+  }
+
+  #[test]
+  fn test_next() {
+    let depth = 3;
+    assert_eq!(
+      next(depth, &[]),
+      Some(Next {
+        split_attempt: None,
+        base: None,
+      })
+    );
+
+    // the train grows on its last attempt until it is full
+    let a = attempt("a", MergeState::Testing, None);
+    let b = attempt("b", MergeState::Testing, Some("a"));
+    let c = attempt("c", MergeState::Testing, Some("b"));
+    assert_eq!(
+      next(depth, std::slice::from_ref(&a)),
+      Some(Next {
+        split_attempt: None,
+        base: base("a"),
+      })
+    );
+    assert_eq!(
+      next(depth, &[a.clone(), b.clone()]),
+      Some(Next {
+        split_attempt: None,
+        base: base("b"),
+      })
+    );
+    assert_eq!(next(depth, &[a.clone(), b.clone(), c.clone()]), None);
+    assert_eq!(next(1, std::slice::from_ref(&a)), None);
+
+    // nothing is built while an attempt is being constructed
+    let constructing = attempt("d", MergeState::Constructing, None);
+    assert_eq!(next(depth, &[a.clone(), constructing]), None);
+
+    // when `b` fails, `c` is rebuilt on `a`
+    let attempts: Vec<_> = [&a, &b, &c]
+      .iter()
+      .map(|x| (x.id.clone(), x.base_attempt.clone()))
+      .collect();
+    assert_eq!(built_on(&attempts, "b"), vec!["c"]);
+    assert_eq!(built_on(&attempts, "a"), vec!["b", "c"]);
+    assert!(built_on(&attempts, "c").is_empty());
+    let split = Attempt {
+      state: MergeState::Split,
+      base_attempt: None,
+      staging_hash: String::new(),
+      ..c
+    };
+    assert_eq!(
+      next(depth, &[a, split]),
+      Some(Next {
+        split_attempt: Some("c".to_string()),
+        base: base("a"),
+      })
+    );
+  }
+}
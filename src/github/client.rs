@@ -1,4 +1,7 @@
-use crate::github::types::{PullRequest, Repository};
+use crate::github::types::{
+  AccessLevel, Commit, CommitAuthor, GitIdentity, Label, PullRequest, Repository, Review,
+  StatusState,
+};
 
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -19,6 +22,10 @@ use log::info;
 const APP_TOKEN_LIFESPAN_SECS: i64 = 10 * 60;
 const APP_TOKEN_RENEW_AHEAD_SECS: i64 = 30;
 const REPO_TOKEN_RENEW_AHEAD_SECS: i64 = 30;
+/// Maximum size of a comparison response, in bytes.
+const COMPARISON_LIMIT: usize = 16 * 1024 * 1024;
+/// Maximum size of a page of commits, in bytes.
+const COMMIT_LIST_LIMIT: usize = 4 * 1024 * 1024;
 
 #[derive(Debug, Deserialize)]
 pub struct ServerError {
@@ -41,10 +48,16 @@ pub enum ClientError {
   JWT(#[from] jsonwebtoken::errors::Error),
   #[error("in http library")]
   Http(#[from] actix_web::http::Error),
+  #[error("invalid uri")]
+  Uri(#[from] uri::InvalidUri),
   #[error("sending request")]
   SendRequest(actix_web::client::SendRequestError),
   #[error("decoding json payload")]
   JsonPayload, // no re-export of awc::error::JsonPayloadError
+  #[error("decoding base64 content")]
+  Base64(#[from] base64::DecodeError),
+  #[error("app not installed on {0}")]
+  NotInstalled(String),
   #[error("server returned error response")]
   ServerErrorResponse(StatusCode, Result<ServerError, String>),
 }
@@ -55,6 +68,59 @@ impl From<actix_web::client::SendRequestError> for ClientError {
   }
 }
 
+/// Outcome of merging one commit into a branch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeResult {
+  /// The merge commit created.
+  Merged(String),
+  /// The head was already contained in the base.
+  NothingToMerge,
+  /// The paths which conflict, if known.
+  Conflict(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct ChangedFile {
+  filename: String,
+}
+
+#[derive(Deserialize)]
+struct Comparison {
+  commits: Vec<Commit>,
+  total_commits: usize,
+  #[serde(default)]
+  files: Vec<ChangedFile>,
+}
+
+/// Commits reachable from one commit but not from another.
+#[derive(Debug, Clone)]
+pub struct CommitRange {
+  /// The commits, oldest first, possibly only the first of them.
+  pub commits: Vec<Commit>,
+  /// Number of commits in the whole range.
+  pub total_commits: usize,
+}
+
+/// Percent-encode a string for use as a single URI path segment.
+fn path_segment(s: &str) -> String {
+  s.bytes()
+    .map(|b| match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+      _ => format!("%{:02X}", b),
+    })
+    .collect()
+}
+
+/// Percent-encode a branch name or file path for use in a URI path, keeping
+/// its slashes.
+fn branch_path(branch: &str) -> String {
+  branch
+    .split('/')
+    .map(path_segment)
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
 #[derive(Debug, Serialize)]
 struct Claims {
   #[serde(with = "ts_seconds")]
@@ -122,7 +188,11 @@ enum Permission {
 
 #[derive(Debug, Serialize)]
 struct TokenRequest {
+  #[serde(skip_serializing_if = "Vec::is_empty")]
   repository_ids: Vec<i64>,
+  /// Names of repositories, for repositories whose id is not known yet.
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  repositories: Vec<String>,
   permissions: HashMap<PermissionType, Permission>,
 }
 
@@ -182,11 +252,13 @@ impl TokenCache {
   }
 }
 
+#[derive(Clone)]
 pub struct Client {
   credentials: Credentials,
   // TODO use a resource pool to avoid contending on the cache
   token_cache: Arc<Mutex<TokenCache>>,
   client: AwcClient,
+  api_url: uri::Uri,
 }
 
 impl Client {
@@ -194,11 +266,13 @@ impl Client {
     credentials: Credentials,
     token_cache: Arc<Mutex<TokenCache>>,
     client: AwcClient,
+    api_url: uri::Uri,
   ) -> Self {
     Self {
       credentials,
       token_cache,
       client,
+      api_url,
     }
   }
 
@@ -229,41 +303,70 @@ impl Client {
     ))
   }
 
+  /// Installation of the app on the repository `owner/name`, or `None` if
+  /// the app is not installed on it.
+  async fn installation(&self, owner: &str, name: &str) -> Result<Option<i64>, ClientError> {
+    let uri = self.api_uri(
+      format!(
+        "/repos/{}/{}/installation",
+        path_segment(owner),
+        path_segment(name)
+      )
+      .as_str(),
+    )?;
+    let mut response = self.app_request(Method::GET, uri).await?.send().await?;
+    if response.status() == StatusCode::NOT_FOUND {
+      return Ok(None);
+    }
+    Self::response_ok(&mut response).await?;
+    let installation: Installation = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(Some(installation.id))
+  }
+
+  async fn request_installation_token(
+    &self,
+    installation: i64,
+    request: &TokenRequest,
+  ) -> Result<TokenResponse, ClientError> {
+    let uri =
+      self.api_uri(format!("/app/installations/{}/access_tokens", installation).as_str())?;
+    let mut response = self
+      .app_request(Method::POST, uri)
+      .await?
+      .send_json(request)
+      .await?;
+    Self::response_ok(&mut response).await?;
+    response.json().await.map_err(|_| ClientError::JsonPayload)
+  }
+
   async fn request_repo_token(&self, repo: &Repository) -> Result<Token, ClientError> {
-    let installation: Installation = {
-      let uri = self
-        .api()
-        .path_and_query(format!("/repos/{}/installation", repo).as_str())
-        .build()?;
-      let mut response = self.app_request(Method::GET, uri).await?.send().await?;
-      Self::response_ok(&mut response).await?;
-      response
-        .json()
-        .await
-        .map_err(|_| ClientError::JsonPayload)?
-    };
-    let TokenResponse { token, expires_at } = {
-      let uri = self
-        .api()
-        .path_and_query(format!("/app/installations/{}/access_tokens", installation.id).as_str())
-        .build()?;
-      let mut response = self
-        .app_request(Method::POST, uri)
-        .await?
-        .send_json(&TokenRequest {
+    let installation = self
+      .installation(&repo.owner, &repo.repo)
+      .await?
+      .ok_or_else(|| ClientError::NotInstalled(repo.to_string()))?;
+    let TokenResponse { token, expires_at } = self
+      .request_installation_token(
+        installation,
+        &TokenRequest {
           repository_ids: vec![repo.id],
-          permissions: [(PermissionType::Issues, Permission::Write)]
-            .iter()
-            .copied()
-            .collect(),
-        })
-        .await?;
-      Self::response_ok(&mut response).await?;
-      response
-        .json()
-        .await
-        .map_err(|_| ClientError::JsonPayload)?
-    };
+          repositories: vec![],
+          permissions: [
+            (PermissionType::Checks, Permission::Read),
+            (PermissionType::Contents, Permission::Write),
+            (PermissionType::Issues, Permission::Write),
+            (PermissionType::Metadata, Permission::Read),
+            (PermissionType::PullRequests, Permission::Write),
+            (PermissionType::Statuses, Permission::Write),
+          ]
+          .iter()
+          .copied()
+          .collect(),
+        },
+      )
+      .await?;
     Ok(Token {
       token,
       renew: expires_at - Duration::seconds(REPO_TOKEN_RENEW_AHEAD_SECS),
@@ -293,10 +396,62 @@ impl Client {
     }
   }
 
-  pub fn api(&self) -> uri::Builder {
-    uri::Builder::new()
-      .scheme("https")
-      .authority("api.github.com")
+  /// The repository `owner/name`, if the app is installed on it through the
+  /// same installation as `repo`.
+  pub async fn linked_repository(
+    &self,
+    repo: &Repository,
+    owner: &str,
+    name: &str,
+  ) -> Result<Option<Repository>, ClientError> {
+    let installation = match self.installation(owner, name).await? {
+      Some(installation) => installation,
+      None => return Ok(None),
+    };
+    if self.installation(&repo.owner, &repo.repo).await? != Some(installation) {
+      return Ok(None);
+    }
+    let TokenResponse { token, .. } = self
+      .request_installation_token(
+        installation,
+        &TokenRequest {
+          repository_ids: vec![],
+          repositories: vec![name.to_string()],
+          permissions: [(PermissionType::Metadata, Permission::Read)]
+            .iter()
+            .copied()
+            .collect(),
+        },
+      )
+      .await?;
+    let uri =
+      self.api_uri(format!("/repos/{}/{}", path_segment(owner), path_segment(name)).as_str())?;
+    let mut response = self
+      .api_request(Method::GET, uri)
+      .set_header(header::AUTHORIZATION, format!("Bearer {}", token))
+      .send()
+      .await?;
+    if response.status() == StatusCode::NOT_FOUND {
+      return Ok(None);
+    }
+    Self::response_ok(&mut response).await?;
+    let linked: Repository = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(Some(linked))
+  }
+
+  /// Installation token for `repo`, for authenticating git over HTTPS.
+  pub async fn installation_token(&self, repo: &Repository) -> Result<String, ClientError> {
+    Ok(self.repo_token(repo).await?.token)
+  }
+
+  /// URI of an API endpoint, given its path and query.
+  pub fn api_uri(&self, path_and_query: &str) -> Result<uri::Uri, ClientError> {
+    // a URI without a path is displayed with a trailing slash
+    let base = self.api_url.to_string();
+    Ok(format!("{}{}", base.trim_end_matches('/'), path_and_query).parse()?)
   }
 
   pub fn api_request(&self, method: Method, uri: uri::Uri) -> ClientRequest {
@@ -340,10 +495,7 @@ impl Client {
     message: &str,
   ) -> Result<(), ClientError> {
     info!("commenting: {} #{}: {}", repo, pr_number, message);
-    let uri = self
-      .api()
-      .path_and_query(format!("/repos/{}/issues/{}/comments", repo, pr_number).as_str())
-      .build()?;
+    let uri = self.api_uri(format!("/repos/{}/issues/{}/comments", repo, pr_number).as_str())?;
     let mut response = self
       .repo_request(repo, Method::POST, uri)
       .await?
@@ -355,15 +507,203 @@ impl Client {
     Ok(())
   }
 
+  /// Close an issue.  Also works for PRs.
+  pub async fn close_issue(&self, repo: &Repository, issue_number: i64) -> Result<(), ClientError> {
+    info!("closing issue: {} #{}", repo, issue_number);
+    let uri = self.api_uri(format!("/repos/{}/issues/{}", repo, issue_number).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::PATCH, uri)
+      .await?
+      .send_json(&json!({
+        "state": "closed",
+      }))
+      .await?;
+    Self::response_ok(&mut response).await?;
+    Ok(())
+  }
+
+  /// Number of the milestone titled `title`, or `None` if there is none.
+  pub async fn milestone_number(
+    &self,
+    repo: &Repository,
+    title: &str,
+  ) -> Result<Option<i64>, ClientError> {
+    #[derive(Deserialize)]
+    struct Milestone {
+      number: i64,
+      title: String,
+    }
+    let uri =
+      self.api_uri(format!("/repos/{}/milestones?state=all&per_page=100", repo).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let milestones: Vec<Milestone> = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(
+      milestones
+        .into_iter()
+        .find(|m| m.title == title)
+        .map(|m| m.number),
+    )
+  }
+
+  pub async fn set_milestone(
+    &self,
+    repo: &Repository,
+    issue_number: i64,
+    milestone: i64,
+  ) -> Result<(), ClientError> {
+    info!(
+      "setting milestone: {} #{}: {}",
+      repo, issue_number, milestone
+    );
+    let uri = self.api_uri(format!("/repos/{}/issues/{}", repo, issue_number).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::PATCH, uri)
+      .await?
+      .send_json(&json!({
+        "milestone": milestone,
+      }))
+      .await?;
+    Self::response_ok(&mut response).await?;
+    Ok(())
+  }
+
+  /// Open PR whose head is `branch` in the repository itself, if any.
+  pub async fn pr_with_head(
+    &self,
+    repo: &Repository,
+    branch: &str,
+  ) -> Result<Option<i64>, ClientError> {
+    #[derive(Deserialize)]
+    struct Pr {
+      number: i64,
+    }
+    let uri = self.api_uri(
+      format!(
+        "/repos/{}/pulls?state=open&head={}:{}",
+        repo,
+        repo.owner,
+        branch_path(branch)
+      )
+      .as_str(),
+    )?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let prs: Vec<Pr> = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(prs.first().map(|pr| pr.number))
+  }
+
+  /// Open a PR from `head` into `base`, and return its number.
+  pub async fn create_pr(
+    &self,
+    repo: &Repository,
+    head: &str,
+    base: &str,
+    title: &str,
+    body: &str,
+  ) -> Result<i64, ClientError> {
+    #[derive(Deserialize)]
+    struct Pr {
+      number: i64,
+    }
+    info!("opening PR: {} {} into {}", repo, head, base);
+    let uri = self.api_uri(format!("/repos/{}/pulls", repo).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::POST, uri)
+      .await?
+      .send_json(&json!({
+        "head": head,
+        "base": base,
+        "title": title,
+        "body": body,
+      }))
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let pr: Pr = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(pr.number)
+  }
+
+  /// PRs merged since `since`, with the time each was merged, among the 100
+  /// most recently updated closed PRs.
+  pub async fn merged_prs(
+    &self,
+    repo: &Repository,
+    since: DateTime<Utc>,
+  ) -> Result<Vec<(i64, DateTime<Utc>)>, ClientError> {
+    #[derive(Deserialize)]
+    struct Pr {
+      number: i64,
+      merged_at: Option<DateTime<Utc>>,
+    }
+    let uri = self.api_uri(
+      format!(
+        "/repos/{}/pulls?state=closed&sort=updated&direction=desc&per_page=100",
+        repo
+      )
+      .as_str(),
+    )?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let prs: Vec<Pr> = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(
+      prs
+        .into_iter()
+        .filter_map(|pr| Some((pr.number, pr.merged_at?)))
+        .filter(|(_, merged_at)| *merged_at >= since)
+        .collect(),
+    )
+  }
+
+  /// Change the branch a PR is to be merged into.
+  pub async fn set_pr_base(
+    &self,
+    repo: &Repository,
+    pr_number: i64,
+    base: &str,
+  ) -> Result<(), ClientError> {
+    info!("retargeting: {} #{} to {}", repo, pr_number, base);
+    let uri = self.api_uri(format!("/repos/{}/pulls/{}", repo, pr_number).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::PATCH, uri)
+      .await?
+      .send_json(&json!({
+        "base": base,
+      }))
+      .await?;
+    Self::response_ok(&mut response).await?;
+    Ok(())
+  }
+
   pub async fn pr_info(
     &self,
     repo: &Repository,
     pr_number: i64,
   ) -> Result<PullRequest, ClientError> {
-    let uri = self
-      .api()
-      .path_and_query(format!("/repos/{}/pulls/{}", repo, pr_number).as_str())
-      .build()?;
+    let uri = self.api_uri(format!("/repos/{}/pulls/{}", repo, pr_number).as_str())?;
     let mut response = self
       .repo_request(repo, Method::GET, uri)
       .await?
@@ -375,4 +715,733 @@ impl Client {
       .await
       .map_err(|_| ClientError::JsonPayload)
   }
+
+  pub async fn labels(
+    &self,
+    repo: &Repository,
+    issue_number: i64,
+  ) -> Result<Vec<String>, ClientError> {
+    let uri = self.api_uri(
+      format!(
+        "/repos/{}/issues/{}/labels?per_page=100",
+        repo, issue_number
+      )
+      .as_str(),
+    )?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let labels: Vec<Label> = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(labels.into_iter().map(|l| l.name).collect())
+  }
+
+  pub async fn add_labels(
+    &self,
+    repo: &Repository,
+    issue_number: i64,
+    labels: &[&str],
+  ) -> Result<(), ClientError> {
+    info!("labeling: {} #{}: {:?}", repo, issue_number, labels);
+    let uri = self.api_uri(format!("/repos/{}/issues/{}/labels", repo, issue_number).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::POST, uri)
+      .await?
+      .send_json(&json!({
+        "labels": labels,
+      }))
+      .await?;
+    Self::response_ok(&mut response).await?;
+    Ok(())
+  }
+
+  /// Remove a label from an issue or PR.  Succeeds if the label was not present.
+  pub async fn remove_label(
+    &self,
+    repo: &Repository,
+    issue_number: i64,
+    label: &str,
+  ) -> Result<(), ClientError> {
+    info!("unlabeling: {} #{}: {}", repo, issue_number, label);
+    let uri = self.api_uri(
+      format!(
+        "/repos/{}/issues/{}/labels/{}",
+        repo,
+        issue_number,
+        path_segment(label)
+      )
+      .as_str(),
+    )?;
+    let mut response = self
+      .repo_request(repo, Method::DELETE, uri)
+      .await?
+      .send()
+      .await?;
+    if response.status() == StatusCode::NOT_FOUND {
+      return Ok(());
+    }
+    Self::response_ok(&mut response).await?;
+    Ok(())
+  }
+
+  /// Fetch a file from the repository at `git_ref`, or the default branch
+  /// if `None`.  Returns `None` if the file does not exist.
+  pub async fn file_contents(
+    &self,
+    repo: &Repository,
+    path: &str,
+    git_ref: Option<&str>,
+  ) -> Result<Option<Vec<u8>>, ClientError> {
+    #[derive(Deserialize)]
+    struct Contents {
+      content: String,
+    }
+    let path_and_query = match git_ref {
+      Some(git_ref) => format!(
+        "/repos/{}/contents/{}?ref={}",
+        repo,
+        branch_path(path),
+        path_segment(git_ref)
+      ),
+      None => format!("/repos/{}/contents/{}", repo, branch_path(path)),
+    };
+    let uri = self.api_uri(path_and_query.as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    if response.status() == StatusCode::NOT_FOUND {
+      return Ok(None);
+    }
+    Self::response_ok(&mut response).await?;
+    let contents: Contents = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    let content: String = contents.content.split_whitespace().collect();
+    Ok(Some(base64::decode(content)?))
+  }
+
+  pub async fn reviews(
+    &self,
+    repo: &Repository,
+    pr_number: i64,
+  ) -> Result<Vec<Review>, ClientError> {
+    let uri =
+      self.api_uri(format!("/repos/{}/pulls/{}/reviews?per_page=100", repo, pr_number).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    response.json().await.map_err(|_| ClientError::JsonPayload)
+  }
+
+  /// Access level of `user` to the repository.
+  pub async fn access_level(
+    &self,
+    repo: &Repository,
+    user: &str,
+  ) -> Result<AccessLevel, ClientError> {
+    #[derive(Deserialize)]
+    struct Permission {
+      // one of admin, write, read or none
+      permission: AccessLevel,
+    }
+    let uri = self.api_uri(
+      format!(
+        "/repos/{}/collaborators/{}/permission",
+        repo,
+        path_segment(user)
+      )
+      .as_str(),
+    )?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    if response.status() == StatusCode::NOT_FOUND {
+      return Ok(AccessLevel::None);
+    }
+    Self::response_ok(&mut response).await?;
+    let permission: Permission = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(permission.permission)
+  }
+
+  pub async fn set_status(
+    &self,
+    repo: &Repository,
+    commit_hash: &str,
+    state: StatusState,
+    context: &str,
+    description: &str,
+  ) -> Result<(), ClientError> {
+    info!(
+      "setting status: {} {}: {} {:?}",
+      repo, commit_hash, context, state
+    );
+    let uri = self.api_uri(format!("/repos/{}/statuses/{}", repo, commit_hash).as_str())?;
+    // descriptions longer than 140 characters are rejected
+    let description: String = description.chars().take(140).collect();
+    let mut response = self
+      .repo_request(repo, Method::POST, uri)
+      .await?
+      .send_json(&json!({
+        "state": state,
+        "context": context,
+        "description": description,
+      }))
+      .await?;
+    Self::response_ok(&mut response).await?;
+    Ok(())
+  }
+
+  /// State of every status context and check run on a commit, by name.
+  /// Check runs which have not completed are pending, and those which
+  /// completed as neutral or skipped count as successful.
+  pub async fn checks(
+    &self,
+    repo: &Repository,
+    commit_hash: &str,
+  ) -> Result<Vec<(String, StatusState)>, ClientError> {
+    #[derive(Deserialize)]
+    struct Status {
+      context: String,
+      state: StatusState,
+    }
+    #[derive(Deserialize)]
+    struct CombinedStatus {
+      statuses: Vec<Status>,
+    }
+    #[derive(Deserialize)]
+    struct CheckRun {
+      name: String,
+      status: String,
+      conclusion: Option<String>,
+    }
+    #[derive(Deserialize)]
+    struct CheckRuns {
+      check_runs: Vec<CheckRun>,
+    }
+
+    let uri = self.api_uri(
+      format!(
+        "/repos/{}/commits/{}/status?per_page=100",
+        repo, commit_hash
+      )
+      .as_str(),
+    )?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let combined: CombinedStatus = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    let mut checks: Vec<(String, StatusState)> = combined
+      .statuses
+      .into_iter()
+      .map(|s| (s.context, s.state))
+      .collect();
+
+    let uri = self.api_uri(
+      format!(
+        "/repos/{}/commits/{}/check-runs?per_page=100",
+        repo, commit_hash
+      )
+      .as_str(),
+    )?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let runs: CheckRuns = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    // the most recent run of each check comes first
+    for run in runs.check_runs {
+      if checks.iter().any(|(name, _)| *name == run.name) {
+        continue;
+      }
+      let state = match (run.status.as_str(), run.conclusion.as_deref()) {
+        ("completed", Some("success" | "neutral" | "skipped")) => StatusState::Success,
+        ("completed", _) => StatusState::Failure,
+        _ => StatusState::Pending,
+      };
+      checks.push((run.name, state));
+    }
+    Ok(checks)
+  }
+
+  /// Name of the repository's default branch.
+  pub async fn default_branch(&self, repo: &Repository) -> Result<String, ClientError> {
+    #[derive(Deserialize)]
+    struct RepoInfo {
+      default_branch: String,
+    }
+    let uri = self.api_uri(format!("/repos/{}", repo).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let info: RepoInfo = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(info.default_branch)
+  }
+
+  /// Whether `branch` is protected.
+  pub async fn branch_protected(
+    &self,
+    repo: &Repository,
+    branch: &str,
+  ) -> Result<bool, ClientError> {
+    #[derive(Deserialize)]
+    struct Branch {
+      protected: bool,
+    }
+    let uri = self.api_uri(format!("/repos/{}/branches/{}", repo, branch_path(branch)).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let branch: Branch = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(branch.protected)
+  }
+
+  /// Commit at the head of `branch`, or `None` if it does not exist.
+  pub async fn branch_hash(
+    &self,
+    repo: &Repository,
+    branch: &str,
+  ) -> Result<Option<String>, ClientError> {
+    #[derive(Deserialize)]
+    struct Object {
+      sha: String,
+    }
+    #[derive(Deserialize)]
+    struct Ref {
+      object: Object,
+    }
+    let uri =
+      self.api_uri(format!("/repos/{}/git/ref/heads/{}", repo, branch_path(branch)).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    if response.status() == StatusCode::NOT_FOUND {
+      return Ok(None);
+    }
+    Self::response_ok(&mut response).await?;
+    let git_ref: Ref = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(Some(git_ref.object.sha))
+  }
+
+  pub async fn create_branch(
+    &self,
+    repo: &Repository,
+    branch: &str,
+    commit_hash: &str,
+  ) -> Result<(), ClientError> {
+    info!("creating branch: {} {} at {}", repo, branch, commit_hash);
+    let uri = self.api_uri(format!("/repos/{}/git/refs", repo).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::POST, uri)
+      .await?
+      .send_json(&json!({
+        "ref": format!("refs/heads/{}", branch),
+        "sha": commit_hash,
+      }))
+      .await?;
+    Self::response_ok(&mut response).await?;
+    Ok(())
+  }
+
+  /// Point `branch` at `commit_hash`, even if this is not a fast-forward.
+  /// The branch is created if it does not exist.
+  pub async fn force_update_branch(
+    &self,
+    repo: &Repository,
+    branch: &str,
+    commit_hash: &str,
+  ) -> Result<(), ClientError> {
+    if self.branch_hash(repo, branch).await?.is_none() {
+      return self.create_branch(repo, branch, commit_hash).await;
+    }
+    info!("resetting branch: {} {} to {}", repo, branch, commit_hash);
+    let uri =
+      self.api_uri(format!("/repos/{}/git/refs/heads/{}", repo, branch_path(branch)).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::PATCH, uri)
+      .await?
+      .send_json(&json!({
+        "sha": commit_hash,
+        "force": true,
+      }))
+      .await?;
+    Self::response_ok(&mut response).await?;
+    Ok(())
+  }
+
+  /// Fast-forward `branch` to `commit_hash`.  Returns `false` if this is
+  /// not a fast-forward, because the branch moved.
+  pub async fn fast_forward_branch(
+    &self,
+    repo: &Repository,
+    branch: &str,
+    commit_hash: &str,
+  ) -> Result<bool, ClientError> {
+    info!(
+      "fast-forwarding branch: {} {} to {}",
+      repo, branch, commit_hash
+    );
+    let uri =
+      self.api_uri(format!("/repos/{}/git/refs/heads/{}", repo, branch_path(branch)).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::PATCH, uri)
+      .await?
+      .send_json(&json!({
+        "sha": commit_hash,
+        "force": false,
+      }))
+      .await?;
+    if response.status() == StatusCode::UNPROCESSABLE_ENTITY {
+      return Ok(false);
+    }
+    Self::response_ok(&mut response).await?;
+    Ok(true)
+  }
+
+  /// Delete `branch`.  Succeeds if the branch did not exist.
+  pub async fn delete_branch(&self, repo: &Repository, branch: &str) -> Result<(), ClientError> {
+    info!("deleting branch: {} {}", repo, branch);
+    let uri =
+      self.api_uri(format!("/repos/{}/git/refs/heads/{}", repo, branch_path(branch)).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::DELETE, uri)
+      .await?
+      .send()
+      .await?;
+    if response.status() == StatusCode::NOT_FOUND
+      || response.status() == StatusCode::UNPROCESSABLE_ENTITY
+    {
+      return Ok(());
+    }
+    Self::response_ok(&mut response).await?;
+    Ok(())
+  }
+
+  /// Merge `head`, a branch or commit, into the branch `base`.
+  pub async fn merge(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+    commit_message: &str,
+  ) -> Result<MergeResult, ClientError> {
+    #[derive(Deserialize)]
+    struct Commit {
+      sha: String,
+    }
+    info!("merging: {} {} into {}", repo, head, base);
+    let uri = self.api_uri(format!("/repos/{}/merges", repo).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::POST, uri)
+      .await?
+      .send_json(&json!({
+        "base": base,
+        "head": head,
+        "commit_message": commit_message,
+      }))
+      .await?;
+    match response.status() {
+      StatusCode::NO_CONTENT => return Ok(MergeResult::NothingToMerge),
+      // the API does not say which paths conflict
+      StatusCode::CONFLICT => return Ok(MergeResult::Conflict(vec![])),
+      _ => (),
+    }
+    Self::response_ok(&mut response).await?;
+    let commit: Commit = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(MergeResult::Merged(commit.sha))
+  }
+
+  /// Tree of the commit `commit_hash`.
+  pub async fn commit_tree(
+    &self,
+    repo: &Repository,
+    commit_hash: &str,
+  ) -> Result<String, ClientError> {
+    #[derive(Deserialize)]
+    struct Tree {
+      sha: String,
+    }
+    #[derive(Deserialize)]
+    struct Commit {
+      tree: Tree,
+    }
+    let uri = self.api_uri(format!("/repos/{}/git/commits/{}", repo, commit_hash).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let commit: Commit = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(commit.tree.sha)
+  }
+
+  /// Create a commit, not on any branch, and return its hash.  The author
+  /// defaults to the app.
+  pub async fn create_commit(
+    &self,
+    repo: &Repository,
+    message: &str,
+    tree: &str,
+    parents: &[&str],
+    author: Option<&GitIdentity>,
+  ) -> Result<String, ClientError> {
+    #[derive(Deserialize)]
+    struct Commit {
+      sha: String,
+    }
+    info!("creating commit: {} {} {:?}", repo, tree, parents);
+    let uri = self.api_uri(format!("/repos/{}/git/commits", repo).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::POST, uri)
+      .await?
+      .send_json(&match author {
+        Some(author) => json!({
+          "message": message,
+          "tree": tree,
+          "parents": parents,
+          "author": author,
+        }),
+        None => json!({
+          "message": message,
+          "tree": tree,
+          "parents": parents,
+        }),
+      })
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let commit: Commit = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(commit.sha)
+  }
+
+  /// Commits of a PR, in order.  At most 250 commits are listed.
+  pub async fn pr_commits(
+    &self,
+    repo: &Repository,
+    pr_number: i64,
+  ) -> Result<CommitRange, ClientError> {
+    #[derive(Deserialize)]
+    struct Pull {
+      commits: usize,
+    }
+    let uri = self.api_uri(format!("/repos/{}/pulls/{}", repo, pr_number).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let Pull {
+      commits: total_commits,
+    } = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+
+    let mut commits = vec![];
+    for page in 1.. {
+      let uri = self.api_uri(
+        format!(
+          "/repos/{}/pulls/{}/commits?per_page=100&page={}",
+          repo, pr_number, page
+        )
+        .as_str(),
+      )?;
+      let mut response = self
+        .repo_request(repo, Method::GET, uri)
+        .await?
+        .send()
+        .await?;
+      Self::response_ok(&mut response).await?;
+      // every commit carries its message and several URLs, so a page of them
+      // is often larger than the default limit
+      let page: Vec<Commit> = response
+        .json()
+        .limit(COMMIT_LIST_LIMIT)
+        .await
+        .map_err(|_| ClientError::JsonPayload)?;
+      let full = page.len() == 100;
+      commits.extend(page);
+      if !full || commits.len() >= total_commits {
+        break;
+      }
+    }
+    Ok(CommitRange {
+      commits,
+      total_commits,
+    })
+  }
+
+  /// Authors of the commits in a PR, in order.
+  pub async fn pr_commit_authors(
+    &self,
+    repo: &Repository,
+    pr_number: i64,
+  ) -> Result<Vec<CommitAuthor>, ClientError> {
+    let uri =
+      self.api_uri(format!("/repos/{}/pulls/{}/commits?per_page=100", repo, pr_number).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    // every commit carries its message and several URLs, so a page of them is
+    // often larger than the default limit
+    response
+      .json()
+      .limit(COMMIT_LIST_LIMIT)
+      .await
+      .map_err(|_| ClientError::JsonPayload)
+  }
+
+  /// Identity to author commits as `login`: the user's public name and
+  /// email, falling back to the login and GitHub's no-reply address.
+  pub async fn git_identity(
+    &self,
+    repo: &Repository,
+    login: &str,
+  ) -> Result<GitIdentity, ClientError> {
+    #[derive(Deserialize)]
+    struct User {
+      id: i64,
+      login: String,
+      name: Option<String>,
+      email: Option<String>,
+    }
+    let uri = self.api_uri(format!("/users/{}", path_segment(login)).as_str())?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    let User {
+      id,
+      login,
+      name,
+      email,
+    } = response
+      .json()
+      .await
+      .map_err(|_| ClientError::JsonPayload)?;
+    Ok(GitIdentity {
+      email: email.unwrap_or_else(|| format!("{}+{}@users.noreply.github.com", id, login)),
+      name: name.unwrap_or(login),
+    })
+  }
+
+  async fn compare(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+  ) -> Result<Comparison, ClientError> {
+    // three dots: compare against the merge base, like `git log base..head`
+    let uri = self.api_uri(
+      format!(
+        "/repos/{}/compare/{}...{}",
+        repo,
+        path_segment(base),
+        path_segment(head)
+      )
+      .as_str(),
+    )?;
+    let mut response = self
+      .repo_request(repo, Method::GET, uri)
+      .await?
+      .send()
+      .await?;
+    Self::response_ok(&mut response).await?;
+    // comparisons include patches, so they are often larger than the default
+    // limit
+    response
+      .json()
+      .limit(COMPARISON_LIMIT)
+      .await
+      .map_err(|_| ClientError::JsonPayload)
+  }
+
+  /// Commits reachable from `head` but not from `base`, oldest first.  At
+  /// most 250 commits are returned.
+  pub async fn compare_commits(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+  ) -> Result<CommitRange, ClientError> {
+    let Comparison {
+      commits,
+      total_commits,
+      ..
+    } = self.compare(repo, base, head).await?;
+    Ok(CommitRange {
+      commits,
+      total_commits,
+    })
+  }
+
+  /// Paths changed by `head` since its merge base with `base`.  At most 300
+  /// paths are returned.
+  pub async fn changed_paths(
+    &self,
+    repo: &Repository,
+    base: &str,
+    head: &str,
+  ) -> Result<Vec<String>, ClientError> {
+    let comparison = self.compare(repo, base, head).await?;
+    Ok(comparison.files.into_iter().map(|f| f.filename).collect())
+  }
 }
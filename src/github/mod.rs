@@ -1,24 +1,109 @@
-use crate::control::command::Context;
-use client::{Client, ClientError};
-use types::Repository;
+use crate::clock::Clock;
+use crate::config::repo::ConfigCache;
+use crate::config::server::{GitBackend, GitConfig};
+use crate::control::backend::github::GithubBackend;
+use crate::control::backend::local::LocalBackend;
+use crate::control::backend::MergeBackend;
+use crate::control::command::{BackportOptions, Command, Context, MergeOptions, RevertOptions};
+use crate::control::{Controller, ControllerError};
+use client::{Client, ClientError, Credentials, TokenCache};
+use types::{GitIdentity, Repository};
 
+use std::sync::Arc;
+
+use actix_web::client::Client as AwcClient;
+use actix_web::http::Uri;
 use async_trait::async_trait;
+use log::error;
+use quaint::pooled::{PooledConnection, Quaint};
 use thiserror::Error;
+use tokio::sync::Mutex;
 
 pub mod client;
 pub mod types;
 pub mod webhook;
 
+/// State shared by everything acting on behalf of the app.
+#[derive(Clone)]
+pub struct Shared {
+  pub credentials: Credentials,
+  pub token_cache: Arc<Mutex<TokenCache>>,
+  pub api_url: Uri,
+  pub db: Quaint,
+  pub configs: Arc<Mutex<ConfigCache>>,
+  /// Secret used to verify webhook signatures, if any.
+  pub webhook_secret: Option<Arc<Vec<u8>>>,
+  pub clock: Arc<dyn Clock>,
+  pub git: GitConfig,
+}
+
+impl Shared {
+  pub fn client(&self) -> Client {
+    Client::new(
+      self.credentials.clone(),
+      self.token_cache.clone(),
+      AwcClient::new(),
+      self.api_url.clone(),
+    )
+  }
+
+  fn backend(&self) -> Box<dyn MergeBackend> {
+    match self.git.backend {
+      GitBackend::Api => Box::new(GithubBackend::new(self.client())),
+      GitBackend::Local => Box::new(LocalBackend::new(
+        self.git.mirror_dir.clone(),
+        self.git.remote_url.clone(),
+        Some(self.client()),
+        GitIdentity {
+          name: self.git.committer_name.clone(),
+          email: self.git.committer_email.clone(),
+        },
+      )),
+    }
+  }
+
+  pub(crate) async fn controller(
+    &self,
+  ) -> Result<Controller<PooledConnection>, quaint::error::Error> {
+    Ok(Controller::new(
+      self.client(),
+      self.backend(),
+      self.db.check_out().await?,
+      self.configs.clone(),
+      self.clock.clone(),
+    ))
+  }
+
+  /// Run the Poll timer forever, ticking every `interval`.
+  pub async fn poll(self, interval: std::time::Duration) {
+    let mut timer = actix_rt::time::interval(interval);
+    loop {
+      timer.tick().await;
+      let result = match self.controller().await {
+        Ok(controller) => controller.poll().await,
+        Err(e) => Err(e.into()),
+      };
+      if let Err(e) = result {
+        error!("poll: {}", e);
+      }
+    }
+  }
+}
+
 #[derive(Debug, Error)]
 pub enum CommandError {
   #[error("client operation")]
   Client(#[from] ClientError),
+  #[error("controller operation")]
+  Controller(#[from] ControllerError),
 }
 
 pub struct CommandContext {
   client: Client,
+  controller: Controller<PooledConnection>,
   repository: Repository,
   issue_number: i64,
+  user: String,
 }
 
 #[async_trait(?Send)]
@@ -26,7 +111,94 @@ impl Context for CommandContext {
   type Error = CommandError;
 
   async fn reply(&mut self, message: String) -> Result<(), Self::Error> {
-    self.client.comment_on_pr(&self.repository, self.issue_number, message.as_str()).await
+    self
+      .controller
+      .comment(&self.repository, self.issue_number, message.as_str())
+      .await
+      .map_err(Into::into)
+  }
+
+  async fn permitted(&mut self, command: &Command) -> Result<bool, Self::Error> {
+    let config = self.controller.config(&self.repository).await?;
+    let required = config.commands.required(command.to_string().as_str());
+    let level = self
+      .client
+      .access_level(&self.repository, &self.user)
+      .await?;
+    Ok(level >= required)
+  }
+
+  async fn merge(&mut self, options: MergeOptions) -> Result<(), Self::Error> {
+    // linking a PR merges it, so the commenter needs permission there too
+    for linked in &options.with {
+      let other = match self
+        .client
+        .linked_repository(&self.repository, &linked.owner, &linked.repo)
+        .await?
+      {
+        Some(other) => other,
+        None => continue,
+      };
+      let config = self.controller.config(&other).await?;
+      let required = config.commands.required("merge");
+      if self.client.access_level(&other, &self.user).await? < required {
+        return self
+          .reply(format!(
+            "Permission denied: you may not run `cherry merge` in {}.",
+            other
+          ))
+          .await;
+      }
+    }
+    self
+      .controller
+      .request(&self.repository, self.issue_number, options)
+      .await
+      .map_err(Into::into)
+  }
+
+  async fn status(&mut self) -> Result<(), Self::Error> {
+    let message = self
+      .controller
+      .status(&self.repository, self.issue_number)
+      .await?;
+    self.reply(message).await
+  }
+
+  async fn report(&mut self) -> Result<(), Self::Error> {
+    let message = self.controller.shadow_report(&self.repository).await?;
+    self.reply(message).await
+  }
+
+  async fn revert(&mut self, options: RevertOptions) -> Result<(), Self::Error> {
+    self
+      .controller
+      .revert(&self.repository, self.issue_number, options, &self.user)
+      .await
+      .map_err(Into::into)
+  }
+
+  async fn tree(&mut self, priority: Option<i64>) -> Result<(), Self::Error> {
+    let message = self
+      .controller
+      .set_tree(&self.repository, priority, &self.user)
+      .await?;
+    self.reply(message).await
+  }
+
+  async fn override_base(&mut self, target: Option<String>) -> Result<(), Self::Error> {
+    let message = self
+      .controller
+      .override_base(&self.repository, self.issue_number, target, &self.user)
+      .await?;
+    self.reply(message).await
+  }
+
+  async fn backport(&mut self, options: BackportOptions) -> Result<(), Self::Error> {
+    self
+      .controller
+      .backport(&self.repository, self.issue_number, options, &self.user)
+      .await
       .map_err(Into::into)
   }
 }
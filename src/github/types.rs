@@ -1,4 +1,4 @@
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -39,8 +39,13 @@ impl fmt::Display for Repository {
   }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Label {
+  pub name: String,
+}
+
 #[derive(Debug, Deserialize)]
-#[serde(rename_all="snake_case")]
+#[serde(rename_all = "snake_case")]
 pub enum PrState {
   Open,
   Closed,
@@ -52,6 +57,19 @@ pub struct PullRequest {
   pub merged: bool,
   pub draft: bool,
   pub commit_hash: String,
+  /// Branch the PR is to be merged into.
+  pub base_ref: String,
+  /// Branch of the PR's commits.
+  pub head_ref: String,
+  /// Repository of `head_ref`, or `None` if it was deleted.
+  pub head_repo_id: Option<i64>,
+  pub labels: Vec<String>,
+  pub title: String,
+  pub body: String,
+  /// Login of the PR's author.
+  pub author: String,
+  /// Web page of the PR.
+  pub url: String,
 }
 
 impl<'de> Deserialize<'de> for PullRequest {
@@ -59,9 +77,25 @@ impl<'de> Deserialize<'de> for PullRequest {
   where
     D: Deserializer<'de>,
   {
+    #[derive(Deserialize)]
+    struct HeadRepo {
+      id: i64,
+    }
     #[derive(Deserialize)]
     struct Head {
       sha: String,
+      #[serde(rename = "ref")]
+      git_ref: String,
+      repo: Option<HeadRepo>,
+    }
+    #[derive(Deserialize)]
+    struct Base {
+      #[serde(rename = "ref")]
+      git_ref: String,
+    }
+    #[derive(Deserialize)]
+    struct User {
+      login: String,
     }
     #[derive(Deserialize)]
     struct RPullRequest {
@@ -69,12 +103,24 @@ impl<'de> Deserialize<'de> for PullRequest {
       merged: bool,
       draft: bool,
       head: Head,
+      base: Base,
+      labels: Vec<Label>,
+      title: String,
+      body: Option<String>,
+      user: User,
+      html_url: String,
     }
     let RPullRequest {
       state,
       merged,
       draft,
       head,
+      base,
+      labels,
+      title,
+      body,
+      user,
+      html_url,
     } = RPullRequest::deserialize(deserializer)?;
 
     Ok(PullRequest {
@@ -82,6 +128,187 @@ impl<'de> Deserialize<'de> for PullRequest {
       merged,
       draft,
       commit_hash: head.sha,
+      base_ref: base.git_ref,
+      head_ref: head.git_ref,
+      head_repo_id: head.repo.map(|r| r.id),
+      labels: labels.into_iter().map(|l| l.name).collect(),
+      title,
+      // GitHub stores bodies with CRLF line endings
+      body: body.unwrap_or_default().replace("\r\n", "\n"),
+      author: user.login,
+      url: html_url,
+    })
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ReviewState {
+  Approved,
+  ChangesRequested,
+  Commented,
+  Dismissed,
+  Pending,
+}
+
+#[derive(Debug, Clone)]
+pub struct Review {
+  pub user: String,
+  pub state: ReviewState,
+  pub commit_hash: String,
+}
+
+impl<'de> Deserialize<'de> for Review {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    #[derive(Deserialize)]
+    struct User {
+      login: String,
+    }
+    #[derive(Deserialize)]
+    struct RReview {
+      user: User,
+      state: ReviewState,
+      commit_id: String,
+    }
+    let RReview {
+      user,
+      state,
+      commit_id,
+    } = RReview::deserialize(deserializer)?;
+
+    Ok(Review {
+      user: user.login,
+      state,
+      commit_hash: commit_id,
     })
   }
 }
+
+/// Name and email recorded as a commit's author.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GitIdentity {
+  pub name: String,
+  pub email: String,
+}
+
+impl fmt::Display for GitIdentity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} <{}>", self.name, self.email)
+  }
+}
+
+/// Author of a commit in a PR.
+#[derive(Debug, Clone)]
+pub struct CommitAuthor {
+  pub identity: GitIdentity,
+  /// Login of the GitHub user matching the identity, if any.
+  pub login: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for CommitAuthor {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    #[derive(Deserialize)]
+    struct User {
+      login: String,
+    }
+    #[derive(Deserialize)]
+    struct Commit {
+      author: GitIdentity,
+    }
+    #[derive(Deserialize)]
+    struct RCommit {
+      commit: Commit,
+      author: Option<User>,
+    }
+    let RCommit { commit, author } = RCommit::deserialize(deserializer)?;
+
+    Ok(CommitAuthor {
+      identity: commit.author,
+      login: author.map(|u| u.login),
+    })
+  }
+}
+
+/// A commit, as listed by the compare API.
+#[derive(Debug, Clone)]
+pub struct Commit {
+  pub sha: String,
+  pub parents: Vec<String>,
+  pub author: GitIdentity,
+  pub message: String,
+}
+
+impl<'de> Deserialize<'de> for Commit {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    #[derive(Deserialize)]
+    struct Parent {
+      sha: String,
+    }
+    #[derive(Deserialize)]
+    struct GitCommit {
+      author: GitIdentity,
+      message: String,
+    }
+    #[derive(Deserialize)]
+    struct RCommit {
+      sha: String,
+      parents: Vec<Parent>,
+      commit: GitCommit,
+    }
+    let RCommit {
+      sha,
+      parents,
+      commit,
+    } = RCommit::deserialize(deserializer)?;
+
+    Ok(Commit {
+      sha,
+      parents: parents.into_iter().map(|p| p.sha).collect(),
+      author: commit.author,
+      message: commit.message,
+    })
+  }
+}
+
+/// A user's level of access to a repository, in increasing order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLevel {
+  None,
+  Read,
+  Triage,
+  Write,
+  Maintain,
+  Admin,
+}
+
+impl fmt::Display for AccessLevel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::None => write!(f, "none"),
+      Self::Read => write!(f, "read"),
+      Self::Triage => write!(f, "triage"),
+      Self::Write => write!(f, "write"),
+      Self::Maintain => write!(f, "maintain"),
+      Self::Admin => write!(f, "admin"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusState {
+  Error,
+  Failure,
+  Pending,
+  Success,
+}
@@ -0,0 +1,87 @@
+use crate::github::types::Repository;
+use crate::github::Shared;
+
+use log::error;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Action {
+  Completed,
+  #[serde(other)]
+  Other,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct CheckSuite {
+  pub head_sha: String,
+  /// `None` if the commit is not at the head of a branch.
+  pub head_branch: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct CheckRun {
+  pub check_suite: CheckSuite,
+}
+
+/// A `check_run` event.
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct RunT {
+  pub action: Action,
+  pub check_run: CheckRun,
+  pub repository: Repository,
+}
+
+/// A `check_suite` event.
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct SuiteT {
+  pub action: Action,
+  pub check_suite: CheckSuite,
+  pub repository: Repository,
+}
+
+pub(super) async fn handle_run(data: RunT, shared: Shared) {
+  handle(
+    data.action,
+    data.check_run.check_suite,
+    data.repository,
+    shared,
+  )
+  .await
+}
+
+pub(super) async fn handle_suite(data: SuiteT, shared: Shared) {
+  handle(data.action, data.check_suite, data.repository, shared).await
+}
+
+async fn handle(action: Action, suite: CheckSuite, repository: Repository, shared: Shared) {
+  if action != Action::Completed {
+    return;
+  }
+  let controller = match shared.controller().await {
+    Ok(controller) => controller,
+    Err(e) => {
+      error!("connecting to database: {}", e);
+      return;
+    }
+  };
+  if let Err(e) = controller
+    .staging_checked(&repository, &suite.head_sha)
+    .await
+  {
+    error!(
+      "handling checks on {} in {}: {}",
+      suite.head_sha, repository, e
+    );
+  }
+  let branch = match suite.head_branch {
+    Some(branch) => branch,
+    None => return,
+  };
+  if let Err(e) = controller
+    .target_checked(&repository, &branch, &suite.head_sha)
+    .await
+  {
+    error!("handling checks on `{}` in {}: {}", branch, repository, e);
+  }
+}
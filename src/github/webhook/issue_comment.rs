@@ -1,14 +1,10 @@
 use crate::control::command::{Command, Context};
-use crate::github::client::{Client, Credentials, TokenCache};
 use crate::github::types::Repository;
 use crate::github::CommandContext;
+use crate::github::Shared;
 
-use std::sync::Arc;
-
-use actix_web::client::Client as AwcClient;
 use log::{error, info};
 use serde::Deserialize;
-use tokio::sync::Mutex;
 
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
@@ -54,17 +50,26 @@ pub(super) struct T {
   pub repository: Repository,
 }
 
-pub(super) async fn handle(data: T, credentials: Credentials, token_cache: Arc<Mutex<TokenCache>>) {
+pub(super) async fn handle(data: T, shared: Shared) {
   match data.action {
     Action::Created => {}
     _ => {
       return;
     }
   }
+  let controller = match shared.controller().await {
+    Ok(controller) => controller,
+    Err(e) => {
+      error!("connecting to database: {}", e);
+      return;
+    }
+  };
   let mut context = CommandContext {
-    client: Client::new(credentials, token_cache, AwcClient::new()),
+    client: shared.client(),
+    controller,
     repository: data.repository,
     issue_number: data.issue.number,
+    user: data.comment.user.login,
   };
   let commands = match Command::parse_comment(&data.comment.body[..]) {
     Ok(commands) => commands,
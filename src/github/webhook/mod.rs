@@ -1,15 +1,18 @@
-use crate::github::client::{Credentials, TokenCache};
-
-use std::sync::Arc;
+use crate::github::Shared;
 
 use actix_rt::spawn;
 use actix_web::{error, http::StatusCode, web, HttpRequest, HttpResponse};
 use log::trace;
+use ring::hmac;
 use serde_json::from_slice;
 use thiserror::Error;
-use tokio::sync::Mutex;
 
+mod check;
 mod issue_comment;
+mod pull_request;
+mod pull_request_review;
+mod push;
+mod status;
 
 #[derive(Debug, Error)]
 pub enum WebhookError {
@@ -19,6 +22,8 @@ pub enum WebhookError {
   InvalidEventType,
   #[error("failed to deserialize webhook payload")]
   PayloadDeserialization(#[from] serde_json::Error),
+  #[error("missing or invalid signature")]
+  InvalidSignature,
 }
 
 impl error::ResponseError for WebhookError {
@@ -27,13 +32,36 @@ impl error::ResponseError for WebhookError {
       Self::MissingEventType | Self::InvalidEventType | Self::PayloadDeserialization(_) => {
         StatusCode::BAD_REQUEST
       }
+      Self::InvalidSignature => StatusCode::UNAUTHORIZED,
     }
   }
 }
 
+/// Check the `X-Hub-Signature-256` header, of the form `sha256=<hex digest>`.
+fn verify_signature(secret: &[u8], signature: Option<&str>, body: &[u8]) -> bool {
+  let digest = match signature.and_then(|s| s.strip_prefix("sha256=")) {
+    Some(digest) if digest.len() % 2 == 0 => digest,
+    _ => return false,
+  };
+  let tag: Result<Vec<u8>, _> = (0..digest.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(&digest[i..i + 2], 16))
+    .collect();
+  match tag {
+    Ok(tag) => hmac::verify(&hmac::Key::new(hmac::HMAC_SHA256, secret), body, &tag).is_ok(),
+    Err(_) => false,
+  }
+}
+
 #[derive(Debug, PartialEq)]
 enum WebhookRequest {
   IssueComment(issue_comment::T),
+  PullRequest(pull_request::T),
+  PullRequestReview(pull_request_review::T),
+  Push(push::T),
+  Status(status::T),
+  CheckRun(check::RunT),
+  CheckSuite(check::SuiteT),
   Unknown,
 }
 
@@ -41,13 +69,25 @@ impl WebhookRequest {
   fn parse(event_type: &str, body: &[u8]) -> Result<Self, WebhookError> {
     match event_type {
       "issue_comment" => Ok(Self::IssueComment(from_slice(&body)?)),
+      "pull_request" => Ok(Self::PullRequest(from_slice(body)?)),
+      "pull_request_review" => Ok(Self::PullRequestReview(from_slice(body)?)),
+      "push" => Ok(Self::Push(from_slice(body)?)),
+      "status" => Ok(Self::Status(from_slice(body)?)),
+      "check_run" => Ok(Self::CheckRun(from_slice(body)?)),
+      "check_suite" => Ok(Self::CheckSuite(from_slice(body)?)),
       _ => Ok(Self::Unknown),
     }
   }
 
-  async fn handle(self, credentials: Credentials, token_cache: Arc<Mutex<TokenCache>>) {
+  async fn handle(self, shared: Shared) {
     match self {
-      Self::IssueComment(d) => issue_comment::handle(d, credentials, token_cache).await,
+      Self::IssueComment(d) => issue_comment::handle(d, shared).await,
+      Self::PullRequest(d) => pull_request::handle(d, shared).await,
+      Self::PullRequestReview(d) => pull_request_review::handle(d, shared).await,
+      Self::Push(d) => push::handle(d, shared).await,
+      Self::Status(d) => status::handle(d, shared).await,
+      Self::CheckRun(d) => check::handle_run(d, shared).await,
+      Self::CheckSuite(d) => check::handle_suite(d, shared).await,
       Self::Unknown => {}
     }
   }
@@ -56,10 +96,17 @@ impl WebhookRequest {
 pub async fn webhook(
   request: HttpRequest,
   body: web::Bytes,
-  credentials: web::Data<Credentials>,
-  token_cache: web::Data<Arc<Mutex<TokenCache>>>,
+  shared: web::Data<Shared>,
 ) -> Result<HttpResponse, WebhookError> {
   let headers = request.headers();
+  if let Some(secret) = &shared.webhook_secret {
+    let signature = headers
+      .get("X-Hub-Signature-256")
+      .and_then(|s| s.to_str().ok());
+    if !verify_signature(secret, signature, &body) {
+      return Err(WebhookError::InvalidSignature);
+    }
+  }
   let event_type = headers
     .get("X-GitHub-Event")
     .ok_or(WebhookError::MissingEventType)?
@@ -68,7 +115,7 @@ pub async fn webhook(
 
   trace!("received webhook: {:?}", event_type);
   let request = WebhookRequest::parse(event_type, &body)?;
-  spawn(request.handle(credentials.as_ref().clone(), token_cache.as_ref().clone()));
+  spawn(request.handle(shared.as_ref().clone()));
   Ok(HttpResponse::Accepted().finish())
 }
 
@@ -80,7 +127,7 @@ mod tests {
   fn test_webhook_parse() {
     use WebhookRequest::*;
     {
-      use crate::github::client::Repository;
+      use crate::github::types::Repository;
       use issue_comment::*;
       assert_eq!(
         IssueComment(T {
@@ -109,6 +156,117 @@ mod tests {
         .unwrap(),
       );
     }
+    {
+      use crate::github::types::Repository;
+      use pull_request::*;
+      assert_eq!(
+        PullRequest(T {
+          action: Action::Labeled,
+          number: 2,
+          label: Some(Label {
+            name: "do-not-merge".to_string(),
+          }),
+          pull_request: Pr {
+            base: Base {
+              git_ref: "master".to_string(),
+            },
+          },
+          changes: None,
+          repository: Repository {
+            id: 186853002,
+            owner: "Codertocat".to_string(),
+            repo: "Hello-World".to_string(),
+          }
+        }),
+        WebhookRequest::parse(
+          "pull_request",
+          include_bytes!("test_data/parse/01_pull_request_labeled.json")
+        )
+        .unwrap(),
+      );
+    }
+    {
+      use crate::github::types::Repository;
+      use push::*;
+      assert_eq!(
+        Push(T {
+          git_ref: "refs/heads/master".to_string(),
+          after: "6113728f27ae82c7b1a177c8d03f9e96e0adf246".to_string(),
+          commits: vec![Commit {
+            added: vec![],
+            modified: vec![".github/cherry.toml".to_string()],
+            removed: vec![],
+          }],
+          repository: Repository {
+            id: 186853002,
+            owner: "Codertocat".to_string(),
+            repo: "Hello-World".to_string(),
+          },
+          default_branch: "master".to_string(),
+        }),
+        WebhookRequest::parse("push", include_bytes!("test_data/parse/02_push.json")).unwrap(),
+      );
+    }
+    {
+      use crate::github::types::Repository;
+      use status::*;
+      assert_eq!(
+        Status(T {
+          sha: "6113728f27ae82c7b1a177c8d03f9e96e0adf246".to_string(),
+          branches: vec![Branch {
+            name: "master".to_string(),
+          }],
+          repository: Repository {
+            id: 186853002,
+            owner: "Codertocat".to_string(),
+            repo: "Hello-World".to_string(),
+          },
+        }),
+        WebhookRequest::parse("status", include_bytes!("test_data/parse/03_status.json")).unwrap(),
+      );
+    }
+    {
+      use crate::github::types::Repository;
+      use check::*;
+      assert_eq!(
+        WebhookRequest::CheckRun(RunT {
+          action: Action::Completed,
+          check_run: CheckRun {
+            check_suite: CheckSuite {
+              head_sha: "ec26c3e57ca3a959ca5aad62de7213c562f8c821".to_string(),
+              head_branch: Some("master".to_string()),
+            },
+          },
+          repository: Repository {
+            id: 186853002,
+            owner: "Codertocat".to_string(),
+            repo: "Hello-World".to_string(),
+          },
+        }),
+        WebhookRequest::parse(
+          "check_run",
+          include_bytes!("test_data/parse/04_check_run.json")
+        )
+        .unwrap(),
+      );
+    }
     assert_eq!(Unknown, WebhookRequest::parse("nyanyan", b"").unwrap(),);
   }
+
+  #[test]
+  fn test_verify_signature() {
+    // example from GitHub's documentation on validating webhook deliveries
+    let secret = b"It's a Secret to Everybody";
+    let body = b"Hello, World!";
+    let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+    assert!(verify_signature(secret, Some(signature), body));
+    assert!(!verify_signature(
+      secret,
+      Some(signature),
+      b"Goodbye, World!"
+    ));
+    assert!(!verify_signature(secret, Some("sha256=75"), body));
+    assert!(!verify_signature(secret, Some("sha1=757107ea"), body));
+    assert!(!verify_signature(secret, None, body));
+  }
 }
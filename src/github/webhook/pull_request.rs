@@ -0,0 +1,113 @@
+use crate::github::types::Repository;
+use crate::github::Shared;
+
+use log::error;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Action {
+  Labeled,
+  Unlabeled,
+  Edited,
+  #[serde(other)]
+  Other,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct Label {
+  pub name: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct Base {
+  #[serde(rename = "ref")]
+  pub git_ref: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct Pr {
+  pub base: Base,
+}
+
+/// Fields changed by an `edited` action.  Only whether the base changed
+/// matters.
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct Changes {
+  pub base: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct T {
+  pub action: Action,
+  pub number: i64,
+  pub label: Option<Label>,
+  pub pull_request: Pr,
+  #[serde(default)]
+  pub changes: Option<Changes>,
+  pub repository: Repository,
+}
+
+pub(super) async fn handle(data: T, shared: Shared) {
+  if data.action == Action::Edited {
+    let base_changed = data.changes.and_then(|c| c.base).is_some();
+    if base_changed {
+      retarget(
+        data.repository,
+        data.number,
+        data.pull_request.base.git_ref,
+        shared,
+      )
+      .await;
+    }
+    return;
+  }
+  let label = match (&data.action, data.label) {
+    (Action::Labeled, Some(label)) | (Action::Unlabeled, Some(label)) => label.name,
+    _ => {
+      return;
+    }
+  };
+  let controller = match shared.controller().await {
+    Ok(controller) => controller,
+    Err(e) => {
+      error!("connecting to database: {}", e);
+      return;
+    }
+  };
+  let result = match data.action {
+    Action::Labeled => {
+      controller
+        .labeled(&data.repository, data.number, &label)
+        .await
+    }
+    Action::Unlabeled => {
+      controller
+        .unlabeled(&data.repository, data.number, &label)
+        .await
+    }
+    Action::Edited | Action::Other => Ok(()),
+  };
+  if let Err(e) = result {
+    error!(
+      "handling label `{}` on {} #{}: {}",
+      label, data.repository, data.number, e
+    );
+  }
+}
+
+async fn retarget(repo: Repository, number: i64, base: String, shared: Shared) {
+  let controller = match shared.controller().await {
+    Ok(controller) => controller,
+    Err(e) => {
+      error!("connecting to database: {}", e);
+      return;
+    }
+  };
+  if let Err(e) = controller.retarget(&repo, number, &base).await {
+    error!(
+      "handling base change to `{}` on {} #{}: {}",
+      base, repo, number, e
+    );
+  }
+}
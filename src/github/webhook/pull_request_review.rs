@@ -0,0 +1,45 @@
+use crate::github::types::Repository;
+use crate::github::Shared;
+
+use log::error;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Action {
+  Submitted,
+  Edited,
+  Dismissed,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct PullRequest {
+  pub number: i64,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct T {
+  pub action: Action,
+  pub pull_request: PullRequest,
+  pub repository: Repository,
+}
+
+pub(super) async fn handle(data: T, shared: Shared) {
+  match data.action {
+    Action::Submitted | Action::Dismissed => {}
+    Action::Edited => {
+      return;
+    }
+  }
+  let controller = match shared.controller().await {
+    Ok(controller) => controller,
+    Err(e) => {
+      error!("connecting to database: {}", e);
+      return;
+    }
+  };
+  let number = data.pull_request.number;
+  if let Err(e) = controller.initiate(&data.repository, number).await {
+    error!("handling review on {} #{}: {}", data.repository, number, e);
+  }
+}
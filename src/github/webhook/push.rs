@@ -0,0 +1,92 @@
+use crate::config::repo::CONFIG_PATH;
+use crate::github::types::Repository;
+use crate::github::Shared;
+
+use log::error;
+use serde::{de, Deserialize, Deserializer};
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct Commit {
+  pub added: Vec<String>,
+  pub modified: Vec<String>,
+  pub removed: Vec<String>,
+}
+
+impl Commit {
+  fn touches(&self, path: &str) -> bool {
+    self
+      .added
+      .iter()
+      .chain(&self.modified)
+      .chain(&self.removed)
+      .any(|p| p == path)
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) struct T {
+  pub git_ref: String,
+  pub after: String,
+  pub commits: Vec<Commit>,
+  pub repository: Repository,
+  pub default_branch: String,
+}
+
+impl<'de> Deserialize<'de> for T {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    #[derive(Deserialize)]
+    struct DefaultBranch {
+      default_branch: String,
+    }
+    #[derive(Deserialize)]
+    struct RT {
+      #[serde(rename = "ref")]
+      git_ref: String,
+      after: String,
+      commits: Vec<Commit>,
+      repository: serde_json::Value,
+    }
+    let RT {
+      git_ref,
+      after,
+      commits,
+      repository,
+    } = RT::deserialize(deserializer)?;
+    let DefaultBranch { default_branch } =
+      DefaultBranch::deserialize(&repository).map_err(de::Error::custom)?;
+    let repository = Repository::deserialize(repository).map_err(de::Error::custom)?;
+
+    Ok(T {
+      git_ref,
+      after,
+      commits,
+      repository,
+      default_branch,
+    })
+  }
+}
+
+pub(super) async fn handle(data: T, shared: Shared) {
+  if data.git_ref != format!("refs/heads/{}", data.default_branch) {
+    return;
+  }
+  if !data.commits.iter().any(|c| c.touches(CONFIG_PATH)) {
+    return;
+  }
+  let controller = match shared.controller().await {
+    Ok(controller) => controller,
+    Err(e) => {
+      error!("connecting to database: {}", e);
+      return;
+    }
+  };
+  if let Err(e) = controller
+    .reload_config(&data.repository, &data.after)
+    .await
+  {
+    error!("reloading configuration of {}: {}", data.repository, e);
+  }
+}
@@ -0,0 +1,48 @@
+use crate::github::types::Repository;
+use crate::github::Shared;
+
+use log::error;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct Branch {
+  pub name: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(super) struct T {
+  pub sha: String,
+  /// Branches whose head is `sha`.
+  pub branches: Vec<Branch>,
+  pub repository: Repository,
+}
+
+pub(super) async fn handle(data: T, shared: Shared) {
+  let controller = match shared.controller().await {
+    Ok(controller) => controller,
+    Err(e) => {
+      error!("connecting to database: {}", e);
+      return;
+    }
+  };
+  if let Err(e) = controller
+    .staging_checked(&data.repository, &data.sha)
+    .await
+  {
+    error!(
+      "handling status on {} in {}: {}",
+      data.sha, data.repository, e
+    );
+  }
+  for branch in &data.branches {
+    if let Err(e) = controller
+      .target_checked(&data.repository, &branch.name, &data.sha)
+      .await
+    {
+      error!(
+        "handling status on `{}` in {}: {}",
+        branch.name, data.repository, e
+      );
+    }
+  }
+}
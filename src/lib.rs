@@ -1,4 +1,8 @@
+pub mod clock;
+pub mod config;
 mod control;
-pub mod github;
 #[cfg(migration)]
 pub mod db;
+pub mod github;
+pub mod schema;
+pub mod tls;
@@ -1,16 +1,24 @@
-use cherry::github::client::{Credentials, TokenCache};
+use cherry::clock::SystemClock;
+use cherry::config::repo::ConfigCache;
+use cherry::config::server::{ServerConfig, ServerConfigError};
+use cherry::github::client::TokenCache;
 use cherry::github::webhook::webhook;
+use cherry::github::Shared;
+use cherry::schema::{self, SchemaError};
+use cherry::tls::{self, CertResolver, TlsError};
 
-use std::env;
 use std::error::Error as _;
 use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
 use actix_web::{middleware::Logger, web, App, HttpServer};
-use clap::{crate_authors, crate_description, crate_name, crate_version, AppSettings, SubCommand};
-use jsonwebtoken::EncodingKey;
-use log::info;
+use clap::{
+  crate_authors, crate_description, crate_name, crate_version, AppSettings, Arg, SubCommand,
+};
+use log::{error, info};
 use thiserror::Error;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::Mutex;
 
 #[derive(Debug, Error)]
@@ -19,23 +27,21 @@ enum MainError {
   Bind(#[source] io::Error),
   #[error("running server")]
   Run(#[source] io::Error),
-  #[error("loading environment variable `{0}`")]
-  Env(String, #[source] env::VarError),
-  #[error("base64-decoding private key")]
-  Base64(#[from] base64::DecodeError),
-  #[error("parsing private key")]
-  PrivateKey(#[from] jsonwebtoken::errors::Error),
+  #[error("loading configuration")]
+  Config(#[from] ServerConfigError),
+  #[error("loading TLS certificate")]
+  Tls(#[from] TlsError),
+  #[error("installing SIGHUP handler")]
+  Signal(#[source] io::Error),
   #[error("database error")]
   DB(#[from] quaint::error::Error),
+  #[error("checking database schema")]
+  Schema(#[from] SchemaError),
   #[cfg(migration)]
   #[error("migrating database")]
   Migration(#[from] cherry::db::MigrationError),
 }
 
-fn var(v: &str) -> Result<String, MainError> {
-  env::var(v).map_err(|e| MainError::Env(v.to_string(), e))
-}
-
 #[actix_rt::main]
 async fn main() {
   std::process::exit(match main_().await {
@@ -54,13 +60,24 @@ async fn main() {
 
 async fn main_() -> Result<(), MainError> {
   drop(dotenv::dotenv());
-  env_logger::init();
 
   let app = clap::App::new(crate_name!())
     .version(crate_version!())
     .author(crate_authors!())
     .about(crate_description!())
+    .arg(
+      Arg::with_name("config")
+        .long("config")
+        .short("c")
+        .value_name("FILE")
+        .help("server configuration file; environment variables override its settings")
+        .takes_value(true),
+    )
     .subcommand(SubCommand::with_name("run").about("run the server"))
+    .subcommand(
+      SubCommand::with_name("check-config")
+        .about("validate the server configuration without starting the server"),
+    )
     .setting(AppSettings::SubcommandRequired);
 
   #[cfg(migration)]
@@ -68,25 +85,34 @@ async fn main_() -> Result<(), MainError> {
 
   let matches = app.get_matches();
 
+  let config = ServerConfig::load(matches.value_of_os("config").map(Path::new))?;
+  env_logger::Builder::new()
+    .parse_filters(&config.log.filter)
+    .init();
+
   if let Some(_) = matches.subcommand_matches("run") {
-    return run().await;
+    return run(config).await;
+  }
+  if matches.subcommand_matches("check-config").is_some() {
+    config.validate()?;
+    println!("configuration OK");
+    return Ok(());
   }
   #[cfg(migration)]
   {
     if let Some(_) = matches.subcommand_matches("migrate") {
-      return migrate().await;
+      return migrate(config).await;
     }
   }
   panic!("invalid subcommand");
 }
 
 #[cfg(migration)]
-async fn migrate() -> Result<(), MainError> {
+async fn migrate(config: ServerConfig) -> Result<(), MainError> {
   use barrel::SqlVariant;
   use quaint::connector::ConnectionInfo;
 
-  let db_addr = var("DATABASE_ADDRESS")?;
-  let db = quaint::single::Quaint::new(db_addr.as_str()).await?;
+  let db = quaint::single::Quaint::new(config.database_url()?.as_str()).await?;
   let db_type = match db.connection_info() {
     ConnectionInfo::Sqlite { .. } => SqlVariant::Sqlite,
   };
@@ -94,33 +120,64 @@ async fn migrate() -> Result<(), MainError> {
   Ok(())
 }
 
-async fn run() -> Result<(), MainError> {
-  let credentials = {
-    let private_key = var("GITHUB_APP_PRIVATE_KEY")?;
-    let private_key = base64::decode(private_key)?;
-    let private_key = EncodingKey::from_rsa_pem(&private_key[..])?;
-    let app_id = var("GITHUB_APP_ID")?;
-    Credentials {
-      app_id,
-      private_key,
-    }
+async fn run(config: ServerConfig) -> Result<(), MainError> {
+  config.validate()?;
+
+  let shared = Shared {
+    credentials: config.credentials()?,
+    token_cache: Arc::new(Mutex::new(TokenCache::new())),
+    api_url: config.api_url()?,
+    db: quaint::pooled::Quaint::new(config.database_url()?.as_str()).await?,
+    configs: Arc::new(Mutex::new(ConfigCache::new())),
+    webhook_secret: config
+      .github
+      .webhook_secret
+      .clone()
+      .map(|s| Arc::new(s.into_bytes())),
+    clock: Arc::new(SystemClock),
+    git: config.git.clone(),
   };
+  schema::check(&shared.db.check_out().await?).await?;
 
-  let token_cache = Arc::new(Mutex::new(TokenCache::new()));
-
-  let bind_address = env::var("BIND_ADDRESS").unwrap_or("127.0.0.1:8080".to_string());
+  // validated to be positive
+  let poll_interval = config.poll.interval.to_std().unwrap();
+  actix_rt::spawn(shared.clone().poll(poll_interval));
 
-  info!("listening on {}", bind_address);
-  HttpServer::new(move || {
+  let mut server = HttpServer::new(move || {
     App::new()
-      .data(credentials.clone())
-      .data(token_cache.clone())
+      .data(shared.clone())
       .wrap(Logger::default())
       .route("/webhook", web::post().to(webhook))
-  })
-  .bind(bind_address)
-  .map_err(MainError::Bind)?
-  .run()
-  .await
-  .map_err(MainError::Run)
+  });
+  if let Some(bind_address) = &config.http.bind_address {
+    info!("listening on {}", bind_address);
+    server = server.bind(bind_address).map_err(MainError::Bind)?;
+  }
+  if let Some(tls) = &config.tls {
+    let resolver = Arc::new(CertResolver::load(
+      tls.certificate_chain.clone(),
+      tls.private_key.clone(),
+    )?);
+    reload_on_hangup(resolver.clone())?;
+    info!("listening with TLS on {}", tls.bind_address);
+    server = server
+      .bind_rustls(&tls.bind_address, tls::server_config(resolver))
+      .map_err(MainError::Bind)?;
+  }
+  server.run().await.map_err(MainError::Run)
+}
+
+/// Reload the TLS certificate whenever the process receives SIGHUP, so that
+/// renewed certificates are picked up without dropping connections.
+fn reload_on_hangup(resolver: Arc<CertResolver>) -> Result<(), MainError> {
+  let mut hangups = signal(SignalKind::hangup()).map_err(MainError::Signal)?;
+  actix_rt::spawn(async move {
+    while hangups.recv().await.is_some() {
+      match resolver.reload() {
+        Ok(()) => info!("reloaded TLS certificate"),
+        Err(e) => error!("reloading TLS certificate: {}", e),
+      }
+    }
+  });
+  Ok(())
 }
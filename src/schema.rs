@@ -0,0 +1,74 @@
+use quaint::connector::Queryable;
+use thiserror::Error;
+
+/// Applied by hand with sqlite3, which only creates missing tables, so a
+/// database created by an older version lacks the columns added since.
+const SCHEMA: &str = include_str!("../schema.sql");
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+  #[error("database error")]
+  DB(#[from] quaint::error::Error),
+  #[error(
+    "the database has no column `{0}.{1}`, so it predates schema.sql and must be recreated with it"
+  )]
+  MissingColumn(String, String),
+}
+
+/// (table, column) of each column created by schema.sql.
+fn columns() -> Vec<(&'static str, &'static str)> {
+  let mut columns = vec![];
+  let mut table = None;
+  for line in SCHEMA.lines().map(str::trim) {
+    if line.starts_with("CREATE TABLE IF NOT EXISTS ") {
+      table = line.split_whitespace().nth(5);
+    } else if line.starts_with(')') {
+      table = None;
+    } else if let (Some(table), Some(column)) = (table, line.split_whitespace().next()) {
+      columns.push((table, column));
+    }
+  }
+  columns
+}
+
+/// Check that the database has every column of schema.sql, rather than fail
+/// on the first query which needs a missing one.
+pub async fn check(db: &impl Queryable) -> Result<(), SchemaError> {
+  let columns = columns();
+  let mut tables: Vec<&str> = columns.iter().map(|(table, _)| *table).collect();
+  tables.dedup();
+  for table in tables {
+    let names: Vec<String> = db
+      .query_raw(format!("PRAGMA table_info({})", table).as_str(), &[])
+      .await?
+      .into_iter()
+      .filter_map(|row| row["name"].to_string())
+      .collect();
+    for (_, column) in columns.iter().filter(|(t, _)| *t == table) {
+      if !names.iter().any(|name| name == column) {
+        return Err(SchemaError::MissingColumn(
+          table.to_string(),
+          column.to_string(),
+        ));
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_columns() {
+    let columns = columns();
+    assert_eq!(columns[0], ("pull_request", "repo_id"));
+    assert!(columns.contains(&("merge_attempt", "base_ref")));
+    assert!(columns.contains(&("batch_result", "base_ref")));
+    assert!(columns.contains(&("repo_config", "timestamp")));
+    assert!(!columns
+      .iter()
+      .any(|(_, column)| column.starts_with("CREATE")));
+  }
+}
@@ -0,0 +1,90 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{NoClientAuth, ResolvesServerCert, ServerConfig, SignatureScheme};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+  #[error("reading `{}`", .0.display())]
+  Read(PathBuf, #[source] io::Error),
+  #[error("no PEM certificates in `{}`", .0.display())]
+  NoCertificates(PathBuf),
+  #[error("no PKCS#8 or RSA private key in `{}`", .0.display())]
+  NoPrivateKey(PathBuf),
+  #[error("unsupported private key type in `{}`", .0.display())]
+  UnsupportedKey(PathBuf),
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+  Ok(BufReader::new(
+    File::open(path).map_err(|e| TlsError::Read(path.to_path_buf(), e))?,
+  ))
+}
+
+/// Load a PEM certificate chain and private key.
+pub fn load_certified_key(chain: &Path, key: &Path) -> Result<CertifiedKey, TlsError> {
+  let certs = pemfile::certs(&mut open(chain)?)
+    .ok()
+    .filter(|certs| !certs.is_empty())
+    .ok_or_else(|| TlsError::NoCertificates(chain.to_path_buf()))?;
+  let private_key = pemfile::pkcs8_private_keys(&mut open(key)?)
+    .ok()
+    .and_then(|keys| keys.into_iter().next())
+    .or_else(|| {
+      pemfile::rsa_private_keys(&mut open(key).ok()?)
+        .ok()
+        .and_then(|keys| keys.into_iter().next())
+    })
+    .ok_or_else(|| TlsError::NoPrivateKey(key.to_path_buf()))?;
+  let signing_key = sign::any_supported_type(&private_key)
+    .map_err(|_| TlsError::UnsupportedKey(key.to_path_buf()))?;
+  Ok(CertifiedKey::new(certs, Arc::new(signing_key)))
+}
+
+/// Serves a single certificate that can be swapped out while the server is
+/// running.
+pub struct CertResolver {
+  chain: PathBuf,
+  key: PathBuf,
+  current: RwLock<CertifiedKey>,
+}
+
+impl CertResolver {
+  pub fn load(chain: PathBuf, key: PathBuf) -> Result<Self, TlsError> {
+    let current = RwLock::new(load_certified_key(&chain, &key)?);
+    Ok(Self {
+      chain,
+      key,
+      current,
+    })
+  }
+
+  /// Re-read the certificate chain and key.  The previous certificate stays
+  /// in use if loading fails.
+  pub fn reload(&self) -> Result<(), TlsError> {
+    let certified_key = load_certified_key(&self.chain, &self.key)?;
+    *self.current.write().unwrap() = certified_key;
+    Ok(())
+  }
+}
+
+impl ResolvesServerCert for CertResolver {
+  fn resolve(
+    &self,
+    _server_name: Option<webpki::DNSNameRef>,
+    _sigschemes: &[SignatureScheme],
+  ) -> Option<CertifiedKey> {
+    Some(self.current.read().unwrap().clone())
+  }
+}
+
+pub fn server_config(resolver: Arc<CertResolver>) -> ServerConfig {
+  let mut config = ServerConfig::new(NoClientAuth::new());
+  config.cert_resolver = resolver;
+  config
+}